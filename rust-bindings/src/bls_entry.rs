@@ -0,0 +1,474 @@
+//! Reading and writing Boot Loader Specification entries without libostree.
+//!
+//! Unlike [`BootconfigParser`](crate::BootconfigParser), [`BlsEntry`] keeps the file exactly
+//! as written: comments, blank lines, key order and repeated keys are preserved, and only
+//! the lines that are changed are rewritten. This makes it suitable for inspecting or
+//! editing `loader/entries` of a mounted disk image.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+const KEY_TITLE: &str = "title";
+const KEY_VERSION: &str = "version";
+const KEY_LINUX: &str = "linux";
+const KEY_INITRD: &str = "initrd";
+const KEY_DEVICETREE: &str = "devicetree";
+const KEY_OPTIONS: &str = "options";
+
+/// The suffix of entry file names.
+const CONF_SUFFIX: &str = ".conf";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    /// A comment, blank line or line without a value, kept verbatim.
+    Other(String),
+    /// A `key value` line, with the whitespace around the value.
+    Pair {
+        key: String,
+        separator: String,
+        value: String,
+        trailing: String,
+    },
+}
+
+impl Line {
+    fn parse(line: &str) -> Self {
+        if line.starts_with('#') {
+            return Line::Other(line.to_string());
+        }
+        let Some(split) = line.find([' ', '\t']) else {
+            return Line::Other(line.to_string());
+        };
+        let (key, rest) = line.split_at(split);
+        let value = rest.trim_start_matches([' ', '\t']);
+        let separator = &rest[..rest.len() - value.len()];
+        let trimmed = value.trim_end();
+        if key.is_empty() || trimmed.is_empty() {
+            return Line::Other(line.to_string());
+        }
+        Line::Pair {
+            key: key.to_string(),
+            separator: separator.to_string(),
+            value: trimmed.to_string(),
+            trailing: value[trimmed.len()..].to_string(),
+        }
+    }
+
+    fn new(key: &str, value: &str) -> Self {
+        Line::Pair {
+            key: key.to_string(),
+            separator: " ".to_string(),
+            value: value.to_string(),
+            trailing: String::new(),
+        }
+    }
+
+    fn pair(&self) -> Option<(&str, &str)> {
+        match self {
+            Line::Pair { key, value, .. } => Some((key, value)),
+            Line::Other(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::Other(line) => f.write_str(line),
+            Line::Pair {
+                key,
+                separator,
+                value,
+                trailing,
+            } => write!(f, "{key}{separator}{value}{trailing}"),
+        }
+    }
+}
+
+/// A Boot Loader Specification entry, as found in `/boot/loader/entries/*.conf`.
+///
+/// Parsing never fails: lines that are not `key value` pairs are kept as they are.
+/// Formatting the entry with [`Display`](fmt::Display) reproduces the parsed text exactly,
+/// apart from the changed keys.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlsEntry {
+    lines: Vec<Line>,
+    trailing_newline: bool,
+}
+
+impl BlsEntry {
+    /// Create an empty entry.
+    pub fn new() -> Self {
+        Self {
+            lines: Vec::new(),
+            trailing_newline: true,
+        }
+    }
+
+    /// Parse the contents of an entry file.
+    pub fn parse(contents: &str) -> Self {
+        let (body, trailing_newline) = match contents.strip_suffix('\n') {
+            Some(body) => (body, true),
+            None => (contents, false),
+        };
+        let lines = if contents.is_empty() {
+            Vec::new()
+        } else {
+            body.split('\n').map(Line::parse).collect()
+        };
+        Self {
+            lines,
+            trailing_newline: trailing_newline || contents.is_empty(),
+        }
+    }
+
+    /// Read an entry file.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    /// Write the entry to `path`, replacing it atomically.
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, self.to_string())?;
+        std::fs::rename(&tmp, path)
+    }
+
+    /// Iterate over the `key value` pairs in file order, including repeated keys.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.iter().filter_map(Line::pair)
+    }
+
+    /// The value of `key`. If the key is repeated, the last value is returned, like the
+    /// bootloaders do.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+            .last()
+    }
+
+    /// All values of `key`, in file order.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.iter().filter(move |(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// Set `key` to `value`. The first line with the key is rewritten and any others are
+    /// removed; a new key is appended.
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.set_all(key, &[value])
+    }
+
+    /// Set `key` to all of `values`, one line each, at the position of the first existing
+    /// line with the key or at the end. An empty slice removes the key.
+    pub fn set_all(&mut self, key: &str, values: &[&str]) {
+        let position = self
+            .lines
+            .iter()
+            .position(|l| l.pair().is_some_and(|(k, _)| k == key));
+        let mut existing = Vec::new();
+        self.lines.retain(|l| match l {
+            Line::Pair { key: k, .. } if k == key => {
+                existing.push(l.clone());
+                false
+            }
+            _ => true,
+        });
+        let position = position.unwrap_or(self.lines.len());
+        let mut existing = existing.into_iter();
+        let new = values.iter().map(|v| match existing.next() {
+            // Keep the original whitespace of unchanged lines.
+            Some(Line::Pair {
+                key,
+                separator,
+                value,
+                trailing,
+            }) if value == *v => Line::Pair {
+                key,
+                separator,
+                value,
+                trailing,
+            },
+            _ => Line::new(key, v),
+        });
+        self.lines
+            .splice(position..position, new.collect::<Vec<_>>());
+    }
+
+    /// Remove all lines with `key`, returning whether there were any.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let len = self.lines.len();
+        self.lines
+            .retain(|l| !l.pair().is_some_and(|(k, _)| k == key));
+        self.lines.len() != len
+    }
+
+    /// The `title` shown in the boot menu.
+    pub fn title(&self) -> Option<&str> {
+        self.get(KEY_TITLE)
+    }
+
+    /// The `version` used to sort entries.
+    pub fn version(&self) -> Option<&str> {
+        self.get(KEY_VERSION)
+    }
+
+    /// The path of the kernel image.
+    pub fn linux(&self) -> Option<&str> {
+        self.get(KEY_LINUX)
+    }
+
+    /// The paths of the initramfs images, in order.
+    pub fn initrds(&self) -> Vec<&str> {
+        self.get_all(KEY_INITRD).collect()
+    }
+
+    /// The path of the device tree.
+    pub fn devicetree(&self) -> Option<&str> {
+        self.get(KEY_DEVICETREE)
+    }
+
+    /// The kernel command line.
+    pub fn options(&self) -> Option<&str> {
+        self.get(KEY_OPTIONS)
+    }
+}
+
+impl fmt::Display for BlsEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{line}")?;
+        }
+        if self.trailing_newline && !self.lines.is_empty() {
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// The file name of a Boot Loader Specification entry, e.g. `ostree-1-fedora+3-1.conf`.
+///
+/// With automatic boot assessment, the name carries a counter of the boot attempts left
+/// and, optionally, done.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlsEntryName {
+    /// The name without the counter and the `.conf` suffix.
+    pub stem: String,
+    /// The number of boot attempts left, if the entry is being assessed.
+    pub tries_left: Option<u32>,
+    /// The number of boot attempts done, if recorded.
+    pub tries_done: Option<u32>,
+}
+
+impl BlsEntryName {
+    /// Parse a file name, returning `None` if it does not end with `.conf`.
+    pub fn parse(file_name: &str) -> Option<Self> {
+        let name = file_name.strip_suffix(CONF_SUFFIX)?;
+        if name.is_empty() {
+            return None;
+        }
+        let plain = || BlsEntryName {
+            stem: name.to_string(),
+            tries_left: None,
+            tries_done: None,
+        };
+        let Some((stem, counter)) = name.rsplit_once('+') else {
+            return Some(plain());
+        };
+        let (left, done) = match counter.split_once('-') {
+            Some((left, done)) => (left, Some(done)),
+            None => (counter, None),
+        };
+        let number = |s: &str| {
+            (!s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+                .then(|| s.parse::<u32>().ok())
+                .flatten()
+        };
+        match (number(left), done.map(number)) {
+            (Some(left), None) if !stem.is_empty() => Some(BlsEntryName {
+                stem: stem.to_string(),
+                tries_left: Some(left),
+                tries_done: None,
+            }),
+            (Some(left), Some(Some(done))) if !stem.is_empty() => Some(BlsEntryName {
+                stem: stem.to_string(),
+                tries_left: Some(left),
+                tries_done: Some(done),
+            }),
+            _ => Some(plain()),
+        }
+    }
+
+    /// Whether boot attempts are counted for this entry.
+    pub fn is_counting(&self) -> bool {
+        self.tries_left.is_some()
+    }
+}
+
+impl fmt::Display for BlsEntryName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.stem)?;
+        if let Some(left) = self.tries_left {
+            write!(f, "+{left}")?;
+            if let Some(done) = self.tries_done {
+                write!(f, "-{done}")?;
+            }
+        }
+        f.write_str(CONF_SUFFIX)
+    }
+}
+
+/// Read all entries in a `loader/entries` directory, sorted by file name. Files not
+/// ending with `.conf` are ignored.
+pub fn read_bls_entries(dir: impl AsRef<Path>) -> io::Result<Vec<(BlsEntryName, BlsEntry)>> {
+    let mut entries = Vec::new();
+    for dirent in std::fs::read_dir(dir)? {
+        let dirent = dirent?;
+        let Some(name) = dirent.file_name().to_str().and_then(BlsEntryName::parse) else {
+            continue;
+        };
+        if dirent.file_type()?.is_file() {
+            entries.push((name, BlsEntry::read(dirent.path())?));
+        }
+    }
+    entries.sort_by_key(|(name, _)| name.to_string());
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const ENTRY: &str = "\
+# Generated by ostree
+title Fedora Linux 40 (ostree:0)
+version\t2
+options  root=UUID=abc rw quiet
+linux /ostree/fedora-abc/vmlinuz
+initrd /ostree/fedora-abc/initramfs.img
+initrd /ostree/fedora-abc/overlay.img
+
+aboot
+";
+
+    #[test]
+    fn should_parse_entry() {
+        let e = BlsEntry::parse(ENTRY);
+        assert_eq!(e.title(), Some("Fedora Linux 40 (ostree:0)"));
+        assert_eq!(e.version(), Some("2"));
+        assert_eq!(e.options(), Some("root=UUID=abc rw quiet"));
+        assert_eq!(
+            e.initrds(),
+            [
+                "/ostree/fedora-abc/initramfs.img",
+                "/ostree/fedora-abc/overlay.img"
+            ]
+        );
+        assert_eq!(e.get("aboot"), None);
+        assert_eq!(e.to_string(), ENTRY);
+    }
+
+    #[test]
+    fn should_only_rewrite_changed_lines() {
+        let mut e = BlsEntry::parse(ENTRY);
+        e.set("options", "root=UUID=abc ro");
+        e.set_all(
+            "initrd",
+            &[
+                "/ostree/fedora-abc/initramfs.img",
+                "/extra.img",
+                "/more.img",
+            ],
+        );
+        e.set("devicetree", "/dtb");
+        assert!(e.remove("version"));
+        let expected = ENTRY
+            .replace("version\t2\n", "")
+            .replace(
+                "options  root=UUID=abc rw quiet",
+                "options root=UUID=abc ro",
+            )
+            .replace(
+                "initrd /ostree/fedora-abc/overlay.img",
+                "initrd /extra.img\ninitrd /more.img",
+            )
+            + "devicetree /dtb\n";
+        assert_eq!(e.to_string(), expected);
+    }
+
+    #[test]
+    fn should_parse_counter_in_file_name() {
+        let name = BlsEntryName::parse("ostree-1-fedora+3-1.conf").unwrap();
+        assert_eq!(name.stem, "ostree-1-fedora");
+        assert_eq!((name.tries_left, name.tries_done), (Some(3), Some(1)));
+        let name = BlsEntryName::parse("ostree-1-fedora+0.conf").unwrap();
+        assert_eq!((name.tries_left, name.tries_done), (Some(0), None));
+        let name = BlsEntryName::parse("ostree-1-fedora+x.conf").unwrap();
+        assert_eq!(name.stem, "ostree-1-fedora+x");
+        assert!(!name.is_counting());
+        assert_eq!(BlsEntryName::parse("ostree-1-fedora"), None);
+    }
+
+    #[test]
+    fn should_read_entries_directory() {
+        let td = tempfile::tempdir().unwrap();
+        std::fs::write(td.path().join("b+2.conf"), "title b\n").unwrap();
+        std::fs::write(td.path().join("a.conf"), "title a\n").unwrap();
+        std::fs::write(td.path().join("README"), "").unwrap();
+        let entries = read_bls_entries(td.path()).unwrap();
+        let titles = entries
+            .iter()
+            .map(|(_, e)| e.title().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["a", "b"]);
+        assert_eq!(entries[1].0.tries_left, Some(2));
+
+        let mut e = entries[0].1.clone();
+        e.set("title", "c");
+        e.write(td.path().join("a.conf")).unwrap();
+        assert_eq!(BlsEntry::read(td.path().join("a.conf")).unwrap(), e);
+    }
+
+    proptest! {
+        #[test]
+        fn should_round_trip_any_text(text in "([#a-z \t]{0,12}\n?){0,8}") {
+            prop_assert_eq!(BlsEntry::parse(&text).to_string(), text);
+        }
+
+        #[test]
+        fn should_round_trip_after_set(
+            text in "([a-z]{1,6}[ \t]{1,2}[a-z=/]{1,8}\n){0,6}",
+            key in "[a-z]{1,6}",
+            values in prop::collection::vec("[a-z=/][a-z=/ ]{0,6}[a-z=/]", 0..3),
+        ) {
+            let mut e = BlsEntry::parse(&text);
+            let values = values.iter().map(String::as_str).collect::<Vec<_>>();
+            e.set_all(&key, &values);
+            prop_assert_eq!(e.get_all(&key).collect::<Vec<_>>(), values.clone());
+            let reparsed = BlsEntry::parse(&e.to_string());
+            prop_assert_eq!(reparsed.to_string(), e.to_string());
+            prop_assert_eq!(reparsed.get_all(&key).collect::<Vec<_>>(), values);
+        }
+
+        #[test]
+        fn should_round_trip_file_names(
+            stem in "[a-z0-9.-]{1,12}",
+            tries_left in prop::option::of(0u32..10),
+            tries_done in prop::option::of(0u32..10),
+        ) {
+            let name = BlsEntryName {
+                stem,
+                tries_left,
+                tries_done: tries_left.and(tries_done),
+            };
+            prop_assert_eq!(BlsEntryName::parse(&name.to_string()), Some(name));
+        }
+    }
+}
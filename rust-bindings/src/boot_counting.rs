@@ -0,0 +1,276 @@
+//! Automatic boot assessment of deployments.
+//!
+//! With boot counting enabled, libostree writes the boot entries of new deployments with a
+//! `+N` suffix, e.g. `ostree-1+3.conf`. The bootloader decrements the counter on each
+//! attempt; once the system has booted successfully the counter is dropped with
+//! [`Sysroot::mark_boot_good`]. An entry whose counter reached zero is reported by
+//! [`Sysroot::failed_deployments`].
+
+use crate::sysroot::SysrootLockGuard;
+use crate::{
+    BlsEntry, BlsEntryName, Deployment, Sysroot, SysrootDeployTreeOpts,
+    SysrootSimpleWriteDeploymentFlags,
+};
+use gio::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// The repository option libostree reads the number of tries from.
+const CONFIG_GROUP: &str = "sysroot";
+const CONFIG_KEY: &str = "boot-counting-tries";
+const ENTRIES_DIR: &str = "boot/loader/entries";
+
+/// The largest number of tries accepted by libostree.
+pub const MAX_BOOT_COUNTING_TRIES: u32 = 5;
+
+/// A boot entry of the current bootloader configuration.
+struct BootEntryFile {
+    path: PathBuf,
+    name: BlsEntryName,
+    /// The stateroot, checksum and serial of the deployment.
+    target: Option<(String, String, i32)>,
+}
+
+impl BootEntryFile {
+    fn is_for(&self, deployment: &Deployment) -> bool {
+        self.target.as_ref().is_some_and(|(osname, csum, serial)| {
+            *osname == deployment.osname()
+                && *csum == deployment.csum()
+                && *serial == deployment.deployserial()
+        })
+    }
+}
+
+impl Sysroot {
+    /// The number of tries the boot entries of new deployments are written with, or
+    /// `None` if boot counting is disabled. Fails if the configured value is invalid.
+    pub fn boot_counting_tries(&self) -> Result<Option<u32>, glib::Error> {
+        let Some(value) = self.boot_counting_config()? else {
+            return Ok(None);
+        };
+        let tries = value.trim().parse::<u32>().map_err(|_| {
+            glib::Error::new(
+                glib::KeyFileError::InvalidValue,
+                &format!("Invalid {CONFIG_GROUP}.{CONFIG_KEY} value {value:?}"),
+            )
+        })?;
+        Ok((tries > 0).then_some(tries))
+    }
+
+    /// Enable boot counting with `tries` attempts for the boot entries of new deployments,
+    /// or disable it.
+    ///
+    /// This is a sysroot-wide setting stored in the repository configuration: it applies
+    /// to every boot entry libostree writes afterwards, including those of deployments
+    /// written by other tools, until it is changed again. To count the boot attempts of a
+    /// single deployment, use [`Sysroot::deploy_tree_with_boot_counting`].
+    pub fn set_boot_counting_tries(&self, tries: Option<u32>) -> Result<(), glib::Error> {
+        if let Some(n) = tries {
+            check_tries(n)?;
+        }
+        self.write_boot_counting_config(tries.map(|n| n.to_string()).as_deref())
+    }
+
+    /// Deploy `revision` like [`Sysroot::deploy_tree_with_options`] and write it like
+    /// [`Sysroot::simple_write_deployment`] with `flags`, so that its boot entry counts
+    /// [`SysrootDeployTreeOpts::boot_counting_tries`] attempts if set.
+    ///
+    /// Unlike [`Sysroot::set_boot_counting_tries`], the setting only applies to this
+    /// deployment: the previous configuration is restored afterwards, even on failure.
+    /// The sysroot lock is taken for the duration of the call, so it must not already be
+    /// held by the caller.
+    #[allow(clippy::too_many_arguments)]
+    pub fn deploy_tree_with_boot_counting<P: IsA<gio::Cancellable>>(
+        &self,
+        osname: Option<&str>,
+        revision: &str,
+        origin: Option<&glib::KeyFile>,
+        merge_deployment: Option<&Deployment>,
+        opts: &SysrootDeployTreeOpts,
+        flags: SysrootSimpleWriteDeploymentFlags,
+        cancellable: Option<&P>,
+    ) -> Result<Deployment, glib::Error> {
+        if let Some(n) = opts.boot_counting_tries {
+            check_tries(n)?;
+        }
+        self.lock()?;
+        let _guard = SysrootLockGuard(self);
+        let previous = self.boot_counting_config()?;
+        if let Some(n) = opts.boot_counting_tries {
+            self.write_boot_counting_config(Some(&n.to_string()))?;
+        }
+        let deployed = self
+            .deploy_tree_with_options(
+                osname,
+                revision,
+                origin,
+                merge_deployment,
+                Some(opts),
+                cancellable,
+            )
+            .and_then(|deployment| {
+                self.simple_write_deployment(
+                    osname,
+                    &deployment,
+                    merge_deployment,
+                    flags,
+                    cancellable,
+                )?;
+                Ok(deployment)
+            });
+        if opts.boot_counting_tries.is_some() {
+            let restored = self.write_boot_counting_config(previous.as_deref());
+            let deployment = deployed?;
+            restored?;
+            return Ok(deployment);
+        }
+        deployed
+    }
+
+    /// Drop the boot counter from the boot entry of `deployment`, so that it is no longer
+    /// assessed, and reload the sysroot. Returns whether the entry had a counter.
+    pub fn mark_boot_good(&self, deployment: &Deployment) -> Result<bool, glib::Error> {
+        let mut marked = false;
+        for entry in self.boot_entry_files()? {
+            if !entry.name.is_counting() || !entry.is_for(deployment) {
+                continue;
+            }
+            let good = BlsEntryName {
+                tries_left: None,
+                tries_done: None,
+                ..entry.name
+            };
+            let dir = entry.path.parent().unwrap();
+            std::fs::rename(&entry.path, dir.join(good.to_string())).map_err(io_error)?;
+            std::fs::File::open(dir)
+                .and_then(|d| d.sync_all())
+                .map_err(io_error)?;
+            marked = true;
+        }
+        if marked {
+            self.unload();
+            self.load(gio::Cancellable::NONE)?;
+        }
+        Ok(marked)
+    }
+
+    /// The deployments whose boot entry has no tries left, i.e. which failed to boot
+    /// successfully.
+    pub fn failed_deployments(&self) -> Result<Vec<Deployment>, glib::Error> {
+        let failed = self
+            .boot_entry_files()?
+            .into_iter()
+            .filter(|e| e.name.tries_left == Some(0))
+            .collect::<Vec<_>>();
+        Ok(self
+            .deployments()
+            .into_iter()
+            .filter(|d| failed.iter().any(|e| e.is_for(d)))
+            .collect())
+    }
+
+    /// The raw configured number of tries, if any.
+    fn boot_counting_config(&self) -> Result<Option<String>, glib::Error> {
+        match self.repo().config().string(CONFIG_GROUP, CONFIG_KEY) {
+            Ok(v) => Ok(Some(v.to_string())),
+            Err(e)
+                if e.matches(glib::KeyFileError::KeyNotFound)
+                    || e.matches(glib::KeyFileError::GroupNotFound) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Set the raw configured number of tries, or remove it.
+    fn write_boot_counting_config(&self, value: Option<&str>) -> Result<(), glib::Error> {
+        let repo = self.repo();
+        let config = repo.copy_config();
+        match value {
+            Some(v) => config.set_string(CONFIG_GROUP, CONFIG_KEY, v),
+            None => {
+                if config.has_key(CONFIG_GROUP, CONFIG_KEY).unwrap_or(false) {
+                    config.remove_key(CONFIG_GROUP, CONFIG_KEY)?;
+                }
+            }
+        }
+        repo.write_config_and_reload(&config)
+    }
+
+    fn boot_entry_files(&self) -> Result<Vec<BootEntryFile>, glib::Error> {
+        let root = self.path().path().ok_or_else(|| {
+            glib::Error::new(
+                gio::IOErrorEnum::NotSupported,
+                "Sysroot is not a local path",
+            )
+        })?;
+        let entries = match crate::read_bls_entries(root.join(ENTRIES_DIR)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(io_error(e)),
+        };
+        Ok(entries
+            .into_iter()
+            .map(|(name, entry)| BootEntryFile {
+                path: root.join(ENTRIES_DIR).join(name.to_string()),
+                target: entry_target(&root, &entry),
+                name,
+            })
+            .collect())
+    }
+}
+
+/// The stateroot, checksum and serial of the deployment booted by `entry`, found by
+/// resolving the `/ostree/boot.N/$stateroot/$bootcsum/$serial` link of its `ostree=`
+/// argument like libostree does.
+fn entry_target(root: &Path, entry: &BlsEntry) -> Option<(String, String, i32)> {
+    let path = entry
+        .options()?
+        .split_whitespace()
+        .find_map(|arg| arg.strip_prefix("ostree="))?;
+    // The link is relative, e.g. `../../../deploy/$stateroot/deploy/$checksum.$serial`.
+    let target = std::fs::read_link(root.join(path.trim_start_matches('/'))).ok()?;
+    let (csum, serial) = target.file_name()?.to_str()?.rsplit_once('.')?;
+    let osname = target.parent()?.parent()?.file_name()?.to_str()?;
+    Some((osname.to_string(), csum.to_string(), serial.parse().ok()?))
+}
+
+fn check_tries(tries: u32) -> Result<(), glib::Error> {
+    if tries == 0 || tries > MAX_BOOT_COUNTING_TRIES {
+        return Err(glib::Error::new(
+            gio::IOErrorEnum::InvalidArgument,
+            &format!("Boot counting tries must be between 1 and {MAX_BOOT_COUNTING_TRIES}"),
+        ));
+    }
+    Ok(())
+}
+
+fn io_error(e: std::io::Error) -> glib::Error {
+    glib::Error::new(gio::IOErrorEnum::Failed, &e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_resolve_entry_target() {
+        let td = tempfile::tempdir().unwrap();
+        let root = td.path();
+        std::fs::create_dir_all(root.join("ostree/boot.0.1/os/b00t")).unwrap();
+        std::os::unix::fs::symlink("boot.0.1", root.join("ostree/boot.0")).unwrap();
+        std::os::unix::fs::symlink(
+            "../../../deploy/os/deploy/c0ffee.1",
+            root.join("ostree/boot.0.1/os/b00t/1"),
+        )
+        .unwrap();
+        let entry =
+            BlsEntry::parse("title os\noptions root=/dev/vda ostree=/ostree/boot.0/os/b00t/1 rw\n");
+        assert_eq!(
+            entry_target(root, &entry),
+            Some(("os".to_string(), "c0ffee".to_string(), 1))
+        );
+        let entry = BlsEntry::parse("title os\noptions root=/dev/vda\n");
+        assert_eq!(entry_target(root, &entry), None);
+    }
+}
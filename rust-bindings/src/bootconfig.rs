@@ -0,0 +1,161 @@
+//! Typed access to the keys of a Boot Loader Specification entry.
+
+use crate::BootconfigParser;
+use glib::GString;
+
+const KEY_TITLE: &str = "title";
+const KEY_VERSION: &str = "version";
+const KEY_LINUX: &str = "linux";
+#[cfg(any(feature = "v2020_7", feature = "dox"))]
+const KEY_INITRD: &str = "initrd";
+const KEY_DEVICETREE: &str = "devicetree";
+const KEY_OPTIONS: &str = "options";
+const KEY_ABOOT: &str = "aboot";
+const KEY_ABOOTCFG: &str = "abootcfg";
+
+impl BootconfigParser {
+    /// The `title` shown in the boot menu.
+    pub fn title(&self) -> Option<GString> {
+        self.get(KEY_TITLE)
+    }
+
+    /// Set the `title` shown in the boot menu.
+    pub fn set_title(&self, title: &str) {
+        self.set(KEY_TITLE, title)
+    }
+
+    /// The `version` used to sort entries.
+    pub fn version(&self) -> Option<GString> {
+        self.get(KEY_VERSION)
+    }
+
+    /// Set the `version` used to sort entries.
+    pub fn set_version(&self, version: &str) {
+        self.set(KEY_VERSION, version)
+    }
+
+    /// The path of the kernel image, from the `linux` key.
+    pub fn linux(&self) -> Option<GString> {
+        self.get(KEY_LINUX)
+    }
+
+    /// Set the path of the kernel image.
+    pub fn set_linux(&self, path: &str) {
+        self.set(KEY_LINUX, path)
+    }
+
+    /// The paths of all initramfs images, in order, from the `initrd` keys.
+    #[cfg(any(feature = "v2020_7", feature = "dox"))]
+    #[cfg_attr(feature = "dox", doc(cfg(feature = "v2020_7")))]
+    pub fn initrds(&self) -> Vec<GString> {
+        self.get(KEY_INITRD)
+            .into_iter()
+            .chain(self.overlay_initrds())
+            .collect()
+    }
+
+    /// Set the paths of the initramfs images, written as one `initrd` key each.
+    ///
+    /// The parser cannot remove keys, so an empty list is rejected with
+    /// [`gio::IOErrorEnum::InvalidArgument`] and nothing is changed.
+    #[cfg(any(feature = "v2020_7", feature = "dox"))]
+    #[cfg_attr(feature = "dox", doc(cfg(feature = "v2020_7")))]
+    pub fn set_initrds(&self, paths: &[&str]) -> Result<(), glib::Error> {
+        let Some((first, overlays)) = paths.split_first() else {
+            return Err(glib::Error::new(
+                gio::IOErrorEnum::InvalidArgument,
+                "At least one initrd is required",
+            ));
+        };
+        self.set(KEY_INITRD, first);
+        self.set_overlay_initrds(overlays);
+        Ok(())
+    }
+
+    /// The path of the device tree, from the `devicetree` key.
+    pub fn devicetree(&self) -> Option<GString> {
+        self.get(KEY_DEVICETREE)
+    }
+
+    /// Set the path of the device tree.
+    pub fn set_devicetree(&self, path: &str) {
+        self.set(KEY_DEVICETREE, path)
+    }
+
+    /// The kernel command line, from the `options` key.
+    pub fn options(&self) -> Option<GString> {
+        self.get(KEY_OPTIONS)
+    }
+
+    /// Set the kernel command line.
+    pub fn set_options(&self, options: &str) {
+        self.set(KEY_OPTIONS, options)
+    }
+
+    /// The kernel command line as [`KernelArgs`](crate::KernelArgs); empty if there is no
+    /// `options` key.
+    #[cfg(any(feature = "v2019_3", feature = "dox"))]
+    #[cfg_attr(feature = "dox", doc(cfg(feature = "v2019_3")))]
+    pub fn kernel_args(&self) -> crate::KernelArgs {
+        crate::KernelArgs::from_string(self.options().as_deref().unwrap_or_default())
+    }
+
+    /// Set the kernel command line from [`KernelArgs`](crate::KernelArgs).
+    #[cfg(any(feature = "v2019_3", feature = "dox"))]
+    #[cfg_attr(feature = "dox", doc(cfg(feature = "v2019_3")))]
+    pub fn set_kernel_args(&self, kargs: &crate::KernelArgs) {
+        self.set_options(&kargs.to_string())
+    }
+
+    /// The path of the Android boot image, from the `aboot` key.
+    pub fn aboot(&self) -> Option<GString> {
+        self.get(KEY_ABOOT)
+    }
+
+    /// Set the path of the Android boot image.
+    pub fn set_aboot(&self, path: &str) {
+        self.set(KEY_ABOOT, path)
+    }
+
+    /// The path of the Android boot image configuration, from the `abootcfg` key.
+    pub fn abootcfg(&self) -> Option<GString> {
+        self.get(KEY_ABOOTCFG)
+    }
+
+    /// Set the path of the Android boot image configuration.
+    pub fn set_abootcfg(&self, path: &str) {
+        self.set(KEY_ABOOTCFG, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_access_typed_keys() {
+        let b = BootconfigParser::new();
+        assert_eq!(b.title(), None);
+        b.set_title("Fedora 40");
+        b.set_linux("/ostree/os-abc/vmlinuz");
+        b.set_options("root=UUID=x rw");
+        assert_eq!(b.title().unwrap(), "Fedora 40");
+        assert_eq!(b.get("linux").unwrap(), "/ostree/os-abc/vmlinuz");
+        assert_eq!(b.options().unwrap(), "root=UUID=x rw");
+    }
+
+    #[cfg(feature = "v2020_7")]
+    #[test]
+    fn should_keep_all_initrds() {
+        let b = BootconfigParser::new();
+        assert!(b.initrds().is_empty());
+        b.set_initrds(&["/initramfs.img", "/overlay1.img", "/overlay2.img"])
+            .unwrap();
+        assert_eq!(
+            b.initrds(),
+            ["/initramfs.img", "/overlay1.img", "/overlay2.img"]
+        );
+        assert!(b.set_initrds(&[]).is_err());
+        assert_eq!(b.initrds().len(), 3);
+    }
+}
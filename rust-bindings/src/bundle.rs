@@ -0,0 +1,603 @@
+//! Self-contained update bundles for offline installation.
+//!
+//! A bundle packs one or more commits, each as a static delta with inline parts and no
+//! fallback objects, together with refs and optionally the summary of the source
+//! repository into a single file. Detached commit metadata, including signatures, is
+//! carried in the delta superblocks.
+//!
+//! The file starts with the magic `OSTBNDL1`, followed by entries of the form
+//! `(u32 name length, name, u64 data length, data)` with big-endian lengths. The first
+//! entry is the `manifest`, an `a{sv}` variant listing the refs and commits.
+
+#[cfg(any(feature = "v2020_2", feature = "dox"))]
+use crate::prelude::SignExt;
+use crate::static_delta::delta_relpath;
+use crate::{
+    CommitMetadata, CommitMetadataError, ObjectType, Repo, RepoTransactionStats, Sign,
+    StaticDeltaError, StaticDeltaGenerateOptions, StaticDeltaSuperblock,
+};
+use gio::prelude::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"OSTBNDL1";
+const MANIFEST: &str = "manifest";
+const SUMMARY: &str = "summary";
+const SUMMARY_SIG: &str = "summary.sig";
+/// Entry names are short; anything longer indicates a corrupt bundle.
+const MAX_NAME_LEN: u32 = 4096;
+
+/// Error returned by the bundle APIs.
+#[derive(Debug, thiserror::Error)]
+pub enum BundleError {
+    /// The bundle is malformed.
+    #[error("invalid bundle: {0}")]
+    Format(String),
+    /// The bundle contents are inconsistent with the repository or the request.
+    #[error("{0}")]
+    Invalid(String),
+    /// A commit in the bundle has no valid signature from any of the given signers.
+    #[error("commit {0} has no valid signature")]
+    Unsigned(String),
+    /// No signers were given to verify the bundle with, and verification was not
+    /// explicitly skipped with [`BundleImportOptions::insecure_skip_verification`].
+    #[error("no signers given to verify the bundle with")]
+    NoSigners,
+    /// A ref is not allowed by the `ostree.ref-binding` of its commit.
+    #[error("ref {ref_}: {source}")]
+    RefBinding {
+        /// The ref from the bundle manifest.
+        ref_: String,
+        /// The binding error.
+        source: CommitMetadataError,
+    },
+    /// An error reading or generating a static delta.
+    #[error(transparent)]
+    StaticDelta(#[from] StaticDeltaError),
+    /// An I/O error.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// Any other error.
+    #[error(transparent)]
+    Glib(#[from] glib::Error),
+}
+
+/// A commit contained in a bundle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleCommit {
+    /// The commit checksum.
+    pub checksum: String,
+    /// The commit the delta is based on, or `None` if the commit is included in full.
+    pub base: Option<String>,
+}
+
+impl BundleCommit {
+    fn entry_name(&self) -> String {
+        format!("deltas/{}", self.checksum)
+    }
+}
+
+/// A temporary directory below the repository `tmp/` directory, removed on drop.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(repo: &Repo) -> Result<Self, BundleError> {
+        glib::mkdtemp(repo.local_path()?.join("tmp/bundle-XXXXXX"))
+            .map(Self)
+            .ok_or_else(|| std::io::Error::last_os_error().into())
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Builder for writing a [`Bundle`] from the commits of a repository.
+#[derive(Debug)]
+pub struct BundleWriter<'a> {
+    repo: &'a Repo,
+    commits: Vec<BundleCommit>,
+    refs: BTreeMap<String, String>,
+    summary: bool,
+}
+
+impl<'a> BundleWriter<'a> {
+    /// Create a writer for commits of `repo`.
+    pub fn new(repo: &'a Repo) -> Self {
+        Self {
+            repo,
+            commits: Vec::new(),
+            refs: BTreeMap::new(),
+            summary: false,
+        }
+    }
+
+    /// Include the commit `checksum` in full.
+    pub fn commit(mut self, checksum: &str) -> Self {
+        self.commits.push(BundleCommit {
+            checksum: checksum.to_string(),
+            base: None,
+        });
+        self
+    }
+
+    /// Include the commit `checksum` as a delta from `base`, which must be present in the
+    /// repository the bundle is imported into, or earlier in the bundle.
+    pub fn commit_from(mut self, base: &str, checksum: &str) -> Self {
+        self.commits.push(BundleCommit {
+            checksum: checksum.to_string(),
+            base: Some(base.to_string()),
+        });
+        self
+    }
+
+    /// Set `name` to `checksum` on import. The commit must be included in the bundle.
+    pub fn set_ref(mut self, name: &str, checksum: &str) -> Self {
+        self.refs.insert(name.to_string(), checksum.to_string());
+        self
+    }
+
+    /// Include the summary of the repository and its signatures, if any.
+    pub fn include_summary(mut self, include: bool) -> Self {
+        self.summary = include;
+        self
+    }
+
+    fn manifest(&self) -> glib::Variant {
+        let commits = self
+            .commits
+            .iter()
+            .map(|c| (c.checksum.clone(), c.base.clone().unwrap_or_default()))
+            .collect::<Vec<_>>();
+        let dict = glib::VariantDict::new(None);
+        dict.insert_value("refs", &self.refs.to_variant());
+        dict.insert_value("commits", &commits.to_variant());
+        dict.end()
+    }
+
+    fn validate(&self) -> Result<(), BundleError> {
+        if self.commits.is_empty() {
+            return Err(BundleError::Invalid("bundle contains no commits".into()));
+        }
+        for c in self.commits.iter() {
+            crate::validate_checksum_string(&c.checksum)?;
+            if let Some(base) = c.base.as_deref() {
+                crate::validate_checksum_string(base)?;
+            }
+        }
+        for (name, checksum) in self.refs.iter() {
+            if !self.commits.iter().any(|c| &c.checksum == checksum) {
+                return Err(BundleError::Invalid(format!(
+                    "ref {name} points to {checksum}, which is not in the bundle"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the bundle to `out`.
+    pub fn write<W: Write, P: IsA<gio::Cancellable>>(
+        &self,
+        out: W,
+        cancellable: Option<&P>,
+    ) -> Result<(), BundleError> {
+        self.validate()?;
+        let mut out = BufWriter::new(out);
+        out.write_all(MAGIC)?;
+        write_entry(&mut out, MANIFEST, self.manifest().data())?;
+
+        let tmpdir = TempDir::new(self.repo)?;
+        let superblock = tmpdir.0.join("superblock");
+        let options = StaticDeltaGenerateOptions::new()
+            .inline_parts(true)
+            .min_fallback_size(0)
+            .filename(&superblock);
+        for c in self.commits.iter() {
+            self.repo.static_delta_generate_with_options(
+                c.base.as_deref(),
+                &c.checksum,
+                &options,
+                cancellable,
+            )?;
+            write_entry(&mut out, &c.entry_name(), &std::fs::read(&superblock)?)?;
+        }
+
+        if self.summary {
+            let repo_path = self.repo.local_path()?;
+            let summary = std::fs::read(repo_path.join(SUMMARY)).map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    BundleError::Invalid("repository has no summary".into())
+                }
+                _ => e.into(),
+            })?;
+            write_entry(&mut out, SUMMARY, &summary)?;
+            match std::fs::read(repo_path.join(SUMMARY_SIG)) {
+                Ok(sig) => write_entry(&mut out, SUMMARY_SIG, &sig)?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Write the bundle to a new file at `path`.
+    pub fn write_to_path<P: IsA<gio::Cancellable>>(
+        &self,
+        path: impl AsRef<Path>,
+        cancellable: Option<&P>,
+    ) -> Result<(), BundleError> {
+        self.write(File::create(path)?, cancellable)
+    }
+}
+
+fn write_entry(out: &mut impl Write, name: &str, data: &[u8]) -> std::io::Result<()> {
+    out.write_all(&(name.len() as u32).to_be_bytes())?;
+    out.write_all(name.as_bytes())?;
+    out.write_all(&(data.len() as u64).to_be_bytes())?;
+    out.write_all(data)
+}
+
+/// A bundle opened for reading.
+#[derive(Debug)]
+pub struct Bundle {
+    file: File,
+    /// Offset and length of each entry.
+    entries: BTreeMap<String, (u64, u64)>,
+    refs: BTreeMap<String, String>,
+    commits: Vec<BundleCommit>,
+}
+
+impl Bundle {
+    /// Open the bundle at `path` and read its manifest.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, BundleError> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut magic = [0u8; 8];
+        file.read_exact_at(&mut magic, 0)
+            .map_err(|_| BundleError::Format("missing magic".into()))?;
+        if &magic != MAGIC {
+            return Err(BundleError::Format("missing magic".into()));
+        }
+        let mut entries = BTreeMap::new();
+        let mut offset = MAGIC.len() as u64;
+        while offset < file_len {
+            let truncated = || BundleError::Format(format!("truncated entry at {offset}"));
+            let mut len = [0u8; 4];
+            file.read_exact_at(&mut len, offset)
+                .map_err(|_| truncated())?;
+            let name_len = u32::from_be_bytes(len);
+            if name_len > MAX_NAME_LEN {
+                return Err(BundleError::Format(format!("invalid entry at {offset}")));
+            }
+            let mut name = vec![0u8; name_len as usize];
+            file.read_exact_at(&mut name, offset + 4)
+                .map_err(|_| truncated())?;
+            let name = String::from_utf8(name)
+                .map_err(|_| BundleError::Format(format!("invalid entry name at {offset}")))?;
+            let mut len = [0u8; 8];
+            file.read_exact_at(&mut len, offset + 4 + name_len as u64)
+                .map_err(|_| truncated())?;
+            let data_len = u64::from_be_bytes(len);
+            let data_offset = offset + 12 + name_len as u64;
+            if data_offset
+                .checked_add(data_len)
+                .map_or(true, |end| end > file_len)
+            {
+                return Err(truncated());
+            }
+            if entries.is_empty() && name != MANIFEST {
+                return Err(BundleError::Format("manifest must come first".into()));
+            }
+            entries.insert(name, (data_offset, data_len));
+            offset = data_offset + data_len;
+        }
+        let mut bundle = Self {
+            file,
+            entries,
+            refs: BTreeMap::new(),
+            commits: Vec::new(),
+        };
+        bundle.read_manifest()?;
+        Ok(bundle)
+    }
+
+    fn read_manifest(&mut self) -> Result<(), BundleError> {
+        let data = self
+            .read_entry(MANIFEST)?
+            .ok_or_else(|| BundleError::Format("missing manifest".into()))?;
+        let v = glib::Variant::from_bytes_with_type(
+            &glib::Bytes::from_owned(data),
+            glib::VariantTy::VARDICT,
+        )
+        .normal_form();
+        let dict = glib::VariantDict::new(Some(&v));
+        let invalid = |_| BundleError::Format("invalid manifest".into());
+        self.refs = dict
+            .lookup::<BTreeMap<String, String>>("refs")
+            .map_err(invalid)?
+            .unwrap_or_default();
+        self.commits = dict
+            .lookup::<Vec<(String, String)>>("commits")
+            .map_err(invalid)?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(checksum, base)| BundleCommit {
+                checksum,
+                base: Some(base).filter(|b| !b.is_empty()),
+            })
+            .collect();
+        for c in self.commits.iter() {
+            if !self.entries.contains_key(&c.entry_name()) {
+                return Err(BundleError::Format(format!(
+                    "missing delta for {}",
+                    c.checksum
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_entry(&self, name: &str) -> Result<Option<Vec<u8>>, BundleError> {
+        let Some(&(offset, len)) = self.entries.get(name) else {
+            return Ok(None);
+        };
+        let mut buf = vec![0u8; len as usize];
+        self.file.read_exact_at(&mut buf, offset)?;
+        Ok(Some(buf))
+    }
+
+    fn delta_data(&self, commit: &BundleCommit) -> Result<Vec<u8>, BundleError> {
+        self.read_entry(&commit.entry_name())?
+            .ok_or_else(|| BundleError::Format(format!("missing delta for {}", commit.checksum)))
+    }
+
+    /// The refs set on import, mapped to their commits.
+    pub fn refs(&self) -> &BTreeMap<String, String> {
+        &self.refs
+    }
+
+    /// The commits in the bundle, in import order.
+    pub fn commits(&self) -> &[BundleCommit] {
+        &self.commits
+    }
+
+    /// Read the delta superblock of `commit`, which contains the commit object and its
+    /// detached metadata.
+    pub fn superblock(&self, commit: &BundleCommit) -> Result<StaticDeltaSuperblock, BundleError> {
+        let superblock = StaticDeltaSuperblock::from_bytes(&self.delta_data(commit)?)?;
+        if superblock.to != commit.checksum || superblock.from != commit.base {
+            return Err(BundleError::Format(format!(
+                "delta for {} does not match the manifest",
+                commit.checksum
+            )));
+        }
+        Ok(superblock)
+    }
+
+    /// The summary of the source repository, if included.
+    pub fn summary(&self) -> Result<Option<Vec<u8>>, BundleError> {
+        self.read_entry(SUMMARY)
+    }
+
+    /// The signatures of the summary, if included.
+    pub fn summary_signatures(&self) -> Result<Option<Vec<u8>>, BundleError> {
+        self.read_entry(SUMMARY_SIG)
+    }
+}
+
+/// The detached metadata of `superblock`'s target commit, as written by libostree.
+fn detached_metadata(superblock: &StaticDeltaSuperblock) -> Option<glib::Variant> {
+    let key = format!(
+        "{}/commitmeta",
+        delta_relpath(superblock.from.as_deref(), &superblock.to)
+    );
+    glib::VariantDict::new(Some(&superblock.metadata))
+        .lookup_value(&key, Some(glib::VariantTy::VARDICT))
+}
+
+/// Check that the commit in `superblock` has a valid signature from one of `signers`.
+fn verify_commit(superblock: &StaticDeltaSuperblock, signers: &[Sign]) -> Result<(), BundleError> {
+    let unsigned = || BundleError::Unsigned(superblock.to.clone());
+    let detached = detached_metadata(superblock).ok_or_else(unsigned)?;
+    #[cfg(any(feature = "v2020_2", feature = "dox"))]
+    {
+        let dict = glib::VariantDict::new(Some(&detached));
+        let data = superblock.commit.data_as_bytes();
+        for sign in signers {
+            let ty = glib::VariantTy::new(&sign.metadata_format())
+                .map_err(|e| BundleError::Invalid(e.to_string()))?
+                .to_owned();
+            let Some(signatures) = dict.lookup_value(&sign.metadata_key(), Some(&ty)) else {
+                continue;
+            };
+            if sign.data_verify(&data, &signatures).is_ok() {
+                return Ok(());
+            }
+        }
+        Err(unsigned())
+    }
+    #[cfg(not(any(feature = "v2020_2", feature = "dox")))]
+    {
+        let _ = (detached, signers);
+        Err(BundleError::Invalid(
+            "signature verification requires the v2020_2 feature".into(),
+        ))
+    }
+}
+
+/// Options for [`Repo::import_bundle_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct BundleImportOptions<'a> {
+    verify_with: &'a [Sign],
+    insecure_skip_verification: bool,
+    allow_ref_binding_mismatch: bool,
+}
+
+impl<'a> BundleImportOptions<'a> {
+    /// Create options with no signers and ref bindings enforced. Importing fails unless
+    /// signers are given or verification is explicitly skipped.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require every commit to carry a valid signature from at least one of `signers`.
+    pub fn verify_with(mut self, signers: &'a [Sign]) -> Self {
+        self.verify_with = signers;
+        self
+    }
+
+    /// Import commits without verifying their signatures. Anyone able to supply the
+    /// bundle then controls what is imported.
+    pub fn insecure_skip_verification(mut self, skip: bool) -> Self {
+        self.insecure_skip_verification = skip;
+        self
+    }
+
+    /// Set refs even if they are not listed in the `ostree.ref-binding` of their commit.
+    /// The refs come from the unsigned bundle manifest, so this allows pointing a ref at
+    /// any signed commit in the bundle.
+    pub fn allow_ref_binding_mismatch(mut self, allow: bool) -> Self {
+        self.allow_ref_binding_mismatch = allow;
+        self
+    }
+}
+
+impl Repo {
+    /// Verify and import the bundle at `path`, see [`Repo::import_bundle_with_options`].
+    /// Every commit must be signed by one of `verify_with`, which must not be empty.
+    pub fn import_bundle<P: IsA<gio::Cancellable>>(
+        &self,
+        path: impl AsRef<Path>,
+        verify_with: &[Sign],
+        cancellable: Option<&P>,
+    ) -> Result<RepoTransactionStats, BundleError> {
+        let options = BundleImportOptions::new().verify_with(verify_with);
+        self.import_bundle_with_options(path, &options, cancellable)
+    }
+
+    /// Import the bundle at `path`.
+    ///
+    /// Commits are checked for signatures as configured in `options`, failing with
+    /// [`BundleError::NoSigners`] if no signers are given and verification is not
+    /// explicitly skipped. Each ref must be allowed by the `ostree.ref-binding` of its
+    /// commit, if it has one; this is all checked before anything is written. The commits
+    /// are imported and the refs set in a single transaction, so that on failure the
+    /// repository is left unchanged. The summary in the bundle, if any, is not installed.
+    pub fn import_bundle_with_options<P: IsA<gio::Cancellable>>(
+        &self,
+        path: impl AsRef<Path>,
+        options: &BundleImportOptions,
+        cancellable: Option<&P>,
+    ) -> Result<RepoTransactionStats, BundleError> {
+        let verify = !options.insecure_skip_verification;
+        if verify && options.verify_with.is_empty() {
+            return Err(BundleError::NoSigners);
+        }
+        let bundle = Bundle::open(path)?;
+        for (name, checksum) in bundle.refs() {
+            let Some(c) = bundle.commits().iter().find(|c| &c.checksum == checksum) else {
+                return Err(BundleError::Invalid(format!(
+                    "ref {name} points to {checksum}, which is not in the bundle"
+                )));
+            };
+            if options.allow_ref_binding_mismatch {
+                continue;
+            }
+            let superblock = bundle.superblock(c)?;
+            CommitMetadata::from_commit(&superblock.commit)
+                .and_then(|m| m.validate_ref_binding(&[name]))
+                .map_err(|source| BundleError::RefBinding {
+                    ref_: name.clone(),
+                    source,
+                })?;
+        }
+        for (i, c) in bundle.commits().iter().enumerate() {
+            let superblock = bundle.superblock(c)?;
+            if verify {
+                verify_commit(&superblock, options.verify_with)?;
+            }
+            if let Some(base) = c.base.as_deref() {
+                let earlier = bundle.commits()[..i].iter().any(|e| e.checksum == base);
+                if !earlier && !self.has_object(ObjectType::Commit, base, cancellable)? {
+                    return Err(BundleError::Invalid(format!(
+                        "commit {} requires base commit {base}",
+                        c.checksum
+                    )));
+                }
+            }
+        }
+
+        let tmpdir = TempDir::new(self)?;
+        let path = tmpdir.0.join("superblock");
+        let txn = self.auto_transaction(cancellable)?;
+        for c in bundle.commits() {
+            std::fs::write(&path, bundle.delta_data(c)?)?;
+            self.static_delta_execute_offline(&gio::File::for_path(&path), false, cancellable)?;
+        }
+        for (name, checksum) in bundle.refs() {
+            self.transaction_set_refspec(name, Some(checksum));
+        }
+        Ok(txn.commit(cancellable)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_reject_invalid_bundles() {
+        let dir = glib::mkdtemp(std::env::temp_dir().join("bundle-XXXXXX")).unwrap();
+        let path = dir.join("bundle");
+        std::fs::write(&path, b"notabundle").unwrap();
+        assert!(matches!(Bundle::open(&path), Err(BundleError::Format(_))));
+
+        let mut data = MAGIC.to_vec();
+        write_entry(&mut data, "deltas/foo", b"").unwrap();
+        std::fs::write(&path, &data).unwrap();
+        assert!(matches!(Bundle::open(&path), Err(BundleError::Format(_))));
+
+        let mut data = MAGIC.to_vec();
+        write_entry(&mut data, MANIFEST, b"").unwrap();
+        data.extend_from_slice(&[0, 0, 0, 4, b'a']);
+        std::fs::write(&path, &data).unwrap();
+        assert!(matches!(Bundle::open(&path), Err(BundleError::Format(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn should_read_manifest() {
+        let dir = glib::mkdtemp(std::env::temp_dir().join("bundle-XXXXXX")).unwrap();
+        let path = dir.join("bundle");
+        let a = "a".repeat(64);
+        let b = "b".repeat(64);
+        let dict = glib::VariantDict::new(None);
+        let refs = BTreeMap::from([("os/stable".to_string(), b.clone())]);
+        dict.insert_value("refs", &refs.to_variant());
+        dict.insert_value(
+            "commits",
+            &vec![(a.clone(), String::new()), (b.clone(), a.clone())].to_variant(),
+        );
+        let mut data = MAGIC.to_vec();
+        write_entry(&mut data, MANIFEST, dict.end().data()).unwrap();
+        write_entry(&mut data, &format!("deltas/{a}"), b"").unwrap();
+        write_entry(&mut data, &format!("deltas/{b}"), b"").unwrap();
+        write_entry(&mut data, SUMMARY, b"summary").unwrap();
+        std::fs::write(&path, &data).unwrap();
+
+        let bundle = Bundle::open(&path).unwrap();
+        assert_eq!(bundle.refs(), &refs);
+        assert_eq!(bundle.commits().len(), 2);
+        assert_eq!(bundle.commits()[0].base, None);
+        assert_eq!(bundle.commits()[1].base.as_deref(), Some(a.as_str()));
+        assert_eq!(bundle.summary().unwrap().unwrap(), b"summary");
+        assert_eq!(bundle.summary_signatures().unwrap(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,42 @@
+//! Helpers for Rust callbacks invoked from libostree.
+
+use std::any::Any;
+use std::panic::{catch_unwind, UnwindSafe};
+use std::process::abort;
+
+/// Call `f`, aborting the process if it panics.
+///
+/// Unwinding across an FFI boundary is Undefined Behavior and we have no other way to communicate
+/// the error. We abort() safely to avoid further problems.
+pub(crate) fn abort_on_panic<R>(f: impl FnOnce() -> R + UnwindSafe) -> R {
+    catch_unwind(f).unwrap_or_else(|panic| {
+        print_panic(panic);
+        abort()
+    })
+}
+
+/// Print a panic message and the value to stderr, if we can.
+///
+/// If the panic value is either `&str` or `String`, we print it. Otherwise, we don't.
+fn print_panic(panic: Box<dyn Any>) {
+    use std::io::Write;
+    let stderr = std::io::stderr();
+    let mut stderr = stderr.lock();
+    // Directly write to stderr instead of eprintln!() as that function panics
+    // if writing fails, which would involve a double panic which we don't want.
+    let _ = stderr.write_all(
+        r#"A Rust callback invoked by C code panicked.
+Unwinding across FFI boundaries is Undefined Behavior so abort() will be called."#
+            .as_bytes(),
+    );
+    let msg = {
+        if let Some(s) = panic.as_ref().downcast_ref::<&str>() {
+            s
+        } else if let Some(s) = panic.as_ref().downcast_ref::<String>() {
+            s
+        } else {
+            "(non-string panic value)"
+        }
+    };
+    let _ = stderr.write_all(msg.as_bytes());
+}
@@ -0,0 +1,361 @@
+//! A high-level wrapper for writing a commit and updating refs in one transaction.
+
+#[cfg(any(feature = "v2020_2", feature = "dox"))]
+use crate::{prelude::*, Sign};
+use crate::{
+    CommitMetadata, MutableTree, Repo, RepoCommitModifier, RepoFile, RepoTransactionStats,
+    ReproducibleOptions,
+};
+use gio::prelude::*;
+use glib::GString;
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+
+/// The filesystem tree to commit.
+#[derive(Debug)]
+pub enum CommitSource<'a> {
+    /// A directory, given as a path relative to a directory file descriptor.
+    Dfd {
+        /// The directory file descriptor.
+        dfd: BorrowedFd<'a>,
+        /// Path relative to `dfd`.
+        path: &'a str,
+    },
+    /// A directory.
+    Directory(&'a gio::File),
+    /// A tar archive.
+    Tar(&'a gio::File),
+    /// A tar archive read from a file descriptor.
+    TarFd(BorrowedFd<'a>),
+    /// An already populated tree. The modifier is not applied to this source, since the
+    /// tree has already been written.
+    MutableTree(&'a MutableTree),
+}
+
+/// How to determine the parent of the new commit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommitParent<'a> {
+    /// Create a commit without a parent.
+    #[default]
+    None,
+    /// Use the given commit checksum as parent.
+    Explicit(&'a str),
+    /// Use the current target of a ref as parent; if the ref does not exist, the commit
+    /// will have no parent.
+    FromRef(&'a str),
+}
+
+/// Builder for writing a commit with [`Repo::write_commit`] and related functions.
+///
+/// This prepares a transaction, imports the source into a [`MutableTree`], writes the
+/// commit and optional signatures, sets the target refs and commits the transaction.
+/// If any step fails, the transaction is aborted.
+#[derive(Debug)]
+pub struct CommitBuilder<'a> {
+    source: CommitSource<'a>,
+    parent: CommitParent<'a>,
+    subject: Option<String>,
+    body: Option<String>,
+    metadata: CommitMetadata,
+    detached_metadata: Option<glib::Variant>,
+    timestamp: Option<u64>,
+    modifier: Option<&'a RepoCommitModifier>,
+    #[cfg(any(feature = "v2020_2", feature = "dox"))]
+    signers: Vec<&'a Sign>,
+    composefs_metadata: bool,
+    bindings: bool,
+    refs: Vec<String>,
+    reproducible: Option<ReproducibleOptions>,
+    expected_checksum: Option<String>,
+}
+
+impl<'a> CommitBuilder<'a> {
+    /// Create a new builder which commits the given source.
+    pub fn new(source: CommitSource<'a>) -> Self {
+        Self {
+            source,
+            parent: CommitParent::default(),
+            subject: None,
+            body: None,
+            metadata: CommitMetadata::default(),
+            detached_metadata: None,
+            timestamp: None,
+            modifier: None,
+            #[cfg(any(feature = "v2020_2", feature = "dox"))]
+            signers: Vec::new(),
+            composefs_metadata: false,
+            bindings: true,
+            refs: Vec::new(),
+            reproducible: None,
+            expected_checksum: None,
+        }
+    }
+
+    /// Set how the parent commit is determined.
+    pub fn parent(mut self, parent: CommitParent<'a>) -> Self {
+        self.parent = parent;
+        self
+    }
+
+    /// Set the commit subject.
+    pub fn subject(mut self, subject: &str) -> Self {
+        self.subject = Some(subject.to_string());
+        self
+    }
+
+    /// Set the commit body.
+    pub fn body(mut self, body: &str) -> Self {
+        self.body = Some(body.to_string());
+        self
+    }
+
+    /// Set the commit metadata.
+    pub fn metadata(mut self, metadata: CommitMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Set detached metadata to write alongside the commit; must be of type `a{sv}`.
+    pub fn detached_metadata(mut self, metadata: glib::Variant) -> Self {
+        self.detached_metadata = Some(metadata);
+        self
+    }
+
+    /// Set the commit timestamp, in seconds since the Unix epoch. By default, the current
+    /// time is used.
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Set the modifier used when importing the source.
+    pub fn modifier(mut self, modifier: &'a RepoCommitModifier) -> Self {
+        self.modifier = Some(modifier);
+        self
+    }
+
+    /// Sign the commit with the given signer, which must have a secret key set.
+    /// May be called multiple times to add several signatures.
+    #[cfg(any(feature = "v2020_2", feature = "dox"))]
+    #[cfg_attr(feature = "dox", doc(cfg(feature = "v2020_2")))]
+    pub fn sign(mut self, signer: &'a Sign) -> Self {
+        self.signers.push(signer);
+        self
+    }
+
+    /// Add composefs metadata to the commit, using [`Repo::commit_add_composefs_metadata`].
+    pub fn composefs_metadata(mut self, enabled: bool) -> Self {
+        self.composefs_metadata = enabled;
+        self
+    }
+
+    /// Whether to bind the commit to the target refs and the repository collection ID,
+    /// like `ostree commit` does by default. Enabled by default.
+    pub fn bindings(mut self, enabled: bool) -> Self {
+        self.bindings = enabled;
+        self
+    }
+
+    /// Add a ref to point at the new commit.
+    pub fn target_ref(mut self, refspec: &str) -> Self {
+        self.refs.push(refspec.to_string());
+        self
+    }
+
+    /// Write the commit in reproducible mode, see [`ReproducibleOptions`].
+    ///
+    /// The commit timestamp is clamped to the source date epoch and the source is imported
+    /// with a modifier normalizing ownership, permissions and extended attributes. This
+    /// cannot be combined with [`CommitBuilder::modifier`]. For tar sources, the xattrs
+    /// stored in the archive are committed unchanged unless all xattrs are stripped.
+    pub fn reproducible(mut self, options: ReproducibleOptions) -> Self {
+        self.reproducible = Some(options);
+        self
+    }
+
+    /// Require the new commit to have the given checksum.
+    ///
+    /// On mismatch, the transaction is aborted without updating any ref, and the error
+    /// message contains the [`RebuildReport`](crate::RebuildReport) comparing the rebuilt
+    /// commit against the expected one, if the latter is present in the repository.
+    pub fn expect_checksum(mut self, checksum: &str) -> Self {
+        self.expected_checksum = Some(checksum.to_string());
+        self
+    }
+
+    fn resolve_parent(&self, repo: &Repo) -> Result<Option<GString>, glib::Error> {
+        match self.parent {
+            CommitParent::None => Ok(None),
+            CommitParent::Explicit(checksum) => Ok(Some(repo.require_rev(checksum)?)),
+            CommitParent::FromRef(refspec) => repo.resolve_rev(refspec, true),
+        }
+    }
+
+    fn write_tree(
+        &self,
+        repo: &Repo,
+        cancellable: Option<&gio::Cancellable>,
+    ) -> Result<RepoFile, glib::Error> {
+        let reproducible_modifier;
+        let modifier = match self.reproducible.as_ref() {
+            Some(opts) => {
+                reproducible_modifier = opts.modifier(self.xattr_source()?);
+                Some(&reproducible_modifier)
+            }
+            None => self.modifier,
+        };
+        let mtree = match self.source {
+            CommitSource::MutableTree(mtree) => mtree.clone(),
+            _ => {
+                let mtree = MutableTree::new();
+                match self.source {
+                    CommitSource::Dfd { dfd, path } => repo.write_dfd_to_mtree(
+                        dfd.as_raw_fd(),
+                        path,
+                        &mtree,
+                        modifier,
+                        cancellable,
+                    )?,
+                    CommitSource::Directory(dir) => {
+                        repo.write_directory_to_mtree(dir, &mtree, modifier, cancellable)?
+                    }
+                    CommitSource::Tar(archive) => {
+                        repo.write_archive_to_mtree(archive, &mtree, modifier, true, cancellable)?
+                    }
+                    CommitSource::TarFd(fd) => repo.write_archive_to_mtree_from_fd(
+                        fd.as_raw_fd(),
+                        &mtree,
+                        modifier,
+                        true,
+                        cancellable,
+                    )?,
+                    CommitSource::MutableTree(_) => unreachable!(),
+                }
+                mtree
+            }
+        };
+        let root = repo.write_mtree(&mtree, cancellable)?;
+        // Safety: write_mtree always returns a RepoFile
+        Ok(root.downcast::<RepoFile>().unwrap())
+    }
+
+    fn xattr_source(&self) -> Result<Option<OwnedFd>, glib::Error> {
+        let open_error = |e: std::io::Error| {
+            glib::Error::new(
+                gio::IOErrorEnum::Failed,
+                &format!("Opening commit source for xattrs: {e}"),
+            )
+        };
+        match self.source {
+            CommitSource::Dfd { dfd, path } => {
+                let path = CString::new(path).map_err(|e| open_error(e.into()))?;
+                // Safety: path is a valid C string and dfd is a valid file descriptor.
+                let fd = unsafe {
+                    libc::openat(
+                        dfd.as_raw_fd(),
+                        path.as_ptr(),
+                        libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+                    )
+                };
+                if fd < 0 {
+                    return Err(open_error(std::io::Error::last_os_error()));
+                }
+                // Safety: fd was just opened and is owned by us.
+                Ok(Some(unsafe { OwnedFd::from_raw_fd(fd) }))
+            }
+            CommitSource::Directory(dir) => match dir.path() {
+                Some(path) => Ok(Some(std::fs::File::open(path).map_err(open_error)?.into())),
+                None => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Write the commit to `repo`, returning its checksum and the transaction statistics.
+    pub fn commit(
+        self,
+        repo: &Repo,
+        cancellable: Option<&gio::Cancellable>,
+    ) -> Result<(GString, RepoTransactionStats), glib::Error> {
+        if self.reproducible.is_some() && self.modifier.is_some() {
+            return Err(glib::Error::new(
+                gio::IOErrorEnum::InvalidArgument,
+                "A custom modifier cannot be used in reproducible mode",
+            ));
+        }
+        let parent = self.resolve_parent(repo)?;
+        let txn = repo.auto_transaction(cancellable)?;
+        let root = self.write_tree(repo, cancellable)?;
+
+        let mut metadata = self.metadata.clone();
+        if self.bindings && !self.refs.is_empty() {
+            let refs = self
+                .refs
+                .iter()
+                .map(|r| Ok(crate::parse_refspec(r)?.1))
+                .collect::<Result<Vec<_>, glib::Error>>()?;
+            let refs = refs.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+            metadata.set_ref_binding(&refs);
+        }
+        #[cfg(any(feature = "v2018_6", feature = "dox"))]
+        if let Some(collection_id) = repo.collection_id().filter(|_| self.bindings) {
+            metadata.set_collection_binding(&collection_id);
+        }
+        let metadata = if self.composefs_metadata {
+            let dict = metadata.to_variant_dict();
+            repo.commit_add_composefs_metadata(0, &dict, &root, cancellable)?;
+            dict.end()
+        } else {
+            metadata.to_variant()
+        };
+
+        let subject = self.subject.as_deref();
+        let body = self.body.as_deref();
+        let timestamp = match self.reproducible.as_ref() {
+            Some(opts) => Some(opts.clamp_timestamp(self.timestamp)),
+            None => self.timestamp,
+        };
+        let checksum = match timestamp {
+            Some(t) => repo.write_commit_with_time(
+                parent.as_deref(),
+                subject,
+                body,
+                Some(&metadata),
+                &root,
+                t,
+                cancellable,
+            )?,
+            None => repo.write_commit(
+                parent.as_deref(),
+                subject,
+                body,
+                Some(&metadata),
+                &root,
+                cancellable,
+            )?,
+        };
+
+        if let Some(expected) = self.expected_checksum.as_deref() {
+            if let Some(report) = repo.verify_rebuild(&checksum, expected)? {
+                return Err(glib::Error::new(
+                    gio::IOErrorEnum::Failed,
+                    &report.to_string(),
+                ));
+            }
+        }
+
+        if let Some(detached) = self.detached_metadata.as_ref() {
+            repo.write_commit_detached_metadata(&checksum, Some(detached), cancellable)?;
+        }
+        #[cfg(any(feature = "v2020_2", feature = "dox"))]
+        for signer in self.signers.iter() {
+            signer.commit(repo, &checksum, cancellable)?;
+        }
+
+        for refspec in self.refs.iter() {
+            repo.transaction_set_refspec(refspec, Some(&checksum));
+        }
+        let stats = txn.commit(cancellable)?;
+        Ok((checksum, stats))
+    }
+}
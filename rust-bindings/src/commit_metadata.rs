@@ -0,0 +1,433 @@
+//! Typed access to the well-known keys of the commit metadata dictionary.
+
+use crate::COMMIT_META_CONTAINER_CMD;
+use crate::COMMIT_META_KEY_COMPOSEFS_DIGEST_V0;
+use glib::prelude::*;
+use glib::{Variant, VariantDict, VariantTy};
+use std::collections::BTreeMap;
+
+// These mirror the `OSTREE_COMMIT_META_KEY_*` and `OSTREE_METADATA_KEY_*` constants,
+// which are only exported by the generated bindings behind version features.
+const KEY_VERSION: &str = "version";
+const KEY_ARCHITECTURE: &str = "ostree.architecture";
+const KEY_BOOTABLE: &str = "ostree.bootable";
+const KEY_LINUX: &str = "ostree.linux";
+const KEY_ENDOFLIFE: &str = "ostree.endoflife";
+const KEY_ENDOFLIFE_REBASE: &str = "ostree.endoflife-rebase";
+const KEY_SOURCE_TITLE: &str = "ostree.source-title";
+const KEY_REF_BINDING: &str = "ostree.ref-binding";
+const KEY_COLLECTION_BINDING: &str = "ostree.collection-binding";
+
+/// Error returned when reading or validating commit metadata.
+#[derive(Debug, thiserror::Error)]
+pub enum CommitMetadataError {
+    /// The variant is not a metadata dictionary.
+    #[error("expected metadata of type a{{sv}}, found {0}")]
+    NotADictionary(String),
+    /// The variant is not a commit object.
+    #[error("expected a commit object, found {0}")]
+    NotACommit(String),
+    /// A well-known key holds a value of the wrong type.
+    #[error("metadata key {key} has type {actual}, expected {expected}")]
+    InvalidType {
+        /// The metadata key.
+        key: String,
+        /// The type defined for this key.
+        expected: String,
+        /// The type found in the metadata.
+        actual: String,
+    },
+    /// The commit is bound to a set of refs which does not include the given ref.
+    #[error("commit is not bound to ref {ref_}; bound refs: {}", .bound.join(", "))]
+    RefNotBound {
+        /// The ref which was checked.
+        ref_: String,
+        /// The refs listed in `ostree.ref-binding`.
+        bound: Vec<String>,
+    },
+}
+
+/// A typed view of the `a{sv}` metadata dictionary stored in a commit.
+///
+/// Getters and setters are provided for the keys documented by libostree; any other
+/// key is preserved as-is and can be accessed with [`CommitMetadata::get`] and
+/// [`CommitMetadata::insert`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommitMetadata {
+    entries: BTreeMap<String, Variant>,
+}
+
+impl CommitMetadata {
+    /// Create empty commit metadata.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a metadata dictionary; must be of type `a{sv}`.
+    pub fn from_variant(v: &Variant) -> Result<Self, CommitMetadataError> {
+        if v.type_() != VariantTy::VARDICT {
+            return Err(CommitMetadataError::NotADictionary(v.type_().to_string()));
+        }
+        let entries = v
+            .iter()
+            .map(|entry| {
+                let key = entry.child_value(0).str().unwrap().to_string();
+                let value = entry.child_value(1).as_variant().unwrap();
+                (key, value)
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+
+    /// Extract the metadata from a commit object of type `(a{sv}aya(say)sstayay)`.
+    pub fn from_commit(commit: &Variant) -> Result<Self, CommitMetadataError> {
+        if commit.type_().as_str() != crate::COMMIT_GVARIANT_STRING.as_str() {
+            return Err(CommitMetadataError::NotACommit(commit.type_().to_string()));
+        }
+        Self::from_variant(&commit.child_value(0))
+    }
+
+    /// Create commit metadata from the contents of a `VariantDict`.
+    pub fn from_dict(dict: &VariantDict) -> Self {
+        // A VariantDict always serializes to a{sv}.
+        Self::from_variant(&dict.end()).unwrap()
+    }
+
+    /// Serialize to an `a{sv}` variant, suitable for [`Repo::write_commit`](crate::Repo::write_commit).
+    pub fn to_variant(&self) -> Variant {
+        let dict = self.to_variant_dict();
+        dict.end()
+    }
+
+    /// Create a new `VariantDict` containing all entries.
+    pub fn to_variant_dict(&self) -> VariantDict {
+        let dict = VariantDict::new(None);
+        for (k, v) in self.entries.iter() {
+            dict.insert_value(k, v);
+        }
+        dict
+    }
+
+    /// Look up the raw value of a key.
+    pub fn get(&self, key: &str) -> Option<&Variant> {
+        self.entries.get(key)
+    }
+
+    /// Set the raw value of a key, returning the previous value.
+    pub fn insert(&mut self, key: &str, value: impl ToVariant) -> Option<Variant> {
+        self.entries.insert(key.to_string(), value.to_variant())
+    }
+
+    /// Remove a key, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<Variant> {
+        self.entries.remove(key)
+    }
+
+    /// Whether the metadata contains the key.
+    pub fn contains(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Iterate over all entries, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Variant)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Iterate over entries whose key is not one of the well-known keys.
+    pub fn unknown_keys(&self) -> impl Iterator<Item = (&str, &Variant)> {
+        self.iter().filter(|(k, _)| !is_well_known(k))
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn typed<T: FromVariant>(&self, key: &str) -> Result<Option<T>, CommitMetadataError> {
+        let Some(v) = self.entries.get(key) else {
+            return Ok(None);
+        };
+        v.get::<T>()
+            .map(Some)
+            .ok_or_else(|| CommitMetadataError::InvalidType {
+                key: key.to_string(),
+                expected: T::static_variant_type().to_string(),
+                actual: v.type_().to_string(),
+            })
+    }
+
+    /// The `version` key: a freeform version string.
+    pub fn version(&self) -> Result<Option<String>, CommitMetadataError> {
+        self.typed(KEY_VERSION)
+    }
+
+    /// Set the `version` key.
+    pub fn set_version(&mut self, version: &str) -> &mut Self {
+        self.insert(KEY_VERSION, version);
+        self
+    }
+
+    /// The `ostree.architecture` key.
+    pub fn architecture(&self) -> Result<Option<String>, CommitMetadataError> {
+        self.typed(KEY_ARCHITECTURE)
+    }
+
+    /// Set the `ostree.architecture` key.
+    pub fn set_architecture(&mut self, arch: &str) -> &mut Self {
+        self.insert(KEY_ARCHITECTURE, arch);
+        self
+    }
+
+    /// The `ostree.bootable` key; set if the commit is intended to be bootable.
+    pub fn bootable(&self) -> Result<bool, CommitMetadataError> {
+        Ok(self.typed(KEY_BOOTABLE)?.unwrap_or_default())
+    }
+
+    /// Set the `ostree.bootable` key.
+    pub fn set_bootable(&mut self, bootable: bool) -> &mut Self {
+        self.insert(KEY_BOOTABLE, bootable);
+        self
+    }
+
+    /// The `ostree.linux` key: the kernel release (i.e. `uname -r`).
+    pub fn linux(&self) -> Result<Option<String>, CommitMetadataError> {
+        self.typed(KEY_LINUX)
+    }
+
+    /// Set the `ostree.linux` key.
+    pub fn set_linux(&mut self, release: &str) -> &mut Self {
+        self.insert(KEY_LINUX, release);
+        self
+    }
+
+    /// Set `ostree.bootable` and `ostree.linux` by inspecting the kernel in the given root.
+    #[cfg(any(feature = "v2021_1", feature = "dox"))]
+    pub fn set_bootable_from_root<P: IsA<gio::File>>(
+        &mut self,
+        root: &P,
+        cancellable: Option<&gio::Cancellable>,
+    ) -> Result<&mut Self, glib::Error> {
+        let dict = VariantDict::new(None);
+        crate::commit_metadata_for_bootable(root, &dict, cancellable)?;
+        self.entries.extend(Self::from_dict(&dict).entries);
+        Ok(self)
+    }
+
+    /// The `ostree.composefs.digest.v0` key: the fs-verity digest of the composefs image.
+    pub fn composefs_digest(&self) -> Result<Option<Vec<u8>>, CommitMetadataError> {
+        self.typed(COMMIT_META_KEY_COMPOSEFS_DIGEST_V0)
+    }
+
+    /// Set the `ostree.composefs.digest.v0` key.
+    pub fn set_composefs_digest(&mut self, digest: &[u8]) -> &mut Self {
+        self.insert(COMMIT_META_KEY_COMPOSEFS_DIGEST_V0, digest);
+        self
+    }
+
+    /// The `ostree.endoflife` key: a message shown when an update stream ends.
+    pub fn endoflife(&self) -> Result<Option<String>, CommitMetadataError> {
+        self.typed(KEY_ENDOFLIFE)
+    }
+
+    /// Set the `ostree.endoflife` key.
+    pub fn set_endoflife(&mut self, message: &str) -> &mut Self {
+        self.insert(KEY_ENDOFLIFE, message);
+        self
+    }
+
+    /// The `ostree.endoflife-rebase` key: the refspec to rebase onto.
+    pub fn endoflife_rebase(&self) -> Result<Option<String>, CommitMetadataError> {
+        self.typed(KEY_ENDOFLIFE_REBASE)
+    }
+
+    /// Set the `ostree.endoflife-rebase` key.
+    pub fn set_endoflife_rebase(&mut self, refspec: &str) -> &mut Self {
+        self.insert(KEY_ENDOFLIFE_REBASE, refspec);
+        self
+    }
+
+    /// The `ostree.source-title` key: a short human-readable description of the source.
+    pub fn source_title(&self) -> Result<Option<String>, CommitMetadataError> {
+        self.typed(KEY_SOURCE_TITLE)
+    }
+
+    /// Set the `ostree.source-title` key.
+    pub fn set_source_title(&mut self, title: &str) -> &mut Self {
+        self.insert(KEY_SOURCE_TITLE, title);
+        self
+    }
+
+    /// The `ostree.ref-binding` key: the branch names this commit may be retrieved from.
+    pub fn ref_binding(&self) -> Result<Option<Vec<String>>, CommitMetadataError> {
+        self.typed(KEY_REF_BINDING)
+    }
+
+    /// Set the `ostree.ref-binding` key.
+    pub fn set_ref_binding(&mut self, refs: &[&str]) -> &mut Self {
+        // Sort so that the commit checksum does not depend on the caller's ordering.
+        let mut refs = refs.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        refs.sort();
+        refs.dedup();
+        self.insert(KEY_REF_BINDING, refs);
+        self
+    }
+
+    /// The `ostree.collection-binding` key: the collection ID this commit is bound to.
+    pub fn collection_binding(&self) -> Result<Option<String>, CommitMetadataError> {
+        self.typed(KEY_COLLECTION_BINDING)
+    }
+
+    /// Set the `ostree.collection-binding` key.
+    pub fn set_collection_binding(&mut self, collection_id: &str) -> &mut Self {
+        self.insert(KEY_COLLECTION_BINDING, collection_id);
+        self
+    }
+
+    /// The `ostree.container-cmd` key: the Docker/OCI `CMD` verb.
+    pub fn container_cmd(&self) -> Result<Option<Vec<String>>, CommitMetadataError> {
+        self.typed(COMMIT_META_CONTAINER_CMD)
+    }
+
+    /// Set the `ostree.container-cmd` key.
+    pub fn set_container_cmd(&mut self, cmd: &[&str]) -> &mut Self {
+        self.insert(COMMIT_META_CONTAINER_CMD, cmd);
+        self
+    }
+
+    /// Check that each of `refs` is allowed by `ostree.ref-binding`.
+    ///
+    /// A commit without a ref binding may be referenced by any ref, matching the behavior
+    /// of `ostree_repo_pull()`.
+    pub fn validate_ref_binding(&self, refs: &[&str]) -> Result<(), CommitMetadataError> {
+        let Some(bound) = self.ref_binding()? else {
+            return Ok(());
+        };
+        if let Some(r) = refs.iter().find(|r| !bound.iter().any(|b| b == *r)) {
+            return Err(CommitMetadataError::RefNotBound {
+                ref_: r.to_string(),
+                bound,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn is_well_known(key: &str) -> bool {
+    matches!(
+        key,
+        KEY_VERSION
+            | KEY_ARCHITECTURE
+            | KEY_BOOTABLE
+            | KEY_LINUX
+            | KEY_ENDOFLIFE
+            | KEY_ENDOFLIFE_REBASE
+            | KEY_SOURCE_TITLE
+            | KEY_REF_BINDING
+            | KEY_COLLECTION_BINDING
+            | COMMIT_META_CONTAINER_CMD
+            | COMMIT_META_KEY_COMPOSEFS_DIGEST_V0
+    )
+}
+
+impl ToVariant for CommitMetadata {
+    fn to_variant(&self) -> Variant {
+        CommitMetadata::to_variant(self)
+    }
+}
+
+impl TryFrom<&Variant> for CommitMetadata {
+    type Error = CommitMetadataError;
+
+    fn try_from(v: &Variant) -> Result<Self, Self::Error> {
+        Self::from_variant(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_roundtrip_well_known_keys() {
+        let mut meta = CommitMetadata::new();
+        meta.set_version("42.1")
+            .set_bootable(true)
+            .set_linux("6.1.0-1.x86_64")
+            .set_composefs_digest(&[0xab; 32])
+            .set_ref_binding(&["exampleos/x86_64/stable", "exampleos/x86_64/testing"])
+            .set_collection_binding("org.example.Os");
+        let parsed = CommitMetadata::from_variant(&meta.to_variant()).unwrap();
+        assert_eq!(parsed, meta);
+        assert_eq!(parsed.version().unwrap().as_deref(), Some("42.1"));
+        assert!(parsed.bootable().unwrap());
+        assert_eq!(parsed.linux().unwrap().as_deref(), Some("6.1.0-1.x86_64"));
+        assert_eq!(parsed.composefs_digest().unwrap(), Some(vec![0xab; 32]));
+        assert_eq!(parsed.endoflife().unwrap(), None);
+        assert_eq!(parsed.unknown_keys().count(), 0);
+    }
+
+    #[test]
+    fn should_pass_through_unknown_keys() {
+        let dict = VariantDict::new(None);
+        dict.insert("version", "1");
+        dict.insert("rpmostree.inputhash", "abcdef");
+        dict.insert("custom.count", 3u32);
+        let meta = CommitMetadata::from_dict(&dict);
+        let unknown = meta.unknown_keys().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(unknown, vec!["custom.count", "rpmostree.inputhash"]);
+        let out = meta.to_variant_dict();
+        assert_eq!(out.lookup::<u32>("custom.count").unwrap(), Some(3),);
+        assert_eq!(
+            out.lookup::<String>("rpmostree.inputhash")
+                .unwrap()
+                .as_deref(),
+            Some("abcdef")
+        );
+    }
+
+    #[test]
+    fn should_report_invalid_type() {
+        let mut meta = CommitMetadata::new();
+        meta.insert("version", 7u32);
+        match meta.version() {
+            Err(CommitMetadataError::InvalidType {
+                key,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(key, "version");
+                assert_eq!(expected, "s");
+                assert_eq!(actual, "u");
+            }
+            o => panic!("unexpected result {o:?}"),
+        }
+    }
+
+    #[test]
+    fn should_reject_non_dictionary() {
+        assert!(matches!(
+            CommitMetadata::from_variant(&"foo".to_variant()),
+            Err(CommitMetadataError::NotADictionary(_))
+        ));
+    }
+
+    #[test]
+    fn should_validate_ref_binding() {
+        let mut meta = CommitMetadata::new();
+        meta.validate_ref_binding(&["any/ref"]).unwrap();
+        meta.set_ref_binding(&["os/stable", "os/testing"]);
+        meta.validate_ref_binding(&["os/stable"]).unwrap();
+        meta.validate_ref_binding(&["os/testing", "os/stable"])
+            .unwrap();
+        let err = meta.validate_ref_binding(&["os/devel"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "commit is not bound to ref os/devel; bound refs: os/stable, os/testing"
+        );
+    }
+}
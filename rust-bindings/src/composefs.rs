@@ -0,0 +1,266 @@
+//! Typed wrappers for composefs image generation and verification.
+
+use crate::{CommitMetadata, ObjectType, Repo, RepoFile, COMMIT_META_KEY_COMPOSEFS_DIGEST_V0};
+use gio::prelude::*;
+use std::fmt;
+#[cfg(any(feature = "v2024_7", feature = "dox"))]
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::str::FromStr;
+
+const DIGEST_LEN: usize = ffi::OSTREE_SHA256_DIGEST_LEN as usize;
+
+/// Error message used by libostree when fs-verity is required but unavailable.
+const NO_FSVERITY_MESSAGE: &str = "fsverity required but filesystem does not support it";
+
+/// Error returned by the composefs APIs.
+#[derive(Debug, thiserror::Error)]
+pub enum ComposefsError {
+    /// libostree was built without composefs support.
+    #[error("composefs is not supported in this ostree build")]
+    Unsupported,
+    /// The repository requires fs-verity, but its filesystem does not support it.
+    #[error("fs-verity is required, but the repository filesystem does not support it")]
+    NoFsVerity,
+    /// The commit does not record a composefs digest.
+    #[error("commit {0} has no composefs digest")]
+    MissingDigest(String),
+    /// A composefs digest has the wrong length.
+    #[error("invalid composefs digest length {0}, expected {DIGEST_LEN}")]
+    InvalidDigest(usize),
+    /// The digest of the generated image does not match the one recorded in the commit.
+    #[error("generated composefs digest {actual} does not match expected digest {expected}")]
+    Mismatch {
+        /// Digest recorded in the commit.
+        expected: ComposefsDigest,
+        /// Digest of the freshly generated image.
+        actual: ComposefsDigest,
+    },
+    /// Any other error.
+    #[error(transparent)]
+    Glib(glib::Error),
+}
+
+impl From<glib::Error> for ComposefsError {
+    fn from(e: glib::Error) -> Self {
+        if e.matches(gio::IOErrorEnum::NotSupported) && e.message().contains("composefs") {
+            Self::Unsupported
+        } else if e.message().contains(NO_FSVERITY_MESSAGE) {
+            Self::NoFsVerity
+        } else {
+            Self::Glib(e)
+        }
+    }
+}
+
+/// The fs-verity digest of a composefs image, as stored under
+/// [`COMMIT_META_KEY_COMPOSEFS_DIGEST_V0`] in commit metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ComposefsDigest([u8; DIGEST_LEN]);
+
+impl ComposefsDigest {
+    /// Create a digest from its binary representation.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ComposefsError> {
+        bytes
+            .try_into()
+            .map(Self)
+            .map_err(|_| ComposefsError::InvalidDigest(bytes.len()))
+    }
+
+    /// The binary digest.
+    pub fn as_bytes(&self) -> &[u8; DIGEST_LEN] {
+        &self.0
+    }
+
+    /// The digest as a hexadecimal string.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl fmt::Display for ComposefsDigest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl FromStr for ComposefsDigest {
+    type Err = ComposefsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).map_err(|_| ComposefsError::InvalidDigest(s.len() / 2))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// How fs-verity digests of the backing files are recorded in a composefs image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ComposefsVerity {
+    /// Do not record fs-verity digests.
+    Disabled,
+    /// Record fs-verity digests only for files that already have fs-verity enabled.
+    IfPresent,
+    /// Always record fs-verity digests, computing them if needed. This also checks the
+    /// generated image against the digest recorded in the commit, if any.
+    #[default]
+    Enabled,
+}
+
+impl ComposefsVerity {
+    fn to_u32(self) -> u32 {
+        match self {
+            Self::Disabled => 0,
+            Self::IfPresent => 1,
+            Self::Enabled => 2,
+        }
+    }
+}
+
+/// Options for [`Repo::checkout_composefs_with_options`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ComposefsOptions {
+    verity: ComposefsVerity,
+}
+
+impl ComposefsOptions {
+    /// Create default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how fs-verity digests are recorded.
+    pub fn verity(mut self, verity: ComposefsVerity) -> Self {
+        self.verity = verity;
+        self
+    }
+
+    /// Convert to the `a{sv}` options variant accepted by [`Repo::checkout_composefs`].
+    pub fn to_variant(&self) -> glib::Variant {
+        let dict = glib::VariantDict::new(None);
+        dict.insert("verity", self.verity.to_u32());
+        dict.end()
+    }
+}
+
+impl Repo {
+    /// Write a composefs image for `checksum` to `path`, relative to `dfd`.
+    ///
+    /// With [`ComposefsVerity::Enabled`], the generated image is checked against the digest
+    /// recorded in the commit, if any.
+    #[cfg(any(feature = "v2024_7", feature = "dox"))]
+    #[cfg_attr(feature = "dox", doc(cfg(feature = "v2024_7")))]
+    pub fn checkout_composefs_with_options<P: IsA<gio::Cancellable>>(
+        &self,
+        options: &ComposefsOptions,
+        dfd: BorrowedFd<'_>,
+        path: &str,
+        checksum: &str,
+        cancellable: Option<&P>,
+    ) -> Result<(), ComposefsError> {
+        self.checkout_composefs(
+            Some(&options.to_variant()),
+            dfd.as_raw_fd(),
+            path,
+            checksum,
+            cancellable,
+        )?;
+        Ok(())
+    }
+
+    /// Return the composefs digest recorded in the metadata of commit `checksum`.
+    pub fn composefs_digest(&self, checksum: &str) -> Result<ComposefsDigest, ComposefsError> {
+        let commit = self.load_variant(ObjectType::Commit, checksum)?;
+        let digest = CommitMetadata::from_commit(&commit)
+            .and_then(|m| m.composefs_digest())
+            .map_err(|e| {
+                ComposefsError::Glib(glib::Error::new(
+                    gio::IOErrorEnum::InvalidData,
+                    &e.to_string(),
+                ))
+            })?
+            .ok_or_else(|| ComposefsError::MissingDigest(checksum.to_string()))?;
+        ComposefsDigest::from_bytes(&digest)
+    }
+
+    /// Generate the composefs image for commit `checksum` in memory and return its digest.
+    ///
+    /// This does not require fs-verity support from the repository filesystem; the
+    /// fs-verity digests of the content objects are computed from their contents.
+    pub fn generate_composefs_digest<P: IsA<gio::Cancellable>>(
+        &self,
+        checksum: &str,
+        cancellable: Option<&P>,
+    ) -> Result<ComposefsDigest, ComposefsError> {
+        let (root, _) = self.read_commit(checksum, cancellable)?;
+        // Safety: read_commit always returns a RepoFile
+        let root = root.downcast::<RepoFile>().unwrap();
+        let dict = glib::VariantDict::new(None);
+        self.commit_add_composefs_metadata(0, &dict, &root, cancellable)?;
+        let digest = dict
+            .lookup::<Vec<u8>>(COMMIT_META_KEY_COMPOSEFS_DIGEST_V0)
+            .ok()
+            .flatten()
+            .ok_or_else(|| ComposefsError::MissingDigest(checksum.to_string()))?;
+        ComposefsDigest::from_bytes(&digest)
+    }
+
+    /// Check that the composefs digest recorded in commit `checksum` matches a freshly
+    /// generated image, returning the digest on success.
+    pub fn verify_composefs_digest<P: IsA<gio::Cancellable>>(
+        &self,
+        checksum: &str,
+        cancellable: Option<&P>,
+    ) -> Result<ComposefsDigest, ComposefsError> {
+        let expected = self.composefs_digest(checksum)?;
+        let actual = self.generate_composefs_digest(checksum, cancellable)?;
+        if expected != actual {
+            return Err(ComposefsError::Mismatch { expected, actual });
+        }
+        Ok(actual)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_digest() {
+        let hex = "ab".repeat(DIGEST_LEN);
+        let digest: ComposefsDigest = hex.parse().unwrap();
+        assert_eq!(digest.as_bytes(), &[0xab; DIGEST_LEN]);
+        assert_eq!(digest.to_string(), hex);
+        assert!(matches!(
+            ComposefsDigest::from_bytes(&[0; 4]),
+            Err(ComposefsError::InvalidDigest(4))
+        ));
+        assert!("zz".parse::<ComposefsDigest>().is_err());
+    }
+
+    #[test]
+    fn should_serialize_options() {
+        let v = ComposefsOptions::new()
+            .verity(ComposefsVerity::IfPresent)
+            .to_variant();
+        let dict = glib::VariantDict::new(Some(&v));
+        assert_eq!(dict.lookup::<u32>("verity").unwrap(), Some(1));
+    }
+
+    #[test]
+    fn should_classify_errors() {
+        let e = glib::Error::new(
+            gio::IOErrorEnum::NotSupported,
+            "composefs is not supported in this ostree build",
+        );
+        assert!(matches!(
+            ComposefsError::from(e),
+            ComposefsError::Unsupported
+        ));
+        let e = glib::Error::new(gio::IOErrorEnum::Failed, NO_FSVERITY_MESSAGE);
+        assert!(matches!(
+            ComposefsError::from(e),
+            ComposefsError::NoFsVerity
+        ));
+        let e = glib::Error::new(gio::IOErrorEnum::Failed, "other");
+        assert!(matches!(ComposefsError::from(e), ComposefsError::Glib(_)));
+    }
+}
@@ -1,3 +1,8 @@
 /// Metadata key corresponding to the Docker/OCI `CMD` verb.
 /// <https://github.com/opencontainers/image-spec/blob/main/config.md>
 pub const COMMIT_META_CONTAINER_CMD: &str = "ostree.container-cmd";
+
+/// Metadata key holding the fs-verity digest of the composefs image built from a
+/// commit, as written by [`Repo::commit_add_composefs_metadata`](crate::Repo::commit_add_composefs_metadata).
+/// GVariant type `ay`.
+pub const COMMIT_META_KEY_COMPOSEFS_DIGEST_V0: &str = "ostree.composefs.digest.v0";
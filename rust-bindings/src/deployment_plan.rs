@@ -0,0 +1,478 @@
+//! Editing the deployment list of a sysroot as a single transaction.
+//!
+//! A [`DeploymentPlan`] records changes against a snapshot of the deployment list. Nothing
+//! is written until [`DeploymentPlan::apply`], which takes the sysroot lock, checks that
+//! the deployments did not change in the meantime and writes the new list at once.
+
+use crate::sysroot::SysrootLockGuard;
+use crate::{Deployment, Sysroot, SysrootSimpleWriteDeploymentFlags, SysrootWriteDeploymentsOpts};
+use gio::prelude::*;
+
+/// A deployment in the list computed by [`DeploymentPlan::preview`].
+#[derive(Debug, Clone)]
+pub struct PlannedDeployment {
+    /// The deployment.
+    pub deployment: Deployment,
+    /// The position in the boot menu; `0` is the default entry.
+    pub index: usize,
+    /// The stateroot.
+    pub osname: String,
+    /// The commit checksum.
+    pub checksum: String,
+    /// The deployment serial.
+    pub deployserial: i32,
+    /// The checksum of the kernel and initramfs.
+    pub bootcsum: String,
+    /// The title of the boot loader entry, or `None` if no entry was written yet, as for
+    /// added deployments.
+    pub title: Option<String>,
+    /// The version of the boot loader entry, or `None` if no entry was written yet.
+    pub version: Option<String>,
+    /// Whether the deployment will be pinned.
+    pub pinned: bool,
+    /// Whether this is the booted deployment.
+    pub booted: bool,
+    /// Whether this is the staged deployment.
+    pub staged: bool,
+    /// Whether the deployment was added by the plan.
+    pub new: bool,
+}
+
+/// The result of a [`DeploymentPlan`], returned by [`DeploymentPlan::preview`].
+#[derive(Debug, Clone)]
+pub struct DeploymentPlanPreview {
+    /// The new deployment list, in boot order.
+    pub deployments: Vec<PlannedDeployment>,
+    /// The existing deployments that will be removed, either explicitly or by the
+    /// retention rules.
+    pub removed: Vec<Deployment>,
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Remove(Deployment),
+    Move(Deployment, usize),
+    Pin(Deployment, bool),
+}
+
+impl Op {
+    fn deployment(&self) -> &Deployment {
+        match self {
+            Op::Remove(d) | Op::Move(d, _) | Op::Pin(d, _) => d,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    deployment: Deployment,
+    pinned: bool,
+    new: bool,
+}
+
+/// The properties of an existing deployment used by the retention rules.
+#[derive(Debug, Clone, Copy)]
+struct Candidate<'a> {
+    osname: &'a str,
+    /// Pinned, or referenced by a change in the plan.
+    pinned: bool,
+    booted: bool,
+    merge: bool,
+}
+
+/// A set of changes to the deployment list of a [`Sysroot`].
+///
+/// Deployments added with [`add`](Self::add) are placed, and the existing deployments
+/// pruned, with the same rules as [`Sysroot::simple_write_deployment`] using the flags
+/// given to [`flags`](Self::flags). The other changes are then applied in the order they
+/// were made. Deployments referenced by a change are always retained.
+#[derive(Debug, Clone)]
+pub struct DeploymentPlan<'a> {
+    sysroot: &'a Sysroot,
+    original: Vec<Deployment>,
+    booted: Option<Deployment>,
+    merge: Option<Deployment>,
+    osname: Option<String>,
+    flags: SysrootSimpleWriteDeploymentFlags,
+    added: Vec<Deployment>,
+    ops: Vec<Op>,
+}
+
+impl<'a> DeploymentPlan<'a> {
+    /// Start a plan from the current deployments of `sysroot`, which must be loaded.
+    pub fn new(sysroot: &'a Sysroot) -> Self {
+        let booted = sysroot.booted_deployment();
+        let osname = booted.as_ref().map(|d| d.osname().to_string());
+        Self {
+            sysroot,
+            original: sysroot.deployments(),
+            merge: sysroot.merge_deployment(osname.as_deref()),
+            booted,
+            osname,
+            flags: SysrootSimpleWriteDeploymentFlags::NONE,
+            added: Vec::new(),
+            ops: Vec::new(),
+        }
+    }
+
+    /// Set the stateroot whose deployments are pruned by the retention rules. Defaults to
+    /// the stateroot of the booted deployment; if nothing is booted, deployments of all
+    /// stateroots are pruned, as when no osname is given to
+    /// [`Sysroot::simple_write_deployment`].
+    pub fn osname(mut self, osname: &str) -> Self {
+        self.osname = Some(osname.to_string());
+        self
+    }
+
+    /// Set the merge deployment, which is always retained. Defaults to
+    /// [`Sysroot::merge_deployment`] for the stateroot of the booted deployment.
+    pub fn merge_deployment(mut self, deployment: Option<&Deployment>) -> Self {
+        self.merge = deployment.cloned();
+        self
+    }
+
+    /// Set the retention and placement flags, as for
+    /// [`Sysroot::simple_write_deployment`]. Without `NO_CLEAN`, unused deployments and
+    /// objects are cleaned up when the plan is applied.
+    pub fn flags(mut self, flags: SysrootSimpleWriteDeploymentFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Add a deployment created with e.g. [`Sysroot::deploy_tree_with_options`]. It
+    /// becomes the default unless the `NOT_DEFAULT` flag is set.
+    #[allow(clippy::should_implement_trait)]
+    pub fn add(mut self, deployment: &Deployment) -> Self {
+        self.added.push(deployment.clone());
+        self
+    }
+
+    /// Remove a deployment.
+    pub fn remove(mut self, deployment: &Deployment) -> Self {
+        self.ops.push(Op::Remove(deployment.clone()));
+        self
+    }
+
+    /// Move a deployment to `index` in the boot order, or last if `index` is past the end.
+    pub fn move_to(mut self, deployment: &Deployment, index: usize) -> Self {
+        self.ops.push(Op::Move(deployment.clone(), index));
+        self
+    }
+
+    /// Make a deployment the default.
+    pub fn set_default(self, deployment: &Deployment) -> Self {
+        self.move_to(deployment, 0)
+    }
+
+    /// Pin or unpin a deployment. Pinned deployments are never pruned.
+    pub fn pin(mut self, deployment: &Deployment, pinned: bool) -> Self {
+        self.ops.push(Op::Pin(deployment.clone(), pinned));
+        self
+    }
+
+    /// Compute the resulting deployment list without changing anything.
+    pub fn preview(&self) -> Result<DeploymentPlanPreview, glib::Error> {
+        let entries = self.resolve()?;
+        let removed = self
+            .original
+            .iter()
+            .filter(|d| !entries.iter().any(|e| e.deployment.equal(d)))
+            .cloned()
+            .collect();
+        let deployments = entries
+            .into_iter()
+            .enumerate()
+            .map(|(index, e)| {
+                let bootconfig = e.deployment.bootconfig();
+                let entry_key = |key: &str| {
+                    bootconfig
+                        .as_ref()
+                        .and_then(|b| b.get(key))
+                        .map(|v| v.to_string())
+                };
+                PlannedDeployment {
+                    index,
+                    osname: e.deployment.osname().to_string(),
+                    checksum: e.deployment.csum().to_string(),
+                    deployserial: e.deployment.deployserial(),
+                    bootcsum: e.deployment.bootcsum().to_string(),
+                    title: entry_key("title"),
+                    version: entry_key("version"),
+                    pinned: e.pinned,
+                    booted: is(self.booted.as_ref(), &e.deployment),
+                    staged: e.deployment.is_staged(),
+                    new: e.new,
+                    deployment: e.deployment,
+                }
+            })
+            .collect();
+        Ok(DeploymentPlanPreview {
+            deployments,
+            removed,
+        })
+    }
+
+    /// Write the planned deployment list.
+    ///
+    /// The sysroot lock is taken for the duration of the call, so it must not already be
+    /// held by the caller. Fails without changing anything if the deployments were
+    /// modified since the plan was created, or if writing the new list fails.
+    pub fn apply<P: IsA<gio::Cancellable>>(
+        self,
+        cancellable: Option<&P>,
+    ) -> Result<DeploymentPlanPreview, glib::Error> {
+        let cancellable = cancellable.map(|c| c.as_ref());
+        self.sysroot.lock()?;
+        let _guard = SysrootLockGuard(self.sysroot);
+        self.sysroot.load_if_changed(cancellable)?;
+        let current = self.sysroot.deployments();
+        if current.len() != self.original.len()
+            || current.iter().zip(&self.original).any(|(a, b)| !a.equal(b))
+        {
+            return Err(glib::Error::new(
+                gio::IOErrorEnum::Failed,
+                "Deployments changed since the plan was created",
+            ));
+        }
+
+        let preview = self.preview()?;
+        let deployments = preview
+            .deployments
+            .iter()
+            .map(|d| d.deployment.clone())
+            .collect::<Vec<_>>();
+        let opts = SysrootWriteDeploymentsOpts {
+            do_postclean: !self
+                .flags
+                .contains(SysrootSimpleWriteDeploymentFlags::NO_CLEAN),
+        };
+        // Pins are stored in the origin files, outside the deployment list, so they are
+        // changed first and reverted if anything fails.
+        let mut changed_pins = Vec::new();
+        let result = (|| {
+            for d in &preview.deployments {
+                if d.pinned != d.deployment.is_pinned() {
+                    self.sysroot
+                        .deployment_set_pinned(&d.deployment, d.pinned)?;
+                    changed_pins.push(d);
+                }
+            }
+            self.sysroot
+                .write_deployments_with_options(&deployments, &opts, cancellable)
+        })();
+        if let Err(e) = result {
+            for d in changed_pins {
+                let _ = self.sysroot.deployment_set_pinned(&d.deployment, !d.pinned);
+            }
+            return Err(e);
+        }
+        Ok(preview)
+    }
+
+    fn resolve(&self) -> Result<Vec<Entry>, glib::Error> {
+        let mut entries = if self.added.is_empty() {
+            self.original.iter().map(|d| entry(d, false)).collect()
+        } else {
+            self.place_added()
+        };
+
+        for op in &self.ops {
+            let d = op.deployment();
+            let Some(pos) = entries.iter().position(|e| e.deployment.equal(d)) else {
+                return Err(glib::Error::new(
+                    gio::IOErrorEnum::NotFound,
+                    &format!("Deployment {} is not in the plan", describe(d)),
+                ));
+            };
+            match op {
+                Op::Remove(_) => {
+                    if is(self.booted.as_ref(), d) {
+                        return Err(glib::Error::new(
+                            gio::IOErrorEnum::InvalidArgument,
+                            "Cannot remove the booted deployment",
+                        ));
+                    }
+                    entries.remove(pos);
+                }
+                Op::Move(_, index) => {
+                    let e = entries.remove(pos);
+                    entries.insert((*index).min(entries.len()), e);
+                }
+                Op::Pin(_, pinned) => {
+                    if d.is_staged() {
+                        return Err(glib::Error::new(
+                            gio::IOErrorEnum::InvalidArgument,
+                            "Cannot pin the staged deployment",
+                        ));
+                    }
+                    entries[pos].pinned = *pinned;
+                }
+            }
+        }
+
+        if entries.iter().skip(1).any(|e| e.deployment.is_staged()) {
+            return Err(glib::Error::new(
+                gio::IOErrorEnum::InvalidArgument,
+                "The staged deployment must remain the default",
+            ));
+        }
+        Ok(entries)
+    }
+
+    /// Apply the retention rules of [`Sysroot::simple_write_deployment`] to the existing
+    /// deployments and insert the added ones.
+    fn place_added(&self) -> Vec<Entry> {
+        let osnames = self.original.iter().map(|d| d.osname()).collect::<Vec<_>>();
+        let candidates = self
+            .original
+            .iter()
+            .zip(&osnames)
+            .map(|(d, osname)| Candidate {
+                osname,
+                pinned: d.is_pinned()
+                    || self.ops.iter().any(|op| match op {
+                        Op::Remove(_) => false,
+                        _ => op.deployment().equal(d),
+                    }),
+                booted: is(self.booted.as_ref(), d),
+                merge: is(self.merge.as_ref(), d),
+            })
+            .collect::<Vec<_>>();
+        let (kept, insert_at) = retention(&candidates, self.osname.as_deref(), self.flags);
+
+        let mut entries = kept
+            .into_iter()
+            .map(|i| entry(&self.original[i], false))
+            .collect::<Vec<_>>();
+        let added = self.added.iter().map(|d| entry(d, true));
+        entries.splice(insert_at..insert_at, added);
+        entries
+    }
+}
+
+/// Compute which existing deployments are kept when adding deployments for `osname`, or
+/// for all stateroots if `None`, and the position in the kept list where the added ones
+/// go, following `ostree_sysroot_simple_write_deployment()`.
+fn retention(
+    candidates: &[Candidate],
+    osname: Option<&str>,
+    flags: SysrootSimpleWriteDeploymentFlags,
+) -> (Vec<usize>, usize) {
+    let make_default = !flags.contains(SysrootSimpleWriteDeploymentFlags::NOT_DEFAULT);
+    let retain_pending = flags.contains(SysrootSimpleWriteDeploymentFlags::RETAIN_PENDING);
+    let retain_rollback = flags.contains(SysrootSimpleWriteDeploymentFlags::RETAIN_ROLLBACK);
+    let has_booted = candidates.iter().any(|c| c.booted);
+    let has_merge = candidates.iter().any(|c| c.merge);
+    // Without a booted or merge deployment there is no crossover point between pending and
+    // rollback deployments, so everything is retained.
+    let retain = flags.contains(SysrootSimpleWriteDeploymentFlags::RETAIN)
+        || (!has_booted && !has_merge && (retain_pending || retain_rollback));
+
+    let mut kept = Vec::new();
+    let mut insert_at = make_default.then_some(0);
+    let (mut before_booted, mut before_merge) = (true, true);
+    for (i, c) in candidates.iter().enumerate() {
+        before_booted &= !c.booted;
+        before_merge &= !c.merge;
+        let passed_crossover = if has_booted {
+            !before_booted
+        } else {
+            !before_merge
+        };
+        if retain
+            || c.pinned
+            || osname.is_some_and(|o| c.osname != o)
+            || (retain_pending && !passed_crossover)
+            || c.booted
+            || c.merge
+            || (retain_rollback && passed_crossover)
+        {
+            kept.push(i);
+        }
+        if insert_at.is_none() && passed_crossover {
+            insert_at = Some(kept.len());
+        }
+    }
+    let insert_at = insert_at.unwrap_or(kept.len());
+    (kept, insert_at)
+}
+
+fn entry(deployment: &Deployment, new: bool) -> Entry {
+    Entry {
+        pinned: !new && deployment.is_pinned(),
+        deployment: deployment.clone(),
+        new,
+    }
+}
+
+fn is(a: Option<&Deployment>, b: &Deployment) -> bool {
+    a.is_some_and(|a| a.equal(b))
+}
+
+fn describe(d: &Deployment) -> String {
+    format!("{}/{}.{}", d.osname(), d.csum(), d.deployserial())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(osname: &str, booted: bool, merge: bool) -> Candidate<'_> {
+        Candidate {
+            osname,
+            pinned: false,
+            booted,
+            merge,
+        }
+    }
+
+    #[test]
+    fn should_keep_booted_and_merge_only_by_default() {
+        // pending, booted+merge, rollback
+        let c = [
+            candidate("os", false, false),
+            candidate("os", true, true),
+            candidate("os", false, false),
+        ];
+        let flags = SysrootSimpleWriteDeploymentFlags::NONE;
+        assert_eq!(retention(&c, Some("os"), flags), (vec![1], 0));
+        let flags = SysrootSimpleWriteDeploymentFlags::RETAIN_ROLLBACK;
+        assert_eq!(retention(&c, Some("os"), flags), (vec![1, 2], 0));
+        let flags = SysrootSimpleWriteDeploymentFlags::RETAIN_PENDING;
+        assert_eq!(retention(&c, Some("os"), flags), (vec![0, 1], 0));
+    }
+
+    #[test]
+    fn should_keep_pinned_and_other_osnames() {
+        let mut pinned = candidate("os", false, false);
+        pinned.pinned = true;
+        let c = [
+            candidate("os", true, true),
+            pinned,
+            candidate("other", false, false),
+            candidate("os", false, false),
+        ];
+        let flags = SysrootSimpleWriteDeploymentFlags::NONE;
+        assert_eq!(retention(&c, Some("os"), flags), (vec![0, 1, 2], 0));
+        // Without an osname, all stateroots are pruned.
+        assert_eq!(retention(&c, None, flags), (vec![0, 1], 0));
+    }
+
+    #[test]
+    fn should_insert_after_crossover_when_not_default() {
+        let c = [
+            candidate("os", false, false),
+            candidate("os", true, false),
+            candidate("os", false, true),
+        ];
+        let flags = SysrootSimpleWriteDeploymentFlags::NOT_DEFAULT
+            | SysrootSimpleWriteDeploymentFlags::RETAIN;
+        assert_eq!(retention(&c, Some("os"), flags), (vec![0, 1, 2], 2));
+
+        // Without a crossover, everything is retained and the new deployment goes last.
+        let c = [candidate("os", false, false), candidate("os", false, false)];
+        let flags = SysrootSimpleWriteDeploymentFlags::NOT_DEFAULT
+            | SysrootSimpleWriteDeploymentFlags::RETAIN_ROLLBACK;
+        assert_eq!(retention(&c, Some("os"), flags), (vec![0, 1], 2));
+    }
+}
@@ -0,0 +1,303 @@
+//! fs-verity support for repository content objects.
+//!
+//! See <https://docs.kernel.org/filesystems/fsverity.html>. libostree enables fs-verity on
+//! newly written objects according to the `[ex-integrity] fsverity` configuration key; the
+//! functions here allow querying that configuration, enabling fs-verity on objects written
+//! before it was turned on, and measuring objects.
+
+use crate::{ObjectName, ObjectType, Repo, RepoMode};
+use gio::prelude::*;
+use std::fmt;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+
+const INTEGRITY_SECTION: &str = "ex-integrity";
+const LEGACY_FSVERITY_SECTION: &str = "ex-fsverity";
+
+const FS_VERITY_HASH_ALG_SHA256: u32 = 1;
+const FS_VERITY_MAX_DIGEST_SIZE: usize = 64;
+// _IOW('f', 133, struct fsverity_enable_arg)
+const FS_IOC_ENABLE_VERITY: libc::c_ulong = 0x4080_6685;
+// _IOWR('f', 134, struct fsverity_digest)
+const FS_IOC_MEASURE_VERITY: libc::c_ulong = 0xc004_6686;
+
+/// `struct fsverity_enable_arg` from `linux/fsverity.h`.
+#[repr(C)]
+struct FsVerityEnableArg {
+    version: u32,
+    hash_algorithm: u32,
+    block_size: u32,
+    salt_size: u32,
+    salt_ptr: u64,
+    sig_size: u32,
+    reserved1: u32,
+    sig_ptr: u64,
+    reserved2: [u64; 11],
+}
+
+/// `struct fsverity_digest` from `linux/fsverity.h`, with space for the largest digest.
+#[repr(C)]
+struct FsVerityDigestArg {
+    digest_algorithm: u16,
+    digest_size: u16,
+    digest: [u8; FS_VERITY_MAX_DIGEST_SIZE],
+}
+
+/// Error returned by the fs-verity APIs.
+#[derive(Debug, thiserror::Error)]
+pub enum FsVerityError {
+    /// The filesystem containing the repository does not support fs-verity.
+    #[error("fs-verity is not supported by the repository filesystem")]
+    Unsupported,
+    /// fs-verity is not enabled on the object.
+    #[error("fs-verity is not enabled on object {0}")]
+    NotEnabled(String),
+    /// An I/O error on the given object.
+    #[error("object {0}: {1}")]
+    Io(String, #[source] std::io::Error),
+    /// Any other error.
+    #[error(transparent)]
+    Glib(#[from] glib::Error),
+}
+
+/// Whether libostree enables fs-verity on objects it writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsVerityMode {
+    /// fs-verity is not used.
+    Disabled,
+    /// fs-verity is enabled if the filesystem supports it.
+    Opportunistic,
+    /// fs-verity is required; writing objects fails if the filesystem does not support it.
+    Required,
+}
+
+/// The measured fs-verity digest of a file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FsVerityDigest {
+    algorithm: u16,
+    digest: Vec<u8>,
+}
+
+impl FsVerityDigest {
+    /// The hash algorithm, e.g. `1` for SHA-256.
+    pub fn algorithm(&self) -> u16 {
+        self.algorithm
+    }
+
+    /// The binary digest.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.digest
+    }
+
+    /// The digest as a hexadecimal string.
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.digest)
+    }
+}
+
+impl fmt::Display for FsVerityDigest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+/// Statistics returned by [`Repo::enable_fsverity_for_objects`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FsVerityStats {
+    /// Number of objects on which fs-verity was enabled.
+    pub enabled: u32,
+    /// Number of objects which already had fs-verity enabled.
+    pub already_enabled: u32,
+}
+
+fn parse_tristate(value: &str) -> Option<FsVerityMode> {
+    match value.trim() {
+        "yes" | "true" | "1" => Some(FsVerityMode::Required),
+        "maybe" => Some(FsVerityMode::Opportunistic),
+        "no" | "false" | "0" => Some(FsVerityMode::Disabled),
+        _ => None,
+    }
+}
+
+fn is_unsupported(e: &std::io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::ENOTTY) | Some(libc::EOPNOTSUPP)
+    )
+}
+
+impl Repo {
+    /// Return the fs-verity mode configured for this repository.
+    ///
+    /// This follows libostree: `[ex-integrity] fsverity` takes precedence and defaults to
+    /// opportunistic if composefs is enabled; otherwise the legacy `[ex-fsverity] required`
+    /// and `opportunistic` keys are used.
+    pub fn fsverity_mode(&self) -> Result<FsVerityMode, glib::Error> {
+        let config = self.config();
+        let tristate = |key: &str| -> Result<Option<FsVerityMode>, glib::Error> {
+            if !config.has_key(INTEGRITY_SECTION, key).unwrap_or(false) {
+                return Ok(None);
+            }
+            let value = config.string(INTEGRITY_SECTION, key)?;
+            parse_tristate(&value).map(Some).ok_or_else(|| {
+                glib::Error::new(
+                    gio::IOErrorEnum::InvalidArgument,
+                    &format!("Invalid value '{value}' for {INTEGRITY_SECTION}.{key}"),
+                )
+            })
+        };
+        let composefs = tristate("composefs")?.unwrap_or(FsVerityMode::Disabled);
+        let default = match composefs {
+            FsVerityMode::Disabled => FsVerityMode::Disabled,
+            _ => FsVerityMode::Opportunistic,
+        };
+        let mode = tristate("fsverity")?.unwrap_or(default);
+        if mode != FsVerityMode::Disabled {
+            return Ok(mode);
+        }
+        let legacy = |key: &str| {
+            config
+                .boolean(LEGACY_FSVERITY_SECTION, key)
+                .unwrap_or(false)
+        };
+        if legacy("required") {
+            Ok(FsVerityMode::Required)
+        } else if legacy("opportunistic") {
+            Ok(FsVerityMode::Opportunistic)
+        } else {
+            Ok(FsVerityMode::Disabled)
+        }
+    }
+
+    fn open_object(&self, checksum: &str, objtype: ObjectType) -> Result<OwnedFd, FsVerityError> {
+        crate::validate_checksum_string(checksum)?;
+        let compressed = objtype == ObjectType::File && self.mode() == RepoMode::Archive;
+        let path = format!(
+            "objects/{}/{}.{}{}",
+            &checksum[..2],
+            &checksum[2..],
+            crate::object_type_to_string(objtype),
+            if compressed { "z" } else { "" }
+        );
+        let repo_path = self.path().path().ok_or_else(|| {
+            glib::Error::new(
+                gio::IOErrorEnum::NotSupported,
+                "Repository has no local path",
+            )
+        })?;
+        std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(repo_path.join(path))
+            .map(OwnedFd::from)
+            .map_err(|e| FsVerityError::Io(checksum.to_string(), e))
+    }
+
+    /// Enable fs-verity on the loose content objects for which `filter` returns `true`.
+    ///
+    /// This is useful after turning on fs-verity in the configuration of an existing
+    /// repository, since libostree only enables it on newly written objects. Symbolic links
+    /// are skipped. Returns [`FsVerityError::Unsupported`] if the filesystem does not
+    /// support fs-verity.
+    pub fn enable_fsverity_for_objects<F, P>(
+        &self,
+        mut filter: F,
+        cancellable: Option<&P>,
+    ) -> Result<FsVerityStats, FsVerityError>
+    where
+        F: FnMut(&ObjectName) -> bool,
+        P: IsA<gio::Cancellable>,
+    {
+        let mut stats = FsVerityStats::default();
+        let objects = self.list_objects(ffi::OSTREE_REPO_LIST_OBJECTS_LOOSE, cancellable)?;
+        let mut names = objects
+            .keys()
+            .filter(|o| o.object_type() == ObjectType::File)
+            .collect::<Vec<_>>();
+        names.sort_by(|a, b| a.checksum().cmp(b.checksum()));
+        for name in names {
+            if let Some(c) = cancellable {
+                c.as_ref().set_error_if_cancelled()?;
+            }
+            if !filter(name) {
+                continue;
+            }
+            let checksum = name.checksum();
+            let fd = match self.open_object(checksum, ObjectType::File) {
+                // Symlinks are stored as symlinks in bare repositories.
+                Err(FsVerityError::Io(_, e)) if e.raw_os_error() == Some(libc::ELOOP) => continue,
+                r => r?,
+            };
+            let arg = FsVerityEnableArg {
+                version: 1,
+                hash_algorithm: FS_VERITY_HASH_ALG_SHA256,
+                block_size: 4096,
+                salt_size: 0,
+                salt_ptr: 0,
+                sig_size: 0,
+                reserved1: 0,
+                sig_ptr: 0,
+                reserved2: [0; 11],
+            };
+            // Safety: arg matches struct fsverity_enable_arg and outlives the call.
+            let r = unsafe { libc::ioctl(fd.as_raw_fd(), FS_IOC_ENABLE_VERITY as _, &arg) };
+            if r == 0 {
+                stats.enabled += 1;
+                continue;
+            }
+            let e = std::io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(libc::EEXIST) => stats.already_enabled += 1,
+                _ if is_unsupported(&e) => return Err(FsVerityError::Unsupported),
+                _ => return Err(FsVerityError::Io(checksum.to_string(), e)),
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Measure the fs-verity digest of the loose content object `checksum`.
+    ///
+    /// Returns [`FsVerityError::NotEnabled`] if fs-verity is not enabled on the object, and
+    /// [`FsVerityError::Unsupported`] if the filesystem does not support fs-verity.
+    pub fn object_fsverity_digest(&self, checksum: &str) -> Result<FsVerityDigest, FsVerityError> {
+        let fd = self.open_object(checksum, ObjectType::File)?;
+        let mut arg = FsVerityDigestArg {
+            digest_algorithm: 0,
+            digest_size: FS_VERITY_MAX_DIGEST_SIZE as u16,
+            digest: [0; FS_VERITY_MAX_DIGEST_SIZE],
+        };
+        // Safety: arg matches struct fsverity_digest with room for digest_size bytes.
+        let r = unsafe { libc::ioctl(fd.as_raw_fd(), FS_IOC_MEASURE_VERITY as _, &mut arg) };
+        if r < 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(match e.raw_os_error() {
+                Some(libc::ENODATA) => FsVerityError::NotEnabled(checksum.to_string()),
+                _ if is_unsupported(&e) => FsVerityError::Unsupported,
+                _ => FsVerityError::Io(checksum.to_string(), e),
+            });
+        }
+        Ok(FsVerityDigest {
+            algorithm: arg.digest_algorithm,
+            digest: arg.digest[..arg.digest_size as usize].to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_match_kernel_struct_layout() {
+        assert_eq!(std::mem::size_of::<FsVerityEnableArg>(), 128);
+        assert_eq!(std::mem::offset_of!(FsVerityDigestArg, digest), 4);
+    }
+
+    #[test]
+    fn should_parse_tristate() {
+        assert_eq!(parse_tristate("yes"), Some(FsVerityMode::Required));
+        assert_eq!(parse_tristate("maybe"), Some(FsVerityMode::Opportunistic));
+        assert_eq!(parse_tristate("false"), Some(FsVerityMode::Disabled));
+        assert_eq!(parse_tristate("sometimes"), None);
+    }
+}
@@ -0,0 +1,157 @@
+//! Walking the history of a commit, like `ostree log`.
+
+use crate::{CommitMetadata, ObjectType, Repo, RepoCommitState};
+use glib::Variant;
+
+/// A commit in the history returned by [`Repo::log`].
+#[derive(Debug, Clone)]
+pub struct LogCommit {
+    /// The commit checksum.
+    pub checksum: String,
+    /// The parent commit, which may not be present in the repository.
+    pub parent: Option<String>,
+    /// The commit subject.
+    pub subject: String,
+    /// The commit body.
+    pub body: String,
+    /// The commit time, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The commit metadata.
+    pub metadata: CommitMetadata,
+    /// Whether the commit was only partially pulled.
+    pub state: RepoCommitState,
+    /// The commit object.
+    pub commit: Variant,
+}
+
+impl LogCommit {
+    /// Whether some objects of the commit may be missing.
+    pub fn is_partial(&self) -> bool {
+        self.state.contains(RepoCommitState::PARTIAL)
+    }
+}
+
+/// An entry yielded by [`CommitLog`].
+#[derive(Debug, Clone)]
+pub enum LogEntry {
+    /// A commit present in the repository.
+    Commit(LogCommit),
+    /// The parent of the previous commit, with this checksum, is not present in the
+    /// repository, e.g. because it was pulled without history. This is always the last
+    /// entry.
+    Truncated(String),
+}
+
+/// Options for [`Repo::log`].
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    max_count: Option<usize>,
+    since: Option<u64>,
+    until: Option<u64>,
+}
+
+impl LogOptions {
+    /// Create default options: the whole history is returned.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop after `count` commits.
+    pub fn max_count(mut self, count: usize) -> Self {
+        self.max_count = Some(count);
+        self
+    }
+
+    /// Stop at the first commit older than `timestamp`, in seconds since the Unix epoch.
+    pub fn since(mut self, timestamp: u64) -> Self {
+        self.since = Some(timestamp);
+        self
+    }
+
+    /// Skip commits newer than `timestamp`, in seconds since the Unix epoch.
+    pub fn until(mut self, timestamp: u64) -> Self {
+        self.until = Some(timestamp);
+        self
+    }
+}
+
+/// Iterator over the history of a commit, returned by [`Repo::log`].
+#[derive(Debug)]
+pub struct CommitLog<'a> {
+    repo: &'a Repo,
+    next: Option<String>,
+    first: bool,
+    remaining: Option<usize>,
+    options: LogOptions,
+}
+
+impl CommitLog<'_> {
+    fn load(&mut self, checksum: String) -> Result<LogEntry, glib::Error> {
+        // Checking first avoids treating other errors as a truncated history.
+        if !self.first
+            && !self
+                .repo
+                .has_object(ObjectType::Commit, &checksum, gio::Cancellable::NONE)?
+        {
+            return Ok(LogEntry::Truncated(checksum));
+        }
+        self.first = false;
+        let (commit, state) = self.repo.load_commit(&checksum)?;
+        let metadata = CommitMetadata::from_commit(&commit)
+            .map_err(|e| glib::Error::new(gio::IOErrorEnum::InvalidData, &e.to_string()))?;
+        Ok(LogEntry::Commit(LogCommit {
+            checksum,
+            parent: crate::commit_get_parent(&commit).map(|p| p.to_string()),
+            subject: commit.child_value(3).str().unwrap().to_string(),
+            body: commit.child_value(4).str().unwrap().to_string(),
+            timestamp: u64::from_be(commit.child_value(5).get::<u64>().unwrap()),
+            metadata,
+            state,
+            commit,
+        }))
+    }
+}
+
+impl Iterator for CommitLog<'_> {
+    type Item = Result<LogEntry, glib::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.remaining == Some(0) {
+                return None;
+            }
+            let checksum = self.next.take()?;
+            let commit = match self.load(checksum) {
+                Ok(LogEntry::Commit(commit)) => commit,
+                other => return Some(other),
+            };
+            if self.options.since.is_some_and(|t| commit.timestamp < t) {
+                return None;
+            }
+            self.next.clone_from(&commit.parent);
+            if self.options.until.is_some_and(|t| commit.timestamp > t) {
+                continue;
+            }
+            self.remaining = self.remaining.map(|n| n - 1);
+            return Some(Ok(LogEntry::Commit(commit)));
+        }
+    }
+}
+
+impl Repo {
+    /// Walk the history of `rev`, a ref or commit checksum, from newest to oldest by
+    /// following the commit parents.
+    ///
+    /// The walk ends with [`LogEntry::Truncated`] if a parent commit is missing, or after
+    /// an error.
+    pub fn log(&self, rev: &str, options: LogOptions) -> Result<CommitLog<'_>, glib::Error> {
+        let checksum = self.require_rev(rev)?;
+        Ok(CommitLog {
+            repo: self,
+            next: Some(checksum.to_string()),
+            first: true,
+            remaining: options.max_count,
+            options,
+        })
+    }
+}
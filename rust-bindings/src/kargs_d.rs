@@ -0,0 +1,520 @@
+//! Declarative kernel arguments from `kargs.d` drop-in files.
+//!
+//! A drop-in is a TOML file with a `kargs` array and an optional `match-architectures`
+//! array, e.g.:
+//!
+//! ```toml
+//! kargs = ["console=ttyS0,115200n8", "mitigations=auto,nosmt"]
+//! match-architectures = ["x86_64"]
+//! ```
+//!
+//! Drop-ins are read from [`COMMIT_KARGS_DIR`] in the deployed commit and from
+//! [`LOCAL_KARGS_DIR`] in the `/etc` of the merge deployment. A local file replaces the
+//! commit file with the same name; otherwise all files are applied in order of their
+//! names, commit files first.
+//!
+//! This module requires the `kargs_d` feature.
+
+use crate::kernel_args::{format_kernel_arg, normalize_key, split_kernel_args};
+use crate::{KernelArgs, Repo};
+use gio::prelude::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The directory of the drop-ins shipped in a commit.
+pub const COMMIT_KARGS_DIR: &str = "usr/lib/ostree/kargs.d";
+/// The directory of local drop-ins, relative to a deployment.
+pub const LOCAL_KARGS_DIR: &str = "etc/ostree/kargs.d";
+
+const DROPIN_SUFFIX: &str = ".toml";
+
+/// The contents of a drop-in file.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Dropin {
+    kargs: Vec<String>,
+    match_architectures: Option<Vec<String>>,
+}
+
+/// Error returned when reading or applying kernel argument drop-ins.
+#[derive(Debug, thiserror::Error)]
+pub enum KargsError {
+    /// A drop-in file is malformed.
+    #[error("{name}:{line}: {message}")]
+    Parse {
+        /// The file name of the drop-in.
+        name: String,
+        /// The line of the error.
+        line: usize,
+        /// What is wrong.
+        message: String,
+    },
+    /// An I/O error.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// Any other error.
+    #[error(transparent)]
+    Glib(#[from] glib::Error),
+}
+
+/// Where a drop-in comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KargsSourceKind {
+    /// Shipped in the commit, in [`COMMIT_KARGS_DIR`].
+    Commit,
+    /// A local override, in [`LOCAL_KARGS_DIR`].
+    Local,
+}
+
+/// A kernel argument drop-in file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KargsSource {
+    /// The file name, e.g. `10-console.toml`.
+    pub name: String,
+    /// Where the file comes from.
+    pub kind: KargsSourceKind,
+    /// The kernel arguments, one per element.
+    pub kargs: Vec<String>,
+    /// The architectures the arguments apply to, as in [`std::env::consts::ARCH`]; all
+    /// if `None`.
+    pub match_architectures: Option<Vec<String>>,
+}
+
+impl KargsSource {
+    /// Parse the contents of a drop-in file.
+    pub fn parse(name: &str, kind: KargsSourceKind, contents: &str) -> Result<Self, KargsError> {
+        let dropin = toml::from_str::<Dropin>(contents).map_err(|e| KargsError::Parse {
+            name: name.to_string(),
+            line: e
+                .span()
+                .map_or(1, |span| contents[..span.start].matches('\n').count() + 1),
+            message: e.message().to_string(),
+        })?;
+        Ok(Self {
+            name: name.to_string(),
+            kind,
+            kargs: dropin.kargs,
+            match_architectures: dropin.match_architectures,
+        })
+    }
+
+    /// Whether the arguments apply to `arch`.
+    pub fn applies_to(&self, arch: &str) -> bool {
+        self.match_architectures
+            .as_ref()
+            .map_or(true, |archs| archs.iter().any(|a| a == arch))
+    }
+
+    /// Read all `*.toml` drop-ins in `dir`, sorted by name. A missing directory has no
+    /// drop-ins.
+    pub fn read_dir(dir: &Path, kind: KargsSourceKind) -> Result<Vec<Self>, KargsError> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut sources = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if name.ends_with(DROPIN_SUFFIX) && entry.file_type()?.is_file() {
+                let contents = std::fs::read_to_string(entry.path())?;
+                sources.push(Self::parse(&name, kind, &contents)?);
+            }
+        }
+        sources.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(sources)
+    }
+
+    /// Read all drop-ins shipped in [`COMMIT_KARGS_DIR`] of the commit `rev`, sorted by
+    /// name.
+    pub fn read_commit<P: IsA<gio::Cancellable>>(
+        repo: &Repo,
+        rev: &str,
+        cancellable: Option<&P>,
+    ) -> Result<Vec<Self>, KargsError> {
+        let (root, _) = repo.read_commit(rev, cancellable)?;
+        let dir = root.resolve_relative_path(COMMIT_KARGS_DIR);
+        if !dir.query_exists(cancellable) {
+            return Ok(Vec::new());
+        }
+        let mut sources = Vec::new();
+        let children = dir.enumerate_children(
+            "standard::name,standard::type",
+            gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+            cancellable,
+        )?;
+        for info in children {
+            let info = info?;
+            let Some(name) = info.name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if name.ends_with(DROPIN_SUFFIX) && info.file_type() == gio::FileType::Regular {
+                let (contents, _) = dir.child(&name).load_contents(cancellable)?;
+                let contents = std::str::from_utf8(&contents).map_err(|e| KargsError::Parse {
+                    name: name.clone(),
+                    line: 1,
+                    message: e.to_string(),
+                })?;
+                sources.push(Self::parse(&name, KargsSourceKind::Commit, contents)?);
+            }
+        }
+        sources.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(sources)
+    }
+}
+
+/// The set of drop-ins that apply to a deployment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KargsDropins {
+    sources: Vec<KargsSource>,
+}
+
+impl KargsDropins {
+    /// Combine the drop-ins of a commit with local ones, which replace commit drop-ins
+    /// with the same name.
+    pub fn new(commit: Vec<KargsSource>, local: Vec<KargsSource>) -> Self {
+        let mut sources = commit
+            .into_iter()
+            .filter(|c| !local.iter().any(|l| l.name == c.name))
+            .collect::<Vec<_>>();
+        sources.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut local = local;
+        local.sort_by(|a, b| a.name.cmp(&b.name));
+        sources.extend(local);
+        Self { sources }
+    }
+
+    /// The drop-ins, in the order they are applied.
+    pub fn sources(&self) -> &[KargsSource] {
+        &self.sources
+    }
+
+    /// Apply the drop-ins for `arch` to `base`.
+    ///
+    /// Arguments set by drop-ins replace all values of the same key in `base`. If
+    /// `previous` is given, arguments that it contributed are first removed from `base`,
+    /// so that arguments dropped from a drop-in do not linger after an upgrade. When
+    /// several drop-ins set different values for a key, the last one wins and a
+    /// [`KargConflict`] is reported.
+    pub fn merge(
+        &self,
+        base: &KernelArgs,
+        previous: Option<&KargsDropins>,
+        arch: &str,
+    ) -> KargsMerge {
+        // The values of each key, grouped by the drop-in that sets it.
+        let mut by_key = BTreeMap::<String, Vec<(usize, Vec<Option<String>>)>>::new();
+        let mut ordered = Vec::new();
+        for (i, source) in self.sources.iter().enumerate() {
+            if !source.applies_to(arch) {
+                continue;
+            }
+            for (key, value) in source.kargs.iter().flat_map(|a| split_kernel_args(a)) {
+                let sets = by_key.entry(normalize_key(&key)).or_default();
+                match sets.last_mut() {
+                    Some((j, values)) if *j == i => values.push(value.clone()),
+                    _ => sets.push((i, vec![value.clone()])),
+                }
+                ordered.push((i, key, value));
+            }
+        }
+
+        let conflicts = by_key
+            .iter()
+            .filter(|(_, sets)| sets.windows(2).any(|w| w[0].1 != w[1].1))
+            .map(|(key, sets)| KargConflict {
+                key: key.clone(),
+                values: sets
+                    .iter()
+                    .map(|(i, values)| (self.sources[*i].name.clone(), values.clone()))
+                    .collect(),
+                winner: self.sources[sets.last().unwrap().0].name.clone(),
+            })
+            .collect();
+
+        let stale = previous
+            .map(|p| p.merge(&KernelArgs::new(), None, arch).args)
+            .unwrap_or_default();
+        let mut args = base
+            .iter()
+            .filter(|(k, v)| {
+                !by_key.contains_key(&normalize_key(k))
+                    && !stale
+                        .iter()
+                        .any(|s| normalize_key(&s.key) == normalize_key(k) && s.value == *v)
+            })
+            .map(|(key, value)| KargOrigin {
+                key,
+                value,
+                source: None,
+            })
+            .collect::<Vec<_>>();
+        args.extend(
+            ordered
+                .into_iter()
+                .filter(|(i, key, _)| by_key[&normalize_key(key)].last().unwrap().0 == *i)
+                .map(|(i, key, value)| KargOrigin {
+                    key,
+                    value,
+                    source: Some((self.sources[i].name.clone(), self.sources[i].kind)),
+                }),
+        );
+        KargsMerge { args, conflicts }
+    }
+}
+
+/// Two or more drop-ins setting different values for the same key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KargConflict {
+    /// The key, with `-` normalized to `_`.
+    pub key: String,
+    /// The values set by each drop-in, in the order they were applied.
+    pub values: Vec<(String, Vec<Option<String>>)>,
+    /// The name of the drop-in whose values are used.
+    pub winner: String,
+}
+
+/// A kernel argument in a [`KargsMerge`], with where it comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KargOrigin {
+    /// The key.
+    pub key: String,
+    /// The value, if any.
+    pub value: Option<String>,
+    /// The name and kind of the drop-in that set the argument, or `None` if it was
+    /// already present in the base arguments.
+    pub source: Option<(String, KargsSourceKind)>,
+}
+
+impl KargOrigin {
+    /// The argument as written on the kernel command line.
+    pub fn arg(&self) -> String {
+        format_kernel_arg(&self.key, self.value.as_deref())
+    }
+}
+
+/// The result of [`KargsDropins::merge`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KargsMerge {
+    args: Vec<KargOrigin>,
+    conflicts: Vec<KargConflict>,
+}
+
+impl KargsMerge {
+    /// The merged arguments in order, with where each one comes from.
+    pub fn args(&self) -> &[KargOrigin] {
+        &self.args
+    }
+
+    /// The keys set to different values by several drop-ins.
+    pub fn conflicts(&self) -> &[KargConflict] {
+        &self.conflicts
+    }
+
+    /// The merged arguments, suitable for
+    /// [`SysrootDeployTreeOpts::override_kernel_argv`](crate::SysrootDeployTreeOpts).
+    pub fn to_argv(&self) -> Vec<String> {
+        self.args.iter().map(KargOrigin::arg).collect()
+    }
+
+    /// The merged arguments.
+    pub fn kernel_args(&self) -> KernelArgs {
+        let kargs = KernelArgs::new();
+        for arg in self.to_argv() {
+            kargs.append(&arg);
+        }
+        kargs
+    }
+}
+
+#[cfg(any(feature = "v2020_7", feature = "dox"))]
+impl crate::Sysroot {
+    /// Read the drop-ins that apply when deploying `revision`: those of the commit, and
+    /// the local ones in the `/etc` of `merge_deployment`.
+    pub fn kargs_dropins<P: IsA<gio::Cancellable>>(
+        &self,
+        revision: &str,
+        merge_deployment: Option<&crate::Deployment>,
+        cancellable: Option<&P>,
+    ) -> Result<KargsDropins, KargsError> {
+        let commit = KargsSource::read_commit(&self.repo(), revision, cancellable)?;
+        let local = match merge_deployment.and_then(|d| self.deployment_directory(d).path()) {
+            Some(dir) => KargsSource::read_dir(&dir.join(LOCAL_KARGS_DIR), KargsSourceKind::Local)?,
+            None => Vec::new(),
+        };
+        Ok(KargsDropins::new(commit, local))
+    }
+
+    /// Like [`Sysroot::deploy_tree_with_options`], with the kernel arguments computed by
+    /// applying the drop-ins of `revision` to the arguments of `merge_deployment`, or to
+    /// `opts.override_kernel_argv` if set.
+    pub fn deploy_tree_with_kargs_dropins<P: IsA<gio::Cancellable>>(
+        &self,
+        osname: Option<&str>,
+        revision: &str,
+        origin: Option<&glib::KeyFile>,
+        merge_deployment: Option<&crate::Deployment>,
+        opts: &crate::SysrootDeployTreeOpts,
+        cancellable: Option<&P>,
+    ) -> Result<(crate::Deployment, KargsMerge), KargsError> {
+        let base = match (opts.override_kernel_argv, merge_deployment) {
+            (Some(argv), _) => {
+                let kargs = KernelArgs::new();
+                kargs.append_argv(argv);
+                kargs
+            }
+            (None, Some(d)) => d.kernel_args(),
+            (None, None) => KernelArgs::new(),
+        };
+        let previous = match merge_deployment {
+            Some(d) => Some(self.kargs_dropins(&d.csum(), Some(d), cancellable)?),
+            None => None,
+        };
+        let dropins = self.kargs_dropins(revision, merge_deployment, cancellable)?;
+        let merged = dropins.merge(&base, previous.as_ref(), std::env::consts::ARCH);
+
+        let argv = merged.to_argv();
+        let argv = argv.iter().map(String::as_str).collect::<Vec<_>>();
+        let opts = crate::SysrootDeployTreeOpts {
+            override_kernel_argv: Some(&argv),
+            ..*opts
+        };
+        let deployment = self.deploy_tree_with_options(
+            osname,
+            revision,
+            origin,
+            merge_deployment,
+            Some(&opts),
+            cancellable,
+        )?;
+        Ok((deployment, merged))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(name: &str, kind: KargsSourceKind, contents: &str) -> KargsSource {
+        KargsSource::parse(name, kind, contents).unwrap()
+    }
+
+    #[test]
+    fn should_parse_dropin() {
+        let s = source(
+            "10-console.toml",
+            KargsSourceKind::Commit,
+            "# Serial console\nkargs = [\n  \"console=ttyS0,115200n8\", # primary\n  'quiet',\n]\nmatch-architectures = [\"x86_64\"]\n",
+        );
+        assert_eq!(s.kargs, ["console=ttyS0,115200n8", "quiet"]);
+        assert!(s.applies_to("x86_64"));
+        assert!(!s.applies_to("aarch64"));
+
+        let err = KargsSource::parse(
+            "a.toml",
+            KargsSourceKind::Local,
+            "kargs = [\"a\"]\nfoo = []\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a.toml:2: unknown field `foo`, expected `kargs` or `match-architectures`"
+        );
+        let err =
+            KargsSource::parse("a.toml", KargsSourceKind::Local, "\nkargs = [1]\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a.toml:2: invalid type: integer `1`, expected a string"
+        );
+        assert!(KargsSource::parse(
+            "a.toml",
+            KargsSourceKind::Local,
+            "kargs = [\"a\"]\nkargs = [\"b\"]\n"
+        )
+        .is_err());
+        assert!(KargsSource::parse("a.toml", KargsSourceKind::Local, "# empty\n").is_err());
+    }
+
+    #[test]
+    fn should_merge_with_provenance_and_conflicts() {
+        let dropins = KargsDropins::new(
+            vec![
+                source(
+                    "10-a.toml",
+                    KargsSourceKind::Commit,
+                    "kargs = [\"console=tty0\", \"console=ttyS0\", \"foo-bar=1\"]",
+                ),
+                source(
+                    "20-b.toml",
+                    KargsSourceKind::Commit,
+                    "kargs = [\"foo_bar=2\"]",
+                ),
+                source("30-c.toml", KargsSourceKind::Commit, "kargs = [\"gone\"]"),
+                source(
+                    "40-arm.toml",
+                    KargsSourceKind::Commit,
+                    "kargs = [\"arm\"]\nmatch-architectures = [\"aarch64\"]",
+                ),
+            ],
+            vec![source(
+                "30-c.toml",
+                KargsSourceKind::Local,
+                "kargs = [\"local\"]",
+            )],
+        );
+        let names = dropins
+            .sources()
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["10-a.toml", "20-b.toml", "40-arm.toml", "30-c.toml"]
+        );
+
+        let previous = KargsDropins::new(
+            vec![source(
+                "10-a.toml",
+                KargsSourceKind::Commit,
+                "kargs = [\"old=1\"]",
+            )],
+            vec![],
+        );
+        let base = KernelArgs::from_string("root=/dev/vda old=1 console=tty1 foo-bar=0");
+        let merged = dropins.merge(&base, Some(&previous), "x86_64");
+        assert_eq!(
+            merged.to_argv(),
+            [
+                "root=/dev/vda",
+                "console=tty0",
+                "console=ttyS0",
+                "foo_bar=2",
+                "local"
+            ]
+        );
+        assert_eq!(merged.args()[0].source, None);
+        assert_eq!(
+            merged.args()[3].source,
+            Some(("20-b.toml".to_string(), KargsSourceKind::Commit))
+        );
+        assert_eq!(
+            merged.args()[4].source,
+            Some(("30-c.toml".to_string(), KargsSourceKind::Local))
+        );
+        assert_eq!(
+            merged.conflicts(),
+            [KargConflict {
+                key: "foo_bar".into(),
+                values: vec![
+                    ("10-a.toml".into(), vec![Some("1".into())]),
+                    ("20-b.toml".into(), vec![Some("2".into())]),
+                ],
+                winner: "20-b.toml".into(),
+            }]
+        );
+    }
+}
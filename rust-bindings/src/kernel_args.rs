@@ -3,12 +3,19 @@ use glib::object::IsA;
 use glib::translate::*;
 #[cfg(any(feature = "v2019_3", feature = "dox"))]
 use glib::GString;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::ptr;
 
 glib::wrapper! {
     /// Kernel arguments.
-    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    ///
+    /// Two sets of kernel arguments are equal if every key has the same values in the same
+    /// order; the order of different keys does not matter, and `-` and `_` in keys are
+    /// equivalent, as for the kernel.
+    #[derive(Debug)]
     pub struct KernelArgs(Boxed<ffi::OstreeKernelArgs>);
 
     match fn {
@@ -197,6 +204,195 @@ impl KernelArgs {
     pub fn new() -> KernelArgs {
         unsafe { from_glib_full(ffi::ostree_kernel_args_new()) }
     }
+
+    /// Iterate over the arguments in order as keys and optional values, split and unquoted
+    /// like the kernel does: `"foo=bar baz"` and `foo="bar baz"` are both the key `foo`
+    /// with the value `bar baz`.
+    #[cfg(any(feature = "v2019_3", feature = "dox"))]
+    pub fn iter(&self) -> impl Iterator<Item = (String, Option<String>)> {
+        split_kernel_args(&self.to_gstring()).into_iter()
+    }
+
+    /// All values of `key`, in order; `None` for occurrences without a value.
+    #[cfg(any(feature = "v2019_3", feature = "dox"))]
+    pub fn values(&self, key: &str) -> Vec<Option<String>> {
+        let key = normalize_key(key);
+        self.iter()
+            .filter(|(k, _)| normalize_key(k) == key)
+            .map(|(_, v)| v)
+            .collect()
+    }
+
+    /// Whether `key` is present, with or without a value.
+    #[cfg(any(feature = "v2019_3", feature = "dox"))]
+    pub fn contains(&self, key: &str) -> bool {
+        let key = normalize_key(key);
+        self.iter().any(|(k, _)| normalize_key(&k) == key)
+    }
+
+    /// The changes that turn these arguments into `other`, in order of the first
+    /// occurrence of each key.
+    #[cfg(any(feature = "v2019_3", feature = "dox"))]
+    pub fn diff(&self, other: &KernelArgs) -> Vec<KernelArgChange> {
+        let (old, new) = (grouped(self.iter()), grouped(other.iter()));
+        let mut changes = Vec::new();
+        for (key, old_values) in &old {
+            match new
+                .iter()
+                .find(|(k, _)| normalize_key(k) == normalize_key(key))
+            {
+                None => changes.extend(
+                    old_values
+                        .iter()
+                        .map(|v| KernelArgChange::Removed(key.clone(), v.clone())),
+                ),
+                Some((_, new_values)) if new_values != old_values => {
+                    changes.push(KernelArgChange::Changed {
+                        key: key.clone(),
+                        old: old_values.clone(),
+                        new: new_values.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        for (key, new_values) in &new {
+            if !old
+                .iter()
+                .any(|(k, _)| normalize_key(k) == normalize_key(key))
+            {
+                changes.extend(
+                    new_values
+                        .iter()
+                        .map(|v| KernelArgChange::Added(key.clone(), v.clone())),
+                );
+            }
+        }
+        changes
+    }
+
+    /// The values of each key, with normalized keys, for comparisons.
+    #[cfg(any(feature = "v2019_3", feature = "dox"))]
+    fn normalized(&self) -> BTreeMap<String, Vec<Option<String>>> {
+        let mut map = BTreeMap::<_, Vec<_>>::new();
+        for (k, v) in self.iter() {
+            map.entry(normalize_key(&k)).or_default().push(v);
+        }
+        map
+    }
+}
+
+/// A difference between two sets of kernel arguments, returned by [`KernelArgs::diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KernelArgChange {
+    /// An argument with a key that was not present.
+    Added(String, Option<String>),
+    /// An argument with a key that is no longer present.
+    Removed(String, Option<String>),
+    /// A key present in both with different values.
+    Changed {
+        /// The key.
+        key: String,
+        /// The previous values, in order.
+        old: Vec<Option<String>>,
+        /// The new values, in order.
+        new: Vec<Option<String>>,
+    },
+}
+
+impl fmt::Display for KernelArgChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arg = |key: &str, value: &Option<String>| format_kernel_arg(key, value.as_deref());
+        let args = |key: &str, values: &[Option<String>]| {
+            values
+                .iter()
+                .map(|v| arg(key, v))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        match self {
+            KernelArgChange::Added(k, v) => write!(f, "+{}", arg(k, v)),
+            KernelArgChange::Removed(k, v) => write!(f, "-{}", arg(k, v)),
+            KernelArgChange::Changed { key, old, new } => {
+                write!(f, "~{} -> {}", args(key, old), args(key, new))
+            }
+        }
+    }
+}
+
+/// Format a key and optional value as a single argument, quoting values with whitespace.
+pub(crate) fn format_kernel_arg(key: &str, value: Option<&str>) -> String {
+    match value {
+        Some(v) if v.contains(char::is_whitespace) => format!("{key}=\"{v}\""),
+        Some(v) => format!("{key}={v}"),
+        None => key.to_string(),
+    }
+}
+
+/// Treat `-` and `_` in keys as equivalent, like the kernel.
+pub(crate) fn normalize_key(key: &str) -> String {
+    key.replace('-', "_")
+}
+
+/// The values of each key in order of first occurrence, merging equivalent keys.
+fn grouped(
+    args: impl Iterator<Item = (String, Option<String>)>,
+) -> Vec<(String, Vec<Option<String>>)> {
+    let mut groups: Vec<(String, Vec<Option<String>>)> = Vec::new();
+    for (k, v) in args {
+        let key = normalize_key(&k);
+        match groups.iter_mut().find(|(g, _)| normalize_key(g) == key) {
+            Some((_, values)) => values.push(v),
+            None => groups.push((k, vec![v])),
+        }
+    }
+    groups
+}
+
+/// Split a command line into keys and values, following `next_arg()` in the kernel's
+/// `lib/cmdline.c`.
+pub(crate) fn split_kernel_args(cmdline: &str) -> Vec<(String, Option<String>)> {
+    let mut args = Vec::new();
+    let mut rest = cmdline.trim_start();
+    while !rest.is_empty() {
+        let bytes = rest.as_bytes();
+        let quoted = bytes[0] == b'"';
+        let start = usize::from(quoted);
+        let mut in_quote = quoted;
+        let mut equals = None;
+        let mut end = start;
+        while end < bytes.len() {
+            let c = bytes[end];
+            if c.is_ascii_whitespace() && !in_quote {
+                break;
+            }
+            // Like the kernel, an `=` at the very start is not a separator.
+            if equals.is_none() && c == b'=' && end > start {
+                equals = Some(end);
+            }
+            if c == b'"' {
+                in_quote = !in_quote;
+            }
+            end += 1;
+        }
+        let mut arg_end = end;
+        if quoted && end > start && bytes[end - 1] == b'"' {
+            arg_end = end - 1;
+        }
+        let (key, value) = match equals {
+            None => (&rest[start..arg_end], None),
+            Some(eq) => {
+                let mut value = &rest[eq + 1..arg_end.max(eq + 1)];
+                if let Some(v) = value.strip_prefix('"') {
+                    value = v.strip_suffix('"').unwrap_or(v);
+                }
+                (&rest[start..eq], Some(value.to_string()))
+            }
+        };
+        args.push((key.to_string(), value));
+        rest = rest[end..].trim_start();
+    }
+    args
 }
 
 #[cfg(any(feature = "v2019_3", feature = "dox"))]
@@ -213,8 +409,66 @@ impl fmt::Display for KernelArgs {
     }
 }
 
+#[cfg(any(feature = "v2019_3", feature = "dox"))]
+impl PartialEq for KernelArgs {
+    fn eq(&self, other: &Self) -> bool {
+        self.normalized() == other.normalized()
+    }
+}
+
+#[cfg(any(feature = "v2019_3", feature = "dox"))]
+impl Eq for KernelArgs {}
+
+#[cfg(any(feature = "v2019_3", feature = "dox"))]
+impl PartialOrd for KernelArgs {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(any(feature = "v2019_3", feature = "dox"))]
+impl Ord for KernelArgs {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.normalized().cmp(&other.normalized())
+    }
+}
+
+#[cfg(any(feature = "v2019_3", feature = "dox"))]
+impl Hash for KernelArgs {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.normalized().hash(state)
+    }
+}
+
 impl<T: AsRef<str>> From<T> for KernelArgs {
     fn from(v: T) -> Self {
         KernelArgs::from_string(v.as_ref())
     }
 }
+
+impl crate::Deployment {
+    /// The kernel arguments of the deployment boot entry; empty if it has none.
+    pub fn kernel_args(&self) -> KernelArgs {
+        self.bootconfig()
+            .map(|b| b.kernel_args())
+            .unwrap_or_default()
+    }
+}
+
+impl crate::Sysroot {
+    /// Replace the kernel arguments of `deployment` in place, without writing a new
+    /// bootloader configuration. The arguments are written exactly as formatted by
+    /// [`KernelArgs`], without splitting them first.
+    pub fn deployment_set_kernel_args<P: IsA<gio::Cancellable>>(
+        &self,
+        deployment: &crate::Deployment,
+        kargs: &KernelArgs,
+        cancellable: Option<&P>,
+    ) -> Result<(), glib::Error> {
+        self.deployment_set_kargs_in_place(
+            deployment,
+            Some(kargs.to_gstring().as_str()),
+            cancellable,
+        )
+    }
+}
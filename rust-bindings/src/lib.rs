@@ -29,6 +29,16 @@ pub use crate::auto::functions::*;
 pub use crate::auto::*;
 
 // handwritten code
+mod bls_entry;
+pub use crate::bls_entry::*;
+#[cfg(any(feature = "v2020_7", feature = "dox"))]
+mod boot_counting;
+#[cfg(any(feature = "v2020_7", feature = "dox"))]
+pub use crate::boot_counting::*;
+mod bootconfig;
+mod bundle;
+pub use crate::bundle::*;
+mod callback;
 mod checksum;
 pub use crate::checksum::*;
 mod core;
@@ -41,8 +51,24 @@ pub use constants::*;
 
 #[cfg(any(feature = "v2018_6", feature = "dox"))]
 mod collection_ref;
+mod commit_builder;
+pub use crate::commit_builder::*;
+mod commit_metadata;
+pub use crate::commit_metadata::*;
+mod composefs;
+pub use crate::composefs::*;
+#[cfg(any(feature = "v2018_3", feature = "dox"))]
+mod deployment_plan;
+#[cfg(any(feature = "v2018_3", feature = "dox"))]
+pub use crate::deployment_plan::*;
+mod fsverity;
+pub use crate::fsverity::*;
 mod functions;
 pub use crate::functions::*;
+#[cfg(any(feature = "v2015_7", feature = "dox"))]
+mod history;
+#[cfg(any(feature = "v2015_7", feature = "dox"))]
+pub use crate::history::*;
 mod mutable_tree;
 #[allow(unused_imports)]
 pub use crate::mutable_tree::*;
@@ -51,13 +77,29 @@ pub use crate::mutable_tree::*;
 mod kernel_args;
 #[cfg(any(feature = "v2019_3", feature = "dox"))]
 pub use crate::kernel_args::*;
+#[cfg(all(feature = "kargs_d", any(feature = "v2019_3", feature = "dox")))]
+mod kargs_d;
+#[cfg(all(feature = "kargs_d", any(feature = "v2019_3", feature = "dox")))]
+pub use crate::kargs_d::*;
+#[cfg(any(feature = "v2016_6", feature = "dox"))]
+mod mirror;
+#[cfg(any(feature = "v2016_6", feature = "dox"))]
+pub use crate::mirror::*;
 mod object_name;
 pub use crate::object_name::*;
 mod object_details;
 pub use crate::object_details::*;
+mod origin;
+pub use crate::origin::*;
 mod deployment;
+mod pull_local;
+pub use crate::pull_local::*;
 mod repo;
 pub use crate::repo::*;
+mod repo_commit_modifier;
+pub use crate::repo_commit_modifier::*;
+mod reproducible;
+pub use crate::reproducible::*;
 #[cfg(any(feature = "v2016_8", feature = "dox"))]
 mod repo_checkout_at_options;
 #[cfg(any(feature = "v2016_8", feature = "dox"))]
@@ -68,8 +110,20 @@ pub use repo_transaction_stats::RepoTransactionStats;
 mod se_policy;
 #[allow(unused_imports)]
 pub use crate::se_policy::*;
+#[cfg(any(feature = "v2025_3", feature = "dox"))]
+mod soft_reboot;
+#[cfg(any(feature = "v2025_3", feature = "dox"))]
+pub use crate::soft_reboot::*;
 #[cfg(any(feature = "v2020_1", feature = "dox"))]
 mod commit_sizes_entry;
+mod static_delta;
+pub use crate::static_delta::*;
+mod static_delta_generate;
+pub use crate::static_delta_generate::*;
+mod static_delta_index;
+pub use crate::static_delta_index::*;
+mod static_delta_plan;
+pub use crate::static_delta_plan::*;
 #[cfg(any(feature = "v2017_4", feature = "dox"))]
 mod sysroot_write_deployments_opts;
 #[cfg(any(feature = "v2017_4", feature = "dox"))]
@@ -78,6 +132,16 @@ pub use crate::sysroot_write_deployments_opts::*;
 mod sysroot_deploy_tree_opts;
 #[cfg(any(feature = "v2020_7", feature = "dox"))]
 pub use crate::sysroot_deploy_tree_opts::SysrootDeployTreeOpts;
+#[cfg(any(feature = "v2018_3", feature = "dox"))]
+mod sysroot_status;
+#[cfg(any(feature = "v2018_3", feature = "dox"))]
+pub use crate::sysroot_status::*;
+#[cfg(any(feature = "v2018_5", feature = "dox"))]
+mod staged;
+#[cfg(any(feature = "v2018_5", feature = "dox"))]
+pub use crate::staged::*;
+mod tree_diff;
+pub use crate::tree_diff::*;
 
 // tests
 #[cfg(test)]
@@ -0,0 +1,750 @@
+//! Maintaining full mirrors of remote repositories, like `ostree pull --mirror`.
+//!
+//! A mirror pull stores commits and their objects, and the summary and its signatures when
+//! all refs are pulled, but never static deltas, which cannot be applied to an `archive`
+//! repository. With the `mirror_static_deltas` feature, the deltas and delta indexes are
+//! fetched separately from `file://`, `http://` and `https://` URLs, and checked against the
+//! SHA256 digests listed in the verified summary or an index referencing them.
+
+#[cfg(feature = "mirror_static_deltas")]
+use crate::static_delta::{delta_relpath, parse_delta_name, part_relpath};
+#[cfg(feature = "mirror_static_deltas")]
+use crate::static_delta_index::index_relpath;
+use crate::{AsyncProgress, Repo, RepoPullFlags};
+#[cfg(feature = "mirror_static_deltas")]
+use crate::{StaticDeltaIndex, StaticDeltaSuperblock};
+use gio::prelude::*;
+use glib::translate::*;
+use glib::{Variant, VariantTy};
+use std::collections::BTreeMap;
+#[cfg(feature = "mirror_static_deltas")]
+use std::collections::BTreeSet;
+#[cfg(feature = "mirror_static_deltas")]
+use std::io::{Read, Write};
+use std::path::Path;
+#[cfg(feature = "mirror_static_deltas")]
+use std::path::PathBuf;
+use std::ptr;
+#[cfg(feature = "mirror_static_deltas")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const SUMMARY: &str = "summary";
+const SUMMARY_FORMAT: &str = "(a(s(taya{sv}))a{sv})";
+#[cfg(feature = "mirror_static_deltas")]
+const SUMMARY_STATIC_DELTAS: &str = "ostree.static-deltas";
+#[cfg(feature = "mirror_static_deltas")]
+const SUMMARY_INDEXED_DELTAS: &str = "ostree.summary.indexed-deltas";
+
+/// Error returned by [`Repo::mirror`].
+#[derive(Debug, thiserror::Error)]
+pub enum MirrorError {
+    /// A file could not be fetched from the remote.
+    #[error("fetching {0}: {1}")]
+    Fetch(String, String),
+    /// A fetched file is malformed or does not match its expected digest.
+    #[error("invalid {0}: {1}")]
+    Format(String, String),
+    /// The remote uses a setting that mirroring does not support.
+    #[error("unsupported remote setting: {0}")]
+    Unsupported(String),
+    /// An error parsing a static delta.
+    #[error(transparent)]
+    StaticDelta(#[from] crate::StaticDeltaError),
+    /// An I/O error.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// Any other error.
+    #[error(transparent)]
+    Glib(#[from] glib::Error),
+}
+
+/// Options for [`Repo::mirror`].
+#[derive(Debug, Clone)]
+pub struct MirrorOptions {
+    ref_prefix: Option<String>,
+    #[cfg(feature = "mirror_static_deltas")]
+    static_deltas: bool,
+    depth: i32,
+    per_object_fsync: bool,
+    progress: Option<AsyncProgress>,
+}
+
+#[cfg_attr(not(feature = "mirror_static_deltas"), allow(clippy::derivable_impls))]
+impl Default for MirrorOptions {
+    fn default() -> Self {
+        Self {
+            ref_prefix: None,
+            #[cfg(feature = "mirror_static_deltas")]
+            static_deltas: true,
+            depth: 0,
+            per_object_fsync: false,
+            progress: None,
+        }
+    }
+}
+
+impl MirrorOptions {
+    /// Create default options: all refs are mirrored, without history, along with their
+    /// static deltas if the `mirror_static_deltas` feature is enabled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only mirror the refs starting with `prefix`, as listed in the remote summary.
+    ///
+    /// The remote summary would list refs missing from the mirror, so it is not copied;
+    /// an unsigned summary is generated instead.
+    pub fn ref_prefix(mut self, prefix: &str) -> Self {
+        self.ref_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Whether to mirror the static deltas to the mirrored commits. The default is `true`.
+    #[cfg(feature = "mirror_static_deltas")]
+    pub fn static_deltas(mut self, enabled: bool) -> Self {
+        self.static_deltas = enabled;
+        self
+    }
+
+    /// Also mirror this many parent commits, or all of them if `-1`. The default is 0.
+    pub fn depth(mut self, depth: i32) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Sync each object to disk as it is written, avoiding a single large sync at the end.
+    pub fn per_object_fsync(mut self, enabled: bool) -> Self {
+        self.per_object_fsync = enabled;
+        self
+    }
+
+    /// Report progress of the pull to `progress`.
+    pub fn progress(mut self, progress: &AsyncProgress) -> Self {
+        self.progress = Some(progress.clone());
+        self
+    }
+
+    /// Convert to the `a{sv}` options variant accepted by [`Repo::pull_with_options`],
+    /// pulling `refs`, or all refs listed in the remote summary if `None`.
+    pub fn to_variant(&self, refs: Option<&[&str]>) -> Variant {
+        let dict = glib::VariantDict::new(None);
+        dict.insert("flags", RepoPullFlags::MIRROR.bits() as i32);
+        if let Some(refs) = refs {
+            dict.insert("refs", refs);
+        }
+        dict.insert("depth", self.depth);
+        if self.per_object_fsync {
+            dict.insert("per-object-fsync", true);
+        }
+        dict.end()
+    }
+}
+
+/// A ref which was created or updated by [`Repo::mirror`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirroredRef {
+    /// The commit before mirroring, or `None` if the ref did not exist.
+    pub previous: Option<String>,
+    /// The mirrored commit.
+    pub commit: String,
+}
+
+/// The result of [`Repo::mirror`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MirrorReport {
+    /// The refs which changed, keyed by name.
+    pub changed_refs: BTreeMap<String, MirroredRef>,
+    /// The names of the static deltas which were fetched.
+    #[cfg(feature = "mirror_static_deltas")]
+    pub static_deltas: Vec<String>,
+    /// The target commits of the delta indexes which were fetched.
+    #[cfg(feature = "mirror_static_deltas")]
+    pub delta_indexes: Vec<String>,
+}
+
+/// The parts of the summary needed for mirroring.
+#[derive(Debug, Default)]
+struct Summary {
+    refs: BTreeMap<String, String>,
+    #[cfg(feature = "mirror_static_deltas")]
+    deltas: BTreeMap<String, Vec<u8>>,
+    #[cfg(feature = "mirror_static_deltas")]
+    indexed_deltas: bool,
+}
+
+impl Summary {
+    fn from_bytes(data: &[u8]) -> Result<Self, MirrorError> {
+        let invalid = |msg: &str| MirrorError::Format(SUMMARY.to_string(), msg.to_string());
+        let v = Variant::from_bytes_with_type(
+            &glib::Bytes::from(data),
+            VariantTy::new(SUMMARY_FORMAT).unwrap(),
+        );
+        let mut refs = BTreeMap::new();
+        for entry in v.child_value(0).iter() {
+            let name = entry.child_value(0).str().unwrap().to_string();
+            let checksum = entry.child_value(1).child_value(1);
+            let checksum = checksum
+                .fixed_array::<u8>()
+                .ok()
+                .filter(|c| c.len() == 32)
+                .ok_or_else(|| invalid("bad ref checksum"))?;
+            refs.insert(name, hex::encode(checksum));
+        }
+        #[cfg(feature = "mirror_static_deltas")]
+        {
+            let metadata = glib::VariantDict::new(Some(&v.child_value(1)));
+            let mut deltas = BTreeMap::new();
+            if let Some(d) = metadata.lookup_value(SUMMARY_STATIC_DELTAS, Some(VariantTy::VARDICT))
+            {
+                for entry in d.iter() {
+                    let name = entry.child_value(0).str().unwrap().to_string();
+                    let digest = entry.child_value(1).as_variant().unwrap();
+                    let digest = digest
+                        .fixed_array::<u8>()
+                        .map_err(|_| invalid("bad delta digest"))?;
+                    deltas.insert(name, digest.to_vec());
+                }
+            }
+            let indexed_deltas = metadata
+                .lookup::<bool>(SUMMARY_INDEXED_DELTAS)
+                .ok()
+                .flatten()
+                .unwrap_or(false);
+            Ok(Self {
+                refs,
+                deltas,
+                indexed_deltas,
+            })
+        }
+        #[cfg(not(feature = "mirror_static_deltas"))]
+        Ok(Self { refs })
+    }
+
+    /// Read the summary file at `path`, or return `None` if there is none.
+    fn read(path: &Path) -> Result<Option<Self>, MirrorError> {
+        match std::fs::read(path) {
+            Ok(data) => Ok(Some(Self::from_bytes(&data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// How long to wait for the remote before giving up on a request.
+#[cfg(feature = "mirror_static_deltas")]
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Where a repository is fetched from.
+#[cfg(feature = "mirror_static_deltas")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Location {
+    File(PathBuf),
+    /// The base URL, without a trailing slash.
+    Http(String),
+}
+
+#[cfg(feature = "mirror_static_deltas")]
+impl Location {
+    fn parse(url: &str) -> Result<Self, MirrorError> {
+        if let Some(path) = url.strip_prefix("file://") {
+            return Ok(Self::File(PathBuf::from(path)));
+        }
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(Self::Http(url.trim_end_matches('/').to_string()));
+        }
+        if url.starts_with("mirrorlist=") {
+            return Err(MirrorError::Unsupported(format!("mirrorlist URL {url}")));
+        }
+        Err(MirrorError::Unsupported(format!("URL {url}")))
+    }
+}
+
+/// A file fetched to a temporary file, removed on drop unless persisted.
+#[cfg(feature = "mirror_static_deltas")]
+#[derive(Debug)]
+struct Fetched {
+    path: PathBuf,
+    sha256: String,
+}
+
+#[cfg(feature = "mirror_static_deltas")]
+impl Fetched {
+    fn read(&self) -> std::io::Result<Vec<u8>> {
+        std::fs::read(&self.path)
+    }
+
+    /// Move the file to `dest`, creating parent directories.
+    fn persist(self, dest: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dest.parent().unwrap())?;
+        std::fs::rename(&self.path, dest)
+    }
+}
+
+#[cfg(feature = "mirror_static_deltas")]
+impl Drop for Fetched {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Writes to a file while computing its SHA256 digest.
+#[cfg(feature = "mirror_static_deltas")]
+struct HashingWriter {
+    file: std::fs::File,
+    checksum: glib::Checksum,
+}
+
+#[cfg(feature = "mirror_static_deltas")]
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.checksum.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Fetches the static delta files of a remote repository into temporary files, honoring
+/// the URL, `contenturl` and `proxy` options of the remote.
+#[cfg(feature = "mirror_static_deltas")]
+#[derive(Debug)]
+struct Fetcher {
+    /// Where metadata, such as delta superblocks and indexes, is fetched from.
+    metadata: Location,
+    /// Where content, such as delta parts, is fetched from.
+    content: Location,
+    agent: ureq::Agent,
+    tmpdir: PathBuf,
+}
+
+#[cfg(feature = "mirror_static_deltas")]
+impl Fetcher {
+    fn new(repo: &Repo, remote_or_url: &str) -> Result<Self, MirrorError> {
+        let tmpdir = repo.local_path()?.join("tmp");
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(TIMEOUT)
+            .timeout_read(TIMEOUT)
+            .user_agent("ostree-rs");
+        if remote_or_url.starts_with("file://") {
+            let location = Location::parse(remote_or_url)?;
+            return Ok(Self {
+                metadata: location.clone(),
+                content: location,
+                agent: agent.build(),
+                tmpdir,
+            });
+        }
+        let remote = remote_or_url;
+        let option = |name: &str| repo.remote_option(remote, name, None);
+        if let Some(metalink) = option("metalink")? {
+            return Err(MirrorError::Unsupported(format!("metalink {metalink}")));
+        }
+        if repo
+            .local_path()?
+            .join(format!("{remote}.cookies.txt"))
+            .exists()
+        {
+            return Err(MirrorError::Unsupported(format!(
+                "cookies of remote {remote}"
+            )));
+        }
+        for name in ["tls-ca-path", "tls-client-cert-path", "tls-client-key-path"] {
+            if option(name)?.is_some() {
+                return Err(MirrorError::Unsupported(name.into()));
+            }
+        }
+        if repo.remote_boolean_option(remote, "tls-permissive", false)? {
+            return Err(MirrorError::Unsupported("tls-permissive".into()));
+        }
+        let metadata = Location::parse(&repo.remote_get_url(remote)?)?;
+        let content = match option("contenturl")? {
+            Some(url) => Location::parse(&url)?,
+            None => metadata.clone(),
+        };
+        let agent = match option("proxy")? {
+            Some(proxy) => agent.proxy(
+                ureq::Proxy::new(proxy.as_str())
+                    .map_err(|e| MirrorError::Unsupported(format!("proxy {proxy}: {e}")))?,
+            ),
+            None => agent,
+        };
+        Ok(Self {
+            metadata,
+            content,
+            agent: agent.build(),
+            tmpdir,
+        })
+    }
+
+    /// Fetch the metadata file `relpath`, returning `None` if it does not exist.
+    fn fetch_metadata<P: IsA<gio::Cancellable>>(
+        &self,
+        relpath: &str,
+        cancellable: Option<&P>,
+    ) -> Result<Option<Fetched>, MirrorError> {
+        self.fetch(&self.metadata, relpath, cancellable)
+    }
+
+    /// Fetch the content file `relpath`, returning `None` if it does not exist.
+    fn fetch_content<P: IsA<gio::Cancellable>>(
+        &self,
+        relpath: &str,
+        cancellable: Option<&P>,
+    ) -> Result<Option<Fetched>, MirrorError> {
+        self.fetch(&self.content, relpath, cancellable)
+    }
+
+    fn fetch<P: IsA<gio::Cancellable>>(
+        &self,
+        location: &Location,
+        relpath: &str,
+        cancellable: Option<&P>,
+    ) -> Result<Option<Fetched>, MirrorError> {
+        let (mut out, path) = self.tempfile()?;
+        let mut fetched = Fetched {
+            path,
+            sha256: String::new(),
+        };
+        match location {
+            Location::File(root) => match std::fs::File::open(root.join(relpath)) {
+                Ok(mut file) => copy_cancellable(&mut file, &mut out, cancellable)?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            },
+            Location::Http(base) => {
+                let url = format!("{base}/{relpath}");
+                match self.agent.get(&url).call() {
+                    Ok(response) => {
+                        copy_cancellable(&mut response.into_reader(), &mut out, cancellable)?
+                    }
+                    Err(ureq::Error::Status(404 | 410, _)) => return Ok(None),
+                    Err(e) => return Err(MirrorError::Fetch(url, e.to_string())),
+                }
+            }
+        }
+        out.flush()?;
+        fetched.sha256 = out.checksum.string().unwrap().to_string();
+        Ok(Some(fetched))
+    }
+
+    fn tempfile(&self) -> std::io::Result<(HashingWriter, PathBuf)> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        std::fs::create_dir_all(&self.tmpdir)?;
+        loop {
+            let path = self.tmpdir.join(format!(
+                "mirror-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => {
+                    let checksum = glib::Checksum::new(glib::ChecksumType::Sha256).unwrap();
+                    return Ok((HashingWriter { file, checksum }, path));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Copy `input` to `out` until the end, checking for cancellation between reads.
+#[cfg(feature = "mirror_static_deltas")]
+fn copy_cancellable<P: IsA<gio::Cancellable>>(
+    input: &mut impl Read,
+    out: &mut impl Write,
+    cancellable: Option<&P>,
+) -> Result<(), MirrorError> {
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        if let Some(c) = cancellable {
+            c.as_ref().set_error_if_cancelled()?;
+        }
+        let n = match input.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        out.write_all(&buf[..n])?;
+    }
+}
+
+#[cfg(feature = "mirror_static_deltas")]
+fn sha256(data: &[u8]) -> String {
+    let mut checksum = glib::Checksum::new(glib::ChecksumType::Sha256).unwrap();
+    checksum.update(data);
+    checksum.string().unwrap().to_string()
+}
+
+impl Repo {
+    /// Mirror the remote `remote_or_url`, either the name of a configured remote or a
+    /// `file://` URL, into this repository, which should be in `archive` mode.
+    ///
+    /// Refs are stored as local refs of the same name. Besides the commits, the remote
+    /// summary and its signatures are copied when all refs are mirrored, and with the
+    /// `mirror_static_deltas` feature, the static deltas and delta indexes to the mirrored
+    /// commits are fetched. Refs which no longer exist on the remote are kept.
+    ///
+    /// The summary listing the refs and deltas is verified as configured for the remote.
+    /// Deltas are fetched honoring the `contenturl` and `proxy` options of the remote;
+    /// remotes using a metalink, mirrorlist, cookies or `tls-*` options are only supported
+    /// when static deltas are not mirrored.
+    pub fn mirror<P: IsA<gio::Cancellable>>(
+        &self,
+        remote_or_url: &str,
+        options: &MirrorOptions,
+        cancellable: Option<&P>,
+    ) -> Result<MirrorReport, MirrorError> {
+        #[cfg(feature = "mirror_static_deltas")]
+        let fetcher = options
+            .static_deltas
+            .then(|| Fetcher::new(self, remote_or_url))
+            .transpose()?;
+        let before = self.list_refs(None, cancellable)?;
+
+        #[cfg_attr(not(feature = "mirror_static_deltas"), allow(unused_variables))]
+        let (summary, refs) = match options.ref_prefix.as_deref() {
+            Some(prefix) => {
+                let fetched = self
+                    .mirror_summary(remote_or_url, cancellable)?
+                    .ok_or_else(|| MirrorError::Fetch(SUMMARY.into(), "not found".into()))?;
+                let refs = fetched
+                    .refs
+                    .keys()
+                    .filter(|r| r.starts_with(prefix))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                (Some(fetched), Some(refs))
+            }
+            None => (None, None),
+        };
+        let pull_refs = refs
+            .as_ref()
+            .map(|refs| refs.iter().map(|r| r.as_str()).collect::<Vec<_>>());
+        if pull_refs.as_ref().map_or(true, |refs| !refs.is_empty()) {
+            self.pull_with_options(
+                remote_or_url,
+                &options.to_variant(pull_refs.as_deref()),
+                options.progress.as_ref(),
+                cancellable,
+            )?;
+            if let Some(progress) = options.progress.as_ref() {
+                progress.finish();
+            }
+        }
+
+        let after = self.list_refs(None, cancellable)?;
+        let mirrored = after
+            .into_iter()
+            .filter(|(name, _)| match &refs {
+                Some(refs) => refs.contains(&name.to_string()),
+                None => true,
+            })
+            .map(|(name, commit)| (name.to_string(), commit.to_string()))
+            .collect::<BTreeMap<_, _>>();
+        let mut report = MirrorReport::default();
+        for (name, commit) in mirrored.iter() {
+            let previous = before.get(name.as_str()).map(|c| c.to_string());
+            if previous.as_ref() != Some(commit) {
+                report.changed_refs.insert(
+                    name.clone(),
+                    MirroredRef {
+                        previous,
+                        commit: commit.clone(),
+                    },
+                );
+            }
+        }
+
+        #[cfg(feature = "mirror_static_deltas")]
+        if let Some(fetcher) = fetcher {
+            let repo_path = self.local_path()?;
+            // Without a prefix, the pull copied the verified summary into this repository.
+            let summary = match summary {
+                Some(summary) => summary,
+                None => Summary::read(&repo_path.join(SUMMARY))?.unwrap_or_default(),
+            };
+            let targets = mirrored.values().collect::<BTreeSet<_>>();
+            let mut deltas = summary
+                .deltas
+                .into_iter()
+                .filter(|(name, _)| {
+                    parse_delta_name(name).is_ok_and(|(_, to)| targets.contains(&to.to_string()))
+                })
+                .collect::<BTreeMap<_, _>>();
+            if summary.indexed_deltas {
+                for to in targets.iter() {
+                    let relpath = index_relpath(to);
+                    let Some(fetched) = fetcher.fetch_metadata(&relpath, cancellable)? else {
+                        continue;
+                    };
+                    let data = fetched.read()?;
+                    let index = StaticDeltaIndex::from_bytes(to, &data)?;
+                    deltas.extend(index.deltas);
+                    let path = repo_path.join(&relpath);
+                    if std::fs::read(&path).ok().as_deref() != Some(data.as_slice()) {
+                        fetched.persist(&path)?;
+                        report.delta_indexes.push(to.to_string());
+                    }
+                }
+            }
+            for (name, digest) in deltas {
+                if let Some(c) = cancellable {
+                    c.as_ref().set_error_if_cancelled()?;
+                }
+                if self.mirror_static_delta(&fetcher, &name, &digest, cancellable)? {
+                    report.static_deltas.push(name);
+                }
+            }
+        }
+
+        if refs.is_some() {
+            self.regenerate_summary(None, cancellable)?;
+        }
+        Ok(report)
+    }
+
+    /// Fetch the summary of the mirrored repository, or `None` if it has none. For a
+    /// remote, it is verified as configured with `gpg-verify-summary` and
+    /// `sign-verify-summary`.
+    fn mirror_summary<P: IsA<gio::Cancellable>>(
+        &self,
+        remote_or_url: &str,
+        cancellable: Option<&P>,
+    ) -> Result<Option<Summary>, MirrorError> {
+        if let Some(path) = remote_or_url.strip_prefix("file://") {
+            return Summary::read(&Path::new(path).join(SUMMARY));
+        }
+        // The generated binding does not allow for a missing summary.
+        let summary = unsafe {
+            let mut summary = ptr::null_mut();
+            let mut error = ptr::null_mut();
+            let _ = ffi::ostree_repo_remote_fetch_summary_with_options(
+                self.to_glib_none().0,
+                remote_or_url.to_glib_none().0,
+                ptr::null_mut(),
+                &mut summary,
+                ptr::null_mut(),
+                cancellable.map(|c| c.as_ref()).to_glib_none().0,
+                &mut error,
+            );
+            if !error.is_null() {
+                return Err(glib::Error::from_glib_full(error).into());
+            }
+            Option::<glib::Bytes>::from_glib_full(summary)
+        };
+        summary.map(|s| Summary::from_bytes(&s)).transpose()
+    }
+
+    /// Fetch the static delta `name` unless a copy with the superblock digest `digest` is
+    /// already present, returning whether it was fetched.
+    #[cfg(feature = "mirror_static_deltas")]
+    fn mirror_static_delta<P: IsA<gio::Cancellable>>(
+        &self,
+        fetcher: &Fetcher,
+        name: &str,
+        digest: &[u8],
+        cancellable: Option<&P>,
+    ) -> Result<bool, MirrorError> {
+        let (from, to) = parse_delta_name(name)?;
+        let relpath = delta_relpath(from, to);
+        let superblock_relpath = format!("{relpath}/superblock");
+        let superblock_path = self.local_path()?.join(&superblock_relpath);
+        let digest = hex::encode(digest);
+        if let Ok(data) = std::fs::read(&superblock_path) {
+            if sha256(&data) == digest {
+                return Ok(false);
+            }
+        }
+        let fetched = fetcher
+            .fetch_metadata(&superblock_relpath, cancellable)?
+            .ok_or_else(|| MirrorError::Fetch(superblock_relpath.clone(), "not found".into()))?;
+        if fetched.sha256 != digest {
+            return Err(MirrorError::Format(
+                superblock_relpath,
+                "superblock digest mismatch".into(),
+            ));
+        }
+        let superblock = StaticDeltaSuperblock::from_bytes(&fetched.read()?)?;
+        for (i, part) in superblock.parts.iter().enumerate() {
+            if part.inline {
+                continue;
+            }
+            let part_relpath = part_relpath(from, to, i as u32);
+            let part_data = fetcher
+                .fetch_content(&part_relpath, cancellable)?
+                .ok_or_else(|| MirrorError::Fetch(part_relpath.clone(), "not found".into()))?;
+            if part_data.sha256 != part.checksum {
+                return Err(MirrorError::Format(
+                    part_relpath,
+                    "part checksum mismatch".into(),
+                ));
+            }
+            part_data.persist(&self.local_path()?.join(&part_relpath))?;
+        }
+        // Written last, so an interrupted mirror never leaves a delta with missing parts.
+        fetched.persist(&superblock_path)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "mirror_static_deltas")]
+    fn should_parse_locations() {
+        assert!(matches!(
+            Location::parse("mirrorlist=https://example.com/mirrors"),
+            Err(MirrorError::Unsupported(_))
+        ));
+        assert!(matches!(
+            Location::parse("ftp://example.com/repo"),
+            Err(MirrorError::Unsupported(_))
+        ));
+        assert_eq!(
+            Location::parse("file:///srv/repo").unwrap(),
+            Location::File(PathBuf::from("/srv/repo"))
+        );
+        assert_eq!(
+            Location::parse("https://example.com/repo/").unwrap(),
+            Location::Http("https://example.com/repo".into())
+        );
+    }
+
+    #[test]
+    fn should_parse_summary() {
+        let checksum = vec![0xabu8; 32];
+        let refs = vec![(
+            "os/stable".to_string(),
+            (0u64, checksum.clone(), glib::VariantDict::new(None).end()),
+        )];
+        let metadata = glib::VariantDict::new(None);
+        #[cfg(feature = "mirror_static_deltas")]
+        {
+            let deltas = glib::VariantDict::new(None);
+            deltas.insert_value(&hex::encode(&checksum), &vec![1u8; 32].to_variant());
+            metadata.insert_value(SUMMARY_STATIC_DELTAS, &deltas.end());
+            metadata.insert(SUMMARY_INDEXED_DELTAS, true);
+        }
+        let summary = Variant::tuple_from_iter([refs.to_variant(), metadata.end()]);
+        assert_eq!(summary.type_().as_str(), SUMMARY_FORMAT);
+
+        let summary = Summary::from_bytes(summary.data()).unwrap();
+        assert_eq!(summary.refs["os/stable"], hex::encode(&checksum));
+        #[cfg(feature = "mirror_static_deltas")]
+        {
+            assert_eq!(summary.deltas[&hex::encode(&checksum)], vec![1u8; 32]);
+            assert!(summary.indexed_deltas);
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! A typed model of deployment origin files.
+//!
+//! An origin file is a key file describing the inputs a deployment was created from; see
+//! [`Deployment::origin`]. The `origin` group is modeled by [`Origin`]; all other groups,
+//! such as `packages` and `rpmostree` written by rpm-ostree, are carried through unchanged
+//! and in order. Comments are not preserved.
+
+use crate::{Deployment, DeploymentUnlockedState, Sysroot, SysrootUpgrader};
+use gio::prelude::*;
+use glib::KeyFile;
+
+const ORIGIN_GROUP: &str = "origin";
+const KEY_REFSPEC: &str = "refspec";
+const KEY_CONTAINER_IMAGE: &str = "container-image-reference";
+const KEY_UNLOCKED: &str = "unlocked";
+const KEY_OVERRIDE_COMMIT: &str = "override-commit";
+// Mirrors `OSTREE_TRANSIENT_GROUP`, only exported behind a version feature.
+const TRANSIENT_GROUP: &str = "libostree-transient";
+
+/// Error returned when parsing or modifying an origin file.
+#[derive(Debug, thiserror::Error)]
+pub enum OriginError {
+    /// Two keys which are mutually exclusive are both set.
+    #[error("origin keys {0} and {1} are mutually exclusive")]
+    Conflict(String, String),
+    /// A key has an invalid value.
+    #[error("invalid value {value:?} for origin key {group}/{key}")]
+    InvalidValue {
+        /// The group of the key.
+        group: String,
+        /// The key.
+        key: String,
+        /// The invalid value.
+        value: String,
+    },
+    /// The `origin` group cannot be replaced as a whole.
+    #[error("group {0} cannot be set as an extra group")]
+    ReservedGroup(String),
+    /// The key file could not be parsed.
+    #[error(transparent)]
+    KeyFile(#[from] glib::Error),
+}
+
+/// What a deployment tracks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OriginSource {
+    /// An OSTree refspec, `origin/refspec`.
+    Refspec(String),
+    /// A container image reference, `origin/container-image-reference`.
+    ContainerImage(String),
+}
+
+/// The keys of a group, with their raw (escaped) values, in file order.
+pub type OriginGroup = Vec<(String, String)>;
+
+/// The parsed contents of an origin file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    /// What the deployment tracks; `None` if neither a refspec nor an image is set.
+    pub source: Option<OriginSource>,
+    /// The unlocked state recorded in `origin/unlocked`.
+    pub unlocked: Option<DeploymentUnlockedState>,
+    /// The commit pinned with `origin/override-commit`, used instead of the refspec head.
+    pub override_commit: Option<String>,
+    /// Other keys of the `origin` group.
+    origin_extra: OriginGroup,
+    /// All other groups.
+    groups: Vec<(String, OriginGroup)>,
+}
+
+fn unlocked_to_str(state: DeploymentUnlockedState) -> &'static str {
+    match state {
+        DeploymentUnlockedState::Development => "development",
+        DeploymentUnlockedState::Hotfix => "hotfix",
+        DeploymentUnlockedState::Transient => "transient",
+        _ => "none",
+    }
+}
+
+fn unlocked_from_str(s: &str) -> Option<DeploymentUnlockedState> {
+    match s {
+        "none" => Some(DeploymentUnlockedState::None),
+        "development" => Some(DeploymentUnlockedState::Development),
+        "hotfix" => Some(DeploymentUnlockedState::Hotfix),
+        "transient" => Some(DeploymentUnlockedState::Transient),
+        _ => None,
+    }
+}
+
+impl Origin {
+    fn new(source: OriginSource) -> Self {
+        Self {
+            source: Some(source),
+            unlocked: None,
+            override_commit: None,
+            origin_extra: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+
+    /// Create an origin tracking `refspec`, like [`Sysroot::origin_new_from_refspec`].
+    pub fn from_refspec(refspec: &str) -> Self {
+        Self::new(OriginSource::Refspec(refspec.to_string()))
+    }
+
+    /// Create an origin tracking the container image `reference`.
+    pub fn from_container_image(reference: &str) -> Self {
+        Self::new(OriginSource::ContainerImage(reference.to_string()))
+    }
+
+    /// Parse an origin key file.
+    pub fn from_keyfile(keyfile: &KeyFile) -> Result<Self, OriginError> {
+        let get = |key: &str| -> Result<Option<String>, OriginError> {
+            match keyfile.string(ORIGIN_GROUP, key) {
+                Ok(v) => Ok(Some(v.to_string())),
+                Err(e) if e.matches(glib::KeyFileError::KeyNotFound) => Ok(None),
+                Err(e) if e.matches(glib::KeyFileError::GroupNotFound) => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        };
+        let invalid = |key: &str, value: String| OriginError::InvalidValue {
+            group: ORIGIN_GROUP.to_string(),
+            key: key.to_string(),
+            value,
+        };
+        let source = match (get(KEY_REFSPEC)?, get(KEY_CONTAINER_IMAGE)?) {
+            (Some(_), Some(_)) => {
+                return Err(OriginError::Conflict(
+                    KEY_REFSPEC.to_string(),
+                    KEY_CONTAINER_IMAGE.to_string(),
+                ))
+            }
+            (Some(refspec), None) => Some(OriginSource::Refspec(refspec)),
+            (None, Some(image)) => Some(OriginSource::ContainerImage(image)),
+            (None, None) => None,
+        };
+        let unlocked = get(KEY_UNLOCKED)?
+            .map(|v| unlocked_from_str(&v).ok_or_else(|| invalid(KEY_UNLOCKED, v)))
+            .transpose()?;
+        let override_commit = get(KEY_OVERRIDE_COMMIT)?
+            .map(|v| match crate::validate_checksum_string(&v) {
+                Ok(()) => Ok(v),
+                Err(_) => Err(invalid(KEY_OVERRIDE_COMMIT, v)),
+            })
+            .transpose()?;
+
+        let read_group = |group: &str| -> Result<OriginGroup, OriginError> {
+            keyfile
+                .keys(group)?
+                .iter()
+                .map(|k| Ok((k.to_string(), keyfile.value(group, k)?.to_string())))
+                .collect()
+        };
+        let mut origin_extra = Vec::new();
+        let mut groups = Vec::new();
+        for group in keyfile.groups().iter() {
+            if group == ORIGIN_GROUP {
+                origin_extra = read_group(group)?
+                    .into_iter()
+                    .filter(|(k, _)| {
+                        ![
+                            KEY_REFSPEC,
+                            KEY_CONTAINER_IMAGE,
+                            KEY_UNLOCKED,
+                            KEY_OVERRIDE_COMMIT,
+                        ]
+                        .contains(&k.as_str())
+                    })
+                    .collect();
+            } else {
+                groups.push((group.to_string(), read_group(group)?));
+            }
+        }
+        Ok(Self {
+            source,
+            unlocked,
+            override_commit,
+            origin_extra,
+            groups,
+        })
+    }
+
+    /// Parse the contents of an origin file.
+    pub fn parse(data: &str) -> Result<Self, OriginError> {
+        let keyfile = KeyFile::new();
+        keyfile.load_from_data(data, glib::KeyFileFlags::NONE)?;
+        Self::from_keyfile(&keyfile)
+    }
+
+    /// Serialize to a key file, suitable for [`Sysroot::write_origin_file`] or
+    /// [`Sysroot::deploy_tree`].
+    pub fn to_keyfile(&self) -> KeyFile {
+        let keyfile = KeyFile::new();
+        match &self.source {
+            Some(OriginSource::Refspec(r)) => keyfile.set_string(ORIGIN_GROUP, KEY_REFSPEC, r),
+            Some(OriginSource::ContainerImage(i)) => {
+                keyfile.set_string(ORIGIN_GROUP, KEY_CONTAINER_IMAGE, i)
+            }
+            None => {}
+        }
+        for (k, v) in self.origin_extra.iter() {
+            keyfile.set_value(ORIGIN_GROUP, k, v);
+        }
+        if let Some(unlocked) = self.unlocked {
+            keyfile.set_string(ORIGIN_GROUP, KEY_UNLOCKED, unlocked_to_str(unlocked));
+        }
+        if let Some(commit) = self.override_commit.as_deref() {
+            keyfile.set_string(ORIGIN_GROUP, KEY_OVERRIDE_COMMIT, commit);
+        }
+        for (group, entries) in self.groups.iter() {
+            for (k, v) in entries {
+                keyfile.set_value(group, k, v);
+            }
+        }
+        keyfile
+    }
+
+    /// The refspec tracked by this origin, if any.
+    pub fn refspec(&self) -> Option<&str> {
+        match &self.source {
+            Some(OriginSource::Refspec(r)) => Some(r),
+            _ => None,
+        }
+    }
+
+    /// The container image tracked by this origin, if any.
+    pub fn container_image(&self) -> Option<&str> {
+        match &self.source {
+            Some(OriginSource::ContainerImage(i)) => Some(i),
+            _ => None,
+        }
+    }
+
+    /// Other keys of the `origin` group, with their raw (escaped) values, in file order.
+    pub fn origin_extra(&self) -> &OriginGroup {
+        &self.origin_extra
+    }
+
+    /// The keys of `group`, which must not be `origin`.
+    pub fn group(&self, group: &str) -> Option<&OriginGroup> {
+        self.groups.iter().find(|(g, _)| g == group).map(|(_, e)| e)
+    }
+
+    /// Replace the keys of `group`, appending it if new. The `origin` group is rejected with
+    /// [`OriginError::ReservedGroup`]; use the typed fields instead.
+    pub fn set_group(&mut self, group: &str, entries: OriginGroup) -> Result<(), OriginError> {
+        if group == ORIGIN_GROUP {
+            return Err(OriginError::ReservedGroup(group.to_string()));
+        }
+        match self.groups.iter_mut().find(|(g, _)| g == group) {
+            Some((_, e)) => *e = entries,
+            None => self.groups.push((group.to_string(), entries)),
+        }
+        Ok(())
+    }
+
+    /// Remove `group`, returning its keys.
+    pub fn remove_group(&mut self, group: &str) -> Option<OriginGroup> {
+        let i = self.groups.iter().position(|(g, _)| g == group)?;
+        Some(self.groups.remove(i).1)
+    }
+
+    /// The names of all groups other than `origin`, in order.
+    pub fn group_names(&self) -> impl Iterator<Item = &str> {
+        self.groups.iter().map(|(g, _)| g.as_str())
+    }
+
+    /// The layered packages group written by rpm-ostree.
+    pub fn packages(&self) -> Option<&OriginGroup> {
+        self.group("packages")
+    }
+
+    /// The `rpmostree` group written by rpm-ostree.
+    pub fn rpmostree(&self) -> Option<&OriginGroup> {
+        self.group("rpmostree")
+    }
+
+    /// The `libostree-transient` group.
+    pub fn transient(&self) -> Option<&OriginGroup> {
+        self.group(TRANSIENT_GROUP)
+    }
+
+    /// Remove the state which should not be carried across upgrades: the
+    /// `libostree-transient` group, `origin/unlocked` and `origin/override-commit`, like
+    /// [`Deployment::origin_remove_transient_state`].
+    pub fn remove_transient_state(&mut self) {
+        self.remove_group(TRANSIENT_GROUP);
+        self.unlocked = None;
+        self.override_commit = None;
+    }
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.to_keyfile().to_data())
+    }
+}
+
+impl Deployment {
+    /// Parse the origin of this deployment, if it has one.
+    pub fn parsed_origin(&self) -> Result<Option<Origin>, OriginError> {
+        self.origin().map(|o| Origin::from_keyfile(&o)).transpose()
+    }
+}
+
+impl SysrootUpgrader {
+    /// Parse the origin of the deployment being upgraded, if it has one.
+    pub fn parsed_origin(&self) -> Result<Option<Origin>, OriginError> {
+        self.origin().map(|o| Origin::from_keyfile(&o)).transpose()
+    }
+
+    /// Replace the origin of the deployment being upgraded.
+    pub fn set_parsed_origin<P: IsA<gio::Cancellable>>(
+        &self,
+        origin: &Origin,
+        cancellable: Option<&P>,
+    ) -> Result<(), glib::Error> {
+        self.set_origin(Some(&origin.to_keyfile()), cancellable)
+    }
+}
+
+impl Sysroot {
+    /// Write `origin` as the origin file of `deployment`.
+    pub fn write_parsed_origin<P: IsA<gio::Cancellable>>(
+        &self,
+        deployment: &Deployment,
+        origin: &Origin,
+        cancellable: Option<&P>,
+    ) -> Result<(), glib::Error> {
+        self.write_origin_file(deployment, Some(&origin.to_keyfile()), cancellable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMIT: &str = "0e9b0ad7ce5a4c9e4e2fbc7f1e1b5e6f0c2a1d3b4e5f60718293a4b5c6d7e8f9";
+
+    #[test]
+    fn should_round_trip_origin() {
+        let data = format!(
+            "[origin]\nrefspec=fedora:fedora/x86_64/silverblue\ncustom=a\\sb\n\
+             unlocked=hotfix\noverride-commit={COMMIT}\n\n\
+             [packages]\nrequested=vim;htop;\n\n\
+             [libostree-transient]\nfoo=bar\n\n\
+             [rpmostree]\nregenerate-initramfs=true\n"
+        );
+        let origin = Origin::parse(&data).unwrap();
+        assert_eq!(origin.refspec(), Some("fedora:fedora/x86_64/silverblue"));
+        assert_eq!(origin.unlocked, Some(DeploymentUnlockedState::Hotfix));
+        assert_eq!(origin.override_commit.as_deref(), Some(COMMIT));
+        assert_eq!(
+            origin.packages().unwrap(),
+            &vec![("requested".to_string(), "vim;htop;".to_string())]
+        );
+        assert_eq!(
+            origin.group_names().collect::<Vec<_>>(),
+            ["packages", "libostree-transient", "rpmostree"]
+        );
+        assert_eq!(
+            origin.origin_extra(),
+            &vec![("custom".to_string(), "a\\sb".to_string())]
+        );
+        let reparsed = Origin::parse(&origin.to_string()).unwrap();
+        assert_eq!(reparsed, origin);
+        assert_eq!(
+            reparsed.to_keyfile().string("origin", "custom").unwrap(),
+            "a b"
+        );
+
+        let mut cleaned = origin.clone();
+        cleaned.remove_transient_state();
+        assert!(cleaned.transient().is_none());
+        assert!(cleaned.unlocked.is_none() && cleaned.override_commit.is_none());
+        #[cfg(feature = "v2018_3")]
+        {
+            let keyfile = origin.to_keyfile();
+            Deployment::origin_remove_transient_state(&keyfile);
+            assert_eq!(Origin::from_keyfile(&keyfile).unwrap(), cleaned);
+        }
+    }
+
+    #[test]
+    fn should_reject_invalid_origin() {
+        assert!(matches!(
+            Origin::parse("[origin]\nrefspec=a\ncontainer-image-reference=b\n"),
+            Err(OriginError::Conflict(_, _))
+        ));
+        assert!(matches!(
+            Origin::parse("[origin]\nrefspec=a\nunlocked=maybe\n"),
+            Err(OriginError::InvalidValue { key, .. }) if key == "unlocked"
+        ));
+        assert!(matches!(
+            Origin::parse("[origin]\nrefspec=a\noverride-commit=abc\n"),
+            Err(OriginError::InvalidValue { key, .. }) if key == "override-commit"
+        ));
+        assert!(matches!(
+            Origin::parse("not a key file"),
+            Err(OriginError::KeyFile(_))
+        ));
+
+        let mut origin = Origin::from_container_image("ostree-unverified-registry:quay.io/a/b");
+        assert!(matches!(
+            origin.set_group("origin", vec![("refspec".into(), "a".into())]),
+            Err(OriginError::ReservedGroup(_))
+        ));
+        origin
+            .set_group("packages", vec![("requested".into(), "vim;".into())])
+            .unwrap();
+        assert_eq!(origin.group_names().collect::<Vec<_>>(), ["packages"]);
+        assert_eq!(
+            Origin::parse(&origin.to_string())
+                .unwrap()
+                .container_image(),
+            Some("ostree-unverified-registry:quay.io/a/b")
+        );
+    }
+}
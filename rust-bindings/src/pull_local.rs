@@ -0,0 +1,266 @@
+//! Pulling between local repositories, like `ostree pull-local`.
+
+use crate::{AsyncProgress, Repo, RepoPullFlags};
+use gio::prelude::*;
+use std::collections::BTreeMap;
+
+/// Options for [`Repo::pull_local`].
+#[derive(Debug, Clone)]
+pub struct PullLocalOptions {
+    flags: RepoPullFlags,
+    remote: Option<String>,
+    gpg_verify: bool,
+    gpg_verify_summary: bool,
+    sign_verify: bool,
+    disable_verify_bindings: bool,
+    depth: i32,
+    disable_static_deltas: bool,
+    require_static_deltas: bool,
+    per_object_fsync: bool,
+    progress: Option<AsyncProgress>,
+}
+
+impl Default for PullLocalOptions {
+    fn default() -> Self {
+        Self {
+            flags: RepoPullFlags::NONE,
+            remote: None,
+            gpg_verify: false,
+            gpg_verify_summary: false,
+            sign_verify: false,
+            disable_verify_bindings: false,
+            depth: 0,
+            disable_static_deltas: false,
+            require_static_deltas: false,
+            per_object_fsync: false,
+            progress: None,
+        }
+    }
+}
+
+impl PullLocalOptions {
+    /// Create default options: objects are trusted, no verification is done and no parent
+    /// commits are pulled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Verify the checksums of all imported objects instead of trusting the source
+    /// repository, as done by `ostree_repo_import_object_from_with_trust` with `trusted`
+    /// set to `false`. This is always the case for HTTP pulls.
+    pub fn untrusted(mut self, untrusted: bool) -> Self {
+        self.flags.set(RepoPullFlags::UNTRUSTED, untrusted);
+        self
+    }
+
+    /// Reject regular files with a mode outside of 0775, i.e. setuid or world-writable.
+    pub fn bareuseronly_files(mut self, enabled: bool) -> Self {
+        self.flags.set(RepoPullFlags::BAREUSERONLY_FILES, enabled);
+        self
+    }
+
+    /// Only pull the commit objects and their detached metadata.
+    pub fn commit_metadata_only(mut self, enabled: bool) -> Self {
+        self.flags.set(RepoPullFlags::COMMIT_ONLY, enabled);
+        self
+    }
+
+    /// Write the refs as remote refs of `remote`, and use its configuration for GPG and
+    /// signature verification.
+    pub fn remote(mut self, remote: &str) -> Self {
+        self.remote = Some(remote.to_string());
+        self
+    }
+
+    /// Verify commits with the GPG keys of the remote set with [`Self::remote`].
+    pub fn gpg_verify(mut self, verify: bool) -> Self {
+        self.gpg_verify = verify;
+        self
+    }
+
+    /// Verify the summary with the GPG keys of the remote set with [`Self::remote`].
+    pub fn gpg_verify_summary(mut self, verify: bool) -> Self {
+        self.gpg_verify_summary = verify;
+        self
+    }
+
+    /// Verify commits and the summary with the signature keys (e.g. ed25519) configured for
+    /// the remote set with [`Self::remote`]. Local pulls do not verify signatures by default.
+    pub fn sign_verify(mut self, verify: bool) -> Self {
+        self.sign_verify = verify;
+        self
+    }
+
+    /// Do not verify that commits are bound to the refs they are pulled for.
+    pub fn disable_verify_bindings(mut self, disable: bool) -> Self {
+        self.disable_verify_bindings = disable;
+        self
+    }
+
+    /// Also pull this many parent commits, or all of them if `-1`. The default is 0.
+    pub fn depth(mut self, depth: i32) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Do not use static deltas.
+    pub fn disable_static_deltas(mut self, disable: bool) -> Self {
+        self.disable_static_deltas = disable;
+        self
+    }
+
+    /// Fail unless static deltas are available for all refs.
+    pub fn require_static_deltas(mut self, require: bool) -> Self {
+        self.require_static_deltas = require;
+        self
+    }
+
+    /// Sync each object to disk as it is written, avoiding a single large sync at the end.
+    pub fn per_object_fsync(mut self, enabled: bool) -> Self {
+        self.per_object_fsync = enabled;
+        self
+    }
+
+    /// Report progress to `progress`.
+    pub fn progress(mut self, progress: &AsyncProgress) -> Self {
+        self.progress = Some(progress.clone());
+        self
+    }
+
+    fn validate(&self) -> Result<(), glib::Error> {
+        let verify = self.gpg_verify || self.gpg_verify_summary || self.sign_verify;
+        if verify && self.remote.is_none() {
+            return Err(glib::Error::new(
+                gio::IOErrorEnum::InvalidArgument,
+                "Verification of a local pull requires a remote name",
+            ));
+        }
+        if self.disable_static_deltas && self.require_static_deltas {
+            return Err(glib::Error::new(
+                gio::IOErrorEnum::InvalidArgument,
+                "Static deltas cannot be both disabled and required",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Convert to the `a{sv}` options variant accepted by [`Repo::pull_with_options`],
+    /// pulling `refs`.
+    pub fn to_variant(&self, refs: &[&str]) -> glib::Variant {
+        let dict = glib::VariantDict::new(None);
+        dict.insert("flags", self.flags.bits() as i32);
+        dict.insert("refs", refs);
+        if let Some(remote) = self.remote.as_deref() {
+            dict.insert("override-remote-name", remote);
+        }
+        if self.gpg_verify {
+            dict.insert("gpg-verify", true);
+        }
+        if self.gpg_verify_summary {
+            dict.insert("gpg-verify-summary", true);
+        }
+        dict.insert("disable-sign-verify", !self.sign_verify);
+        dict.insert("disable-sign-verify-summary", !self.sign_verify);
+        dict.insert("disable-verify-bindings", self.disable_verify_bindings);
+        dict.insert("depth", self.depth);
+        dict.insert("disable-static-deltas", self.disable_static_deltas);
+        dict.insert("require-static-deltas", self.require_static_deltas);
+        if self.per_object_fsync {
+            dict.insert("per-object-fsync", true);
+        }
+        dict.end()
+    }
+}
+
+impl Repo {
+    /// Pull `refs` from the local repository `source`, or all of its refs if empty.
+    ///
+    /// Returns the pulled refs, as named in this repository, mapped to their new commits.
+    pub fn pull_local<P: IsA<gio::Cancellable>>(
+        &self,
+        source: &Repo,
+        refs: &[&str],
+        options: &PullLocalOptions,
+        cancellable: Option<&P>,
+    ) -> Result<BTreeMap<String, String>, glib::Error> {
+        options.validate()?;
+        let all_refs;
+        let refs = if refs.is_empty() {
+            all_refs = source
+                .list_refs(None, cancellable)?
+                .into_keys()
+                .collect::<Vec<_>>();
+            all_refs.iter().map(|r| r.as_str()).collect::<Vec<_>>()
+        } else {
+            refs.to_vec()
+        };
+        let url = source.path().uri();
+        self.pull_with_options(
+            &url,
+            &options.to_variant(&refs),
+            options.progress.as_ref(),
+            cancellable,
+        )?;
+        if let Some(progress) = options.progress.as_ref() {
+            progress.finish();
+        }
+        let mut ret = BTreeMap::new();
+        for r in refs {
+            let name = match options.remote.as_deref() {
+                Some(remote) => format!("{remote}:{r}"),
+                None => r.to_string(),
+            };
+            if let Some(rev) = self.resolve_rev(&name, true)? {
+                ret.insert(name, rev.to_string());
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_serialize_options() {
+        let v = PullLocalOptions::new()
+            .untrusted(true)
+            .commit_metadata_only(true)
+            .remote("origin")
+            .sign_verify(true)
+            .depth(-1)
+            .to_variant(&["os/stable"]);
+        let dict = glib::VariantDict::new(Some(&v));
+        let flags = RepoPullFlags::UNTRUSTED | RepoPullFlags::COMMIT_ONLY;
+        assert_eq!(
+            dict.lookup::<i32>("flags").unwrap(),
+            Some(flags.bits() as i32)
+        );
+        assert_eq!(
+            dict.lookup::<Vec<String>>("refs").unwrap(),
+            Some(vec!["os/stable".to_string()])
+        );
+        assert_eq!(
+            dict.lookup::<String>("override-remote-name").unwrap(),
+            Some("origin".to_string())
+        );
+        assert_eq!(
+            dict.lookup::<bool>("disable-sign-verify").unwrap(),
+            Some(false)
+        );
+        assert_eq!(dict.lookup::<i32>("depth").unwrap(), Some(-1));
+        assert_eq!(dict.lookup::<bool>("gpg-verify").unwrap(), None);
+    }
+
+    #[test]
+    fn should_require_remote_for_verification() {
+        assert!(PullLocalOptions::new().validate().is_ok());
+        assert!(PullLocalOptions::new().gpg_verify(true).validate().is_err());
+        assert!(PullLocalOptions::new()
+            .sign_verify(true)
+            .remote("origin")
+            .validate()
+            .is_ok());
+    }
+}
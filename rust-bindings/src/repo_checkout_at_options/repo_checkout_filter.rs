@@ -1,11 +1,9 @@
+use crate::callback::abort_on_panic;
 use crate::{Repo, RepoCheckoutFilterResult};
 use glib::ffi::gpointer;
 use glib::translate::*;
 use libc::c_char;
-use std::any::Any;
-use std::panic::catch_unwind;
 use std::path::{Path, PathBuf};
-use std::process::abort;
 
 /// A filter callback to decide which files to checkout from a [Repo](struct.Repo.html). The
 /// function is called for every directory and file in the dirtree.
@@ -94,39 +92,7 @@ pub(super) unsafe extern "C" fn filter_trampoline_unwindsafe(
     stat: *mut libc::stat,
     user_data: gpointer,
 ) -> ffi::OstreeRepoCheckoutFilterResult {
-    // Unwinding across an FFI boundary is Undefined Behavior and we have no other way to communicate
-    // the error. We abort() safely to avoid further problems.
-    let result = catch_unwind(move || filter_trampoline(repo, path, stat, user_data));
-    result.unwrap_or_else(|panic| {
-        print_panic(panic);
-        abort()
-    })
-}
-
-/// Print a panic message and the value to stderr, if we can.
-///
-/// If the panic value is either `&str` or `String`, we print it. Otherwise, we don't.
-fn print_panic(panic: Box<dyn Any>) {
-    use std::io::Write;
-    let stderr = std::io::stderr();
-    let mut stderr = stderr.lock();
-    // Directly write to stderr instead of eprintln!() as that function panics
-    // if writing fails, which would involve a double panic which we don't want.
-    let _ = stderr.write_all(
-        r#"A Rust callback invoked by C code panicked.
-Unwinding across FFI boundaries is Undefined Behavior so abort() will be called."#
-            .as_bytes(),
-    );
-    let msg = {
-        if let Some(s) = panic.as_ref().downcast_ref::<&str>() {
-            s
-        } else if let Some(s) = panic.as_ref().downcast_ref::<String>() {
-            s
-        } else {
-            "(non-string panic value)"
-        }
-    };
-    let _ = stderr.write_all(msg.as_bytes());
+    abort_on_panic(move || filter_trampoline(repo, path, stat, user_data))
 }
 
 #[cfg(test)]
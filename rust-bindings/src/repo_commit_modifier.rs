@@ -0,0 +1,340 @@
+use crate::callback::abort_on_panic;
+use crate::{Repo, RepoCommitFilterResult, RepoCommitModifier, RepoCommitModifierFlags};
+use glib::ffi::gpointer;
+use glib::prelude::*;
+use glib::translate::*;
+use libc::c_char;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Extended attributes, as stored in an OSTree content object: a list of (name, value) pairs.
+pub type Xattrs = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Metadata of a file or directory being committed, which may be changed by a [`CommitFilter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitFileMetadata {
+    file_type: gio::FileType,
+    /// The user ID.
+    pub uid: u32,
+    /// The group ID.
+    pub gid: u32,
+    /// The Unix mode, including file type flag.
+    pub mode: u32,
+    /// Extended attributes to commit.
+    ///
+    /// This is `None` on entry unless the modifier was created with an xattr source, see
+    /// [`RepoCommitModifier::new_with_filter`]. If it is still `None` after the filter returns,
+    /// libostree commits the xattrs it reads from the source as usual; otherwise the given
+    /// list replaces them.
+    pub xattrs: Option<Xattrs>,
+}
+
+impl CommitFileMetadata {
+    pub(crate) fn from_file_info(file_info: &gio::FileInfo, xattrs: Option<Xattrs>) -> Self {
+        Self {
+            file_type: file_info.file_type(),
+            uid: file_info.attribute_uint32("unix::uid"),
+            gid: file_info.attribute_uint32("unix::gid"),
+            mode: file_info.attribute_uint32("unix::mode"),
+            xattrs,
+        }
+    }
+
+    fn apply_to_file_info(&self, file_info: &gio::FileInfo) {
+        file_info.set_attribute_uint32("unix::uid", self.uid);
+        file_info.set_attribute_uint32("unix::gid", self.gid);
+        file_info.set_attribute_uint32("unix::mode", self.mode);
+    }
+
+    /// The type of the file; this cannot be changed.
+    pub fn file_type(&self) -> gio::FileType {
+        self.file_type
+    }
+
+    /// Set an extended attribute, replacing any existing value.
+    pub fn set_xattr(&mut self, name: &[u8], value: &[u8]) {
+        let xattrs = self.xattrs.get_or_insert_with(Vec::new);
+        xattrs.retain(|(k, _)| k != name);
+        xattrs.push((name.to_vec(), value.to_vec()));
+    }
+
+    /// Remove all extended attributes whose name starts with `prefix`, e.g. `b"security."`.
+    pub fn remove_xattrs_with_prefix(&mut self, prefix: &[u8]) {
+        if let Some(xattrs) = self.xattrs.as_mut() {
+            xattrs.retain(|(k, _)| !k.starts_with(prefix));
+        }
+    }
+}
+
+/// A callback invoked for every file and directory being committed.
+///
+/// # Arguments
+/// * `repo` - the `Repo` that is being committed to
+/// * `path` - the path of the current file, as an absolute path rooted at the commit's root
+/// * `metadata` - the metadata of the current file, which may be modified
+///
+/// # Return Value
+/// The return value determines whether the current file is committed or skipped.
+///
+/// # Panics
+/// The callback may not panic. If it does, `abort()` will be called to avoid unwinding across
+/// an FFI boundary and into the libostree C code (which is Undefined Behavior).
+pub trait CommitFilter {
+    /// Inspect and modify the metadata of a file.
+    fn filter(
+        &self,
+        repo: &Repo,
+        path: &Path,
+        metadata: &mut CommitFileMetadata,
+    ) -> RepoCommitFilterResult;
+}
+
+impl<F> CommitFilter for F
+where
+    F: Fn(&Repo, &Path, &mut CommitFileMetadata) -> RepoCommitFilterResult,
+{
+    fn filter(
+        &self,
+        repo: &Repo,
+        path: &Path,
+        metadata: &mut CommitFileMetadata,
+    ) -> RepoCommitFilterResult {
+        self(repo, path, metadata)
+    }
+}
+
+/// State shared between the filter and xattr callbacks.
+struct FilterState {
+    filter: Box<dyn CommitFilter>,
+    xattr_source: Option<OwnedFd>,
+    /// Xattrs computed by the filter, consumed by the xattr callback for the same path.
+    xattrs: RefCell<HashMap<String, Xattrs>>,
+}
+
+impl FilterState {
+    fn source_xattrs(&self, path: &str) -> Option<Xattrs> {
+        let dfd = self.xattr_source.as_ref()?;
+        let relpath = match path.trim_start_matches('/') {
+            "" => ".",
+            p => p,
+        };
+        let v = crate::fs_get_all_xattrs_at(dfd.as_raw_fd(), relpath, gio::Cancellable::NONE);
+        v.ok()?.get::<Xattrs>()
+    }
+
+    fn call(&self, repo: &Repo, path: &str, file_info: &gio::FileInfo) -> RepoCommitFilterResult {
+        let mut metadata = CommitFileMetadata::from_file_info(file_info, self.source_xattrs(path));
+        let result = self.filter.filter(repo, Path::new(path), &mut metadata);
+        metadata.apply_to_file_info(file_info);
+        let mut xattrs = self.xattrs.borrow_mut();
+        match metadata.xattrs {
+            Some(v) if result == RepoCommitFilterResult::Allow => {
+                xattrs.insert(path.to_string(), v);
+            }
+            _ => {
+                xattrs.remove(path);
+            }
+        }
+        result
+    }
+}
+
+unsafe fn filter_trampoline(
+    repo: *mut ffi::OstreeRepo,
+    path: *const c_char,
+    file_info: *mut gio::ffi::GFileInfo,
+    user_data: gpointer,
+) -> ffi::OstreeRepoCommitFilterResult {
+    assert!(!user_data.is_null());
+    assert!(!path.is_null());
+    let state = &*(user_data as *const FilterState);
+    let repo: Borrowed<Repo> = from_glib_borrow(repo);
+    let path: Borrowed<glib::GString> = from_glib_borrow(path);
+    let file_info: Borrowed<gio::FileInfo> = from_glib_borrow(file_info);
+    state.call(&repo, path.as_str(), &file_info).into_glib()
+}
+
+unsafe extern "C" fn filter_trampoline_unwindsafe(
+    repo: *mut ffi::OstreeRepo,
+    path: *const c_char,
+    file_info: *mut gio::ffi::GFileInfo,
+    user_data: gpointer,
+) -> ffi::OstreeRepoCommitFilterResult {
+    abort_on_panic(move || filter_trampoline(repo, path, file_info, user_data))
+}
+
+unsafe extern "C" fn xattr_trampoline_unwindsafe(
+    _repo: *mut ffi::OstreeRepo,
+    path: *const c_char,
+    _file_info: *mut gio::ffi::GFileInfo,
+    user_data: gpointer,
+) -> *mut glib::ffi::GVariant {
+    abort_on_panic(move || {
+        let state = &*(user_data as *const FilterState);
+        let path: Borrowed<glib::GString> = from_glib_borrow(path);
+        // Returning NULL makes libostree use the xattrs from the source.
+        match state.xattrs.borrow_mut().remove(path.as_str()) {
+            Some(xattrs) => xattrs.to_variant().to_glib_full(),
+            None => std::ptr::null_mut(),
+        }
+    })
+}
+
+unsafe extern "C" fn destroy_state(data: gpointer) {
+    let _ = Rc::from_raw(data as *const FilterState);
+}
+
+impl RepoCommitModifier {
+    /// Create a modifier which calls `filter` for each file and directory being committed.
+    ///
+    /// The filter may change the uid, gid, mode and extended attributes, or skip the file.
+    /// Mode changes are subject to [`RepoCommitModifierFlags::CANONICAL_PERMISSIONS`], which
+    /// is applied after the filter.
+    ///
+    /// If `xattr_source` is given, it must be a directory file descriptor for the root of the
+    /// tree being committed; the existing xattrs of each file are read from it and passed to
+    /// the filter, so that individual attributes can be removed or replaced.
+    pub fn new_with_filter<F: CommitFilter + 'static>(
+        flags: RepoCommitModifierFlags,
+        xattr_source: Option<OwnedFd>,
+        filter: F,
+    ) -> RepoCommitModifier {
+        let state = Rc::new(FilterState {
+            filter: Box::new(filter),
+            xattr_source,
+            xattrs: Default::default(),
+        });
+        unsafe {
+            let modifier: RepoCommitModifier =
+                from_glib_full(ffi::ostree_repo_commit_modifier_new(
+                    flags.into_glib(),
+                    Some(filter_trampoline_unwindsafe),
+                    Rc::into_raw(state.clone()) as gpointer,
+                    Some(destroy_state),
+                ));
+            ffi::ostree_repo_commit_modifier_set_xattr_callback(
+                modifier.to_glib_none().0,
+                Some(xattr_trampoline_unwindsafe),
+                Some(destroy_state),
+                Rc::into_raw(state) as gpointer,
+            );
+            modifier
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::ptr;
+
+    fn file_info() -> gio::FileInfo {
+        let info = gio::FileInfo::new();
+        info.set_file_type(gio::FileType::Regular);
+        info.set_attribute_uint32("unix::uid", 1000);
+        info.set_attribute_uint32("unix::gid", 1000);
+        info.set_attribute_uint32("unix::mode", libc::S_IFREG | 0o4755);
+        info
+    }
+
+    fn state<F: CommitFilter + 'static>(filter: F) -> FilterState {
+        FilterState {
+            filter: Box::new(filter),
+            xattr_source: None,
+            xattrs: Default::default(),
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn trampoline_should_panic_if_user_data_is_nullptr() {
+        let repo = Repo::new_default();
+        let path = CString::new("/a/b/c").unwrap();
+        let info = file_info();
+        unsafe {
+            filter_trampoline(
+                repo.to_glib_none().0,
+                path.as_ptr(),
+                info.to_glib_none().0,
+                ptr::null_mut(),
+            );
+        }
+    }
+
+    #[test]
+    fn trampoline_should_update_file_info() {
+        let repo = Repo::new_default();
+        let path = CString::new("/usr/bin/foo").unwrap();
+        let info = file_info();
+        let state = state(|_: &Repo, path: &Path, meta: &mut CommitFileMetadata| {
+            assert_eq!(path, Path::new("/usr/bin/foo"));
+            assert_eq!(meta.file_type(), gio::FileType::Regular);
+            assert_eq!((meta.uid, meta.gid), (1000, 1000));
+            meta.uid = 0;
+            meta.gid = 0;
+            meta.mode &= !0o4000;
+            meta.set_xattr(b"security.selinux", b"system_u:object_r:bin_t:s0\0");
+            RepoCommitFilterResult::Allow
+        });
+        let result = unsafe {
+            filter_trampoline(
+                repo.to_glib_none().0,
+                path.as_ptr(),
+                info.to_glib_none().0,
+                &state as *const FilterState as gpointer,
+            )
+        };
+        assert_eq!(result, ffi::OSTREE_REPO_COMMIT_FILTER_ALLOW);
+        assert_eq!(info.attribute_uint32("unix::uid"), 0);
+        assert_eq!(info.attribute_uint32("unix::gid"), 0);
+        assert_eq!(info.attribute_uint32("unix::mode"), libc::S_IFREG | 0o755);
+        assert_eq!(
+            state.xattrs.borrow().get("/usr/bin/foo").unwrap(),
+            &vec![(
+                b"security.selinux".to_vec(),
+                b"system_u:object_r:bin_t:s0\0".to_vec()
+            )]
+        );
+    }
+
+    #[test]
+    fn trampoline_should_not_record_xattrs_for_skipped_files() {
+        let repo = Repo::new_default();
+        let path = CString::new("/tmp").unwrap();
+        let info = file_info();
+        let state = state(|_: &Repo, _: &Path, meta: &mut CommitFileMetadata| {
+            meta.xattrs = Some(Vec::new());
+            RepoCommitFilterResult::Skip
+        });
+        let result = unsafe {
+            filter_trampoline(
+                repo.to_glib_none().0,
+                path.as_ptr(),
+                info.to_glib_none().0,
+                &state as *const FilterState as gpointer,
+            )
+        };
+        assert_eq!(result, ffi::OSTREE_REPO_COMMIT_FILTER_SKIP);
+        assert!(state.xattrs.borrow().is_empty());
+    }
+
+    #[test]
+    fn should_remove_xattrs_with_prefix() {
+        let mut meta = CommitFileMetadata::from_file_info(
+            &file_info(),
+            Some(vec![
+                (b"security.ima".to_vec(), b"x".to_vec()),
+                (b"user.foo".to_vec(), b"bar".to_vec()),
+            ]),
+        );
+        meta.remove_xattrs_with_prefix(b"security.");
+        assert_eq!(
+            meta.xattrs.unwrap(),
+            vec![(b"user.foo".to_vec(), b"bar".to_vec())]
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! Support for writing byte-identical commits from identical inputs.
+
+use crate::{
+    CommitFileMetadata, CommitMetadata, ObjectType, Repo, RepoCommitFilterResult,
+    RepoCommitModifier, RepoCommitModifierFlags, TreeChangeKind, TreeDiffOptions, TreeEntryType,
+};
+use std::fmt;
+use std::os::fd::OwnedFd;
+use std::path::Path;
+
+/// Environment variable defined by <https://reproducible-builds.org/specs/source-date-epoch/>.
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// Options for writing reproducible commits with [`CommitBuilder::reproducible`](crate::CommitBuilder::reproducible).
+///
+/// In reproducible mode:
+///
+/// - the commit timestamp is clamped to the source date epoch;
+/// - all files are owned by uid and gid 0 and permissions are canonicalized with
+///   [`RepoCommitModifierFlags::CANONICAL_PERMISSIONS`];
+/// - only the extended attributes matching one of the kept prefixes are committed, sorted
+///   by name; other xattrs (e.g. IMA signatures or `user.*` attributes set by build tools)
+///   are considered volatile and stripped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReproducibleOptions {
+    source_date_epoch: u64,
+    keep_xattrs: Vec<Vec<u8>>,
+}
+
+impl ReproducibleOptions {
+    /// Create options using the given source date epoch, in seconds since the Unix epoch.
+    /// The `security.selinux` and `security.capability` xattrs are kept by default.
+    pub fn new(source_date_epoch: u64) -> Self {
+        Self {
+            source_date_epoch,
+            keep_xattrs: vec![
+                b"security.selinux".to_vec(),
+                b"security.capability".to_vec(),
+            ],
+        }
+    }
+
+    /// Create options from the `SOURCE_DATE_EPOCH` environment variable, if it is set.
+    pub fn from_env() -> Result<Option<Self>, std::num::ParseIntError> {
+        match std::env::var(SOURCE_DATE_EPOCH) {
+            Ok(v) => Ok(Some(Self::new(v.trim().parse()?))),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// The source date epoch.
+    pub fn source_date_epoch(&self) -> u64 {
+        self.source_date_epoch
+    }
+
+    /// Also keep extended attributes whose name starts with `prefix`.
+    pub fn keep_xattr(mut self, prefix: &str) -> Self {
+        self.keep_xattrs.push(prefix.as_bytes().to_vec());
+        self
+    }
+
+    /// Strip all extended attributes.
+    pub fn strip_all_xattrs(mut self) -> Self {
+        self.keep_xattrs.clear();
+        self
+    }
+
+    /// Clamp a timestamp to the source date epoch; `None` means the current time.
+    pub fn clamp_timestamp(&self, timestamp: Option<u64>) -> u64 {
+        timestamp.map_or(self.source_date_epoch, |t| t.min(self.source_date_epoch))
+    }
+
+    /// Normalize the metadata of a single file.
+    pub fn normalize(&self, metadata: &mut CommitFileMetadata) {
+        metadata.uid = 0;
+        metadata.gid = 0;
+        if let Some(xattrs) = metadata.xattrs.as_mut() {
+            xattrs.retain(|(k, _)| self.keep_xattrs.iter().any(|p| k.starts_with(p)));
+            xattrs.sort();
+        }
+    }
+
+    /// Create a commit modifier implementing these options.
+    ///
+    /// `xattr_source` is the root directory being committed; without it, xattrs can only be
+    /// stripped entirely (see [`ReproducibleOptions::strip_all_xattrs`]) and are otherwise
+    /// committed unchanged.
+    pub fn modifier(&self, xattr_source: Option<OwnedFd>) -> RepoCommitModifier {
+        let mut flags = RepoCommitModifierFlags::CANONICAL_PERMISSIONS;
+        if self.keep_xattrs.is_empty() {
+            flags |= RepoCommitModifierFlags::SKIP_XATTRS;
+        }
+        let opts = self.clone();
+        RepoCommitModifier::new_with_filter(
+            flags,
+            xattr_source,
+            move |_: &Repo, _: &Path, metadata: &mut CommitFileMetadata| {
+                opts.normalize(metadata);
+                RepoCommitFilterResult::Allow
+            },
+        )
+    }
+}
+
+/// A difference between a rebuilt commit and the expected one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RebuildDifference {
+    /// The commit timestamps differ.
+    Timestamp {
+        /// Timestamp of the expected commit.
+        expected: u64,
+        /// Timestamp of the rebuilt commit.
+        actual: u64,
+    },
+    /// The parent commits differ.
+    Parent,
+    /// The subject or body differ.
+    Message,
+    /// A metadata key is missing, added or has a different value.
+    Metadata(String),
+    /// A path only exists in the rebuilt commit.
+    Added(String),
+    /// A path only exists in the expected commit.
+    Removed(String),
+    /// A file has different content or metadata.
+    Modified(String),
+    /// A directory has different metadata (ownership, mode or xattrs).
+    DirMetadata(String),
+}
+
+impl fmt::Display for RebuildDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Timestamp { expected, actual } => {
+                write!(f, "timestamp: expected {expected}, got {actual}")
+            }
+            Self::Parent => write!(f, "parent commit differs"),
+            Self::Message => write!(f, "subject or body differs"),
+            Self::Metadata(k) => write!(f, "metadata key {k} differs"),
+            Self::Added(p) => write!(f, "A    {p}"),
+            Self::Removed(p) => write!(f, "D    {p}"),
+            Self::Modified(p) => write!(f, "M    {p}"),
+            Self::DirMetadata(p) => write!(f, "M    {p}/ (metadata)"),
+        }
+    }
+}
+
+/// Report describing why a rebuilt commit does not match the expected checksum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebuildReport {
+    /// Checksum of the expected commit.
+    pub expected: String,
+    /// Checksum of the rebuilt commit.
+    pub actual: String,
+    /// The differences found. This is empty if the expected commit is not in the repository.
+    pub differences: Vec<RebuildDifference>,
+}
+
+impl fmt::Display for RebuildReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rebuilt commit {} does not match expected {}",
+            self.actual, self.expected
+        )?;
+        if self.differences.is_empty() {
+            return write!(f, " (expected commit not available for comparison)");
+        }
+        for d in self.differences.iter() {
+            write!(f, "\n  {d}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Repo {
+    /// Compare a rebuilt commit against the expected commit checksum.
+    ///
+    /// Returns `None` if the checksums match. Otherwise, if the expected commit is present
+    /// in the repository, the report lists the differences in commit fields and in the tree.
+    pub fn verify_rebuild(
+        &self,
+        actual: &str,
+        expected: &str,
+    ) -> Result<Option<RebuildReport>, glib::Error> {
+        if actual == expected {
+            return Ok(None);
+        }
+        let mut report = RebuildReport {
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+            differences: Vec::new(),
+        };
+        let Some(expected_v) = self.load_variant_if_exists(ObjectType::Commit, expected)? else {
+            return Ok(Some(report));
+        };
+        let actual_v = self.load_variant(ObjectType::Commit, actual)?;
+        let (a, e) = (
+            actual_v.get::<crate::CommitVariantType>().unwrap(),
+            expected_v.get::<crate::CommitVariantType>().unwrap(),
+        );
+        let d = &mut report.differences;
+        if a.5 != e.5 {
+            d.push(RebuildDifference::Timestamp {
+                expected: u64::from_be(e.5),
+                actual: u64::from_be(a.5),
+            });
+        }
+        if a.1 != e.1 {
+            d.push(RebuildDifference::Parent);
+        }
+        if (&a.3, &a.4) != (&e.3, &e.4) {
+            d.push(RebuildDifference::Message);
+        }
+        let a_meta = CommitMetadata::from_dict(&a.0);
+        let e_meta = CommitMetadata::from_dict(&e.0);
+        let mut keys = a_meta
+            .iter()
+            .chain(e_meta.iter())
+            .map(|(k, _)| k)
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        for k in keys {
+            if a_meta.get(k) != e_meta.get(k) {
+                d.push(RebuildDifference::Metadata(k.to_string()));
+            }
+        }
+        if a.7 != e.7 {
+            d.push(RebuildDifference::DirMetadata(String::new()));
+        }
+        if a.6 != e.6 {
+            self.diff_trees_for_rebuild("", &hex::encode(&e.6), &hex::encode(&a.6), d)?;
+        }
+        Ok(Some(report))
+    }
+
+    fn diff_trees_for_rebuild(
+        &self,
+        path: &str,
+        expected: &str,
+        actual: &str,
+        out: &mut Vec<RebuildDifference>,
+    ) -> Result<(), glib::Error> {
+        let mut changes = Vec::new();
+        self.diff_dirtrees(
+            path,
+            expected,
+            actual,
+            &TreeDiffOptions::default(),
+            &mut changes,
+        )?;
+        out.extend(changes.into_iter().map(|c| match c.kind {
+            TreeChangeKind::Added(_) => RebuildDifference::Added(c.path),
+            TreeChangeKind::Removed(_) => RebuildDifference::Removed(c.path),
+            TreeChangeKind::Modified(m) if m.entry_type == TreeEntryType::Directory => {
+                RebuildDifference::DirMetadata(c.path)
+            }
+            TreeChangeKind::Modified(_) => RebuildDifference::Modified(c.path),
+        }));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_clamp_timestamp() {
+        let opts = ReproducibleOptions::new(1_700_000_000);
+        assert_eq!(opts.clamp_timestamp(None), 1_700_000_000);
+        assert_eq!(opts.clamp_timestamp(Some(1_800_000_000)), 1_700_000_000);
+        assert_eq!(opts.clamp_timestamp(Some(1_600_000_000)), 1_600_000_000);
+    }
+
+    #[test]
+    fn should_normalize_metadata() {
+        let opts = ReproducibleOptions::new(0).keep_xattr("user.keep");
+        let info = gio::FileInfo::new();
+        info.set_file_type(gio::FileType::Regular);
+        info.set_attribute_uint32("unix::uid", 1000);
+        info.set_attribute_uint32("unix::gid", 1000);
+        info.set_attribute_uint32("unix::mode", libc::S_IFREG | 0o644);
+        let xattrs = vec![
+            (b"user.keep".to_vec(), b"1".to_vec()),
+            (b"security.selinux".to_vec(), b"label".to_vec()),
+            (b"security.ima".to_vec(), b"sig".to_vec()),
+            (b"user.other".to_vec(), b"2".to_vec()),
+        ];
+        let mut meta = CommitFileMetadata::from_file_info(&info, Some(xattrs));
+        opts.normalize(&mut meta);
+        assert_eq!((meta.uid, meta.gid), (0, 0));
+        assert_eq!(
+            meta.xattrs.unwrap(),
+            vec![
+                (b"security.selinux".to_vec(), b"label".to_vec()),
+                (b"user.keep".to_vec(), b"1".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_format_report() {
+        let report = RebuildReport {
+            expected: "aaa".into(),
+            actual: "bbb".into(),
+            differences: vec![
+                RebuildDifference::Timestamp {
+                    expected: 1,
+                    actual: 2,
+                },
+                RebuildDifference::Modified("/usr/bin/foo".into()),
+            ],
+        };
+        assert_eq!(
+            report.to_string(),
+            "rebuilt commit bbb does not match expected aaa\n  timestamp: expected 1, got 2\n  M    /usr/bin/foo"
+        );
+    }
+}
@@ -0,0 +1,339 @@
+//! Soft rebooting into another deployment.
+//!
+//! A soft reboot restarts userspace into a deployment prepared at `/run/nextroot`, without
+//! going through the bootloader and kernel. This is only correct if the target deployment
+//! would boot with the same kernel, kernel arguments and initramfs as the booted one.
+
+use crate::{Deployment, KernelArgs, Sysroot};
+use gio::prelude::*;
+
+/// The name of the composefs image in a deployment directory.
+const COMPOSEFS_NAME: &str = ".ostree.cfs";
+/// The kernel argument pointing at the deployment, which differs for every deployment.
+const OSTREE_KARG: &str = "ostree";
+
+/// A reason why a deployment cannot be soft rebooted into.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SoftRebootBlocker {
+    /// No deployment is booted, e.g. when operating on an offline sysroot.
+    #[error("no deployment is booted")]
+    NotBooted,
+    /// The target deployment has a different kernel or initramfs.
+    #[error("kernel {target} differs from the booted kernel {booted}")]
+    KernelSkew {
+        /// The boot checksum of the booted deployment.
+        booted: String,
+        /// The boot checksum of the target deployment.
+        target: String,
+    },
+    /// The target deployment has different kernel arguments, ignoring `ostree=`. Like
+    /// libostree, the arguments are compared as an ordered list of exact strings.
+    #[error(
+        "kernel arguments `{}` differ from the booted `{}`",
+        .target.join(" "),
+        .booted.join(" ")
+    )]
+    KargsMismatch {
+        /// The kernel arguments of the booted deployment.
+        booted: Vec<String>,
+        /// The kernel arguments of the target deployment.
+        target: Vec<String>,
+    },
+    /// The target deployment has different overlay initramfs images.
+    #[error("overlay initramfs images differ")]
+    InitrdMismatch {
+        /// The overlay initramfs images of the booted deployment.
+        booted: Vec<String>,
+        /// The overlay initramfs images of the target deployment.
+        target: Vec<String>,
+    },
+    /// Only one of the deployments has a composefs image, so the target would not be
+    /// mounted the way the initramfs mounted the booted one.
+    #[error(
+        "composefs is {} for the booted deployment but {} for the target",
+        enabled(*.booted),
+        enabled(*.target)
+    )]
+    ComposefsMismatch {
+        /// Whether the booted deployment has a composefs image.
+        booted: bool,
+        /// Whether the target deployment has a composefs image.
+        target: bool,
+    },
+    /// [`Sysroot::deployment_can_soft_reboot`] rejects the target for a difference in
+    /// kernel state not covered by the other blockers.
+    #[error("libostree reports that the kernel state differs")]
+    KernelState,
+}
+
+impl SoftRebootBlocker {
+    /// Whether this is a difference in kernel state, which can be overridden with
+    /// [`SoftRebootPlan::allow_kernel_skew`].
+    pub fn is_kernel_skew(&self) -> bool {
+        matches!(
+            self,
+            Self::KernelSkew { .. }
+                | Self::KargsMismatch { .. }
+                | Self::InitrdMismatch { .. }
+                | Self::KernelState
+        )
+    }
+}
+
+fn enabled(b: bool) -> &'static str {
+    if b {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+/// Error returned by [`SoftRebootPlan::prepare`].
+#[derive(Debug, thiserror::Error)]
+pub enum SoftRebootError {
+    /// The target deployment cannot be soft rebooted into.
+    #[error("cannot soft reboot: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    Blocked(Vec<SoftRebootBlocker>),
+    /// Preparing `/run/nextroot` failed.
+    #[error(transparent)]
+    Glib(#[from] glib::Error),
+}
+
+/// The boot state of a deployment that a soft reboot must preserve.
+#[derive(Debug)]
+struct BootState {
+    bootcsum: String,
+    /// The kernel arguments without `ostree=`, or `None` if the boot entry has none, in
+    /// which case those of the merge deployment are used.
+    kargs: Option<Vec<String>>,
+    overlay_initrds: Vec<String>,
+    composefs: bool,
+}
+
+impl BootState {
+    fn new(sysroot: &Sysroot, deployment: &Deployment) -> Self {
+        let bootconfig = deployment.bootconfig();
+        let kargs = bootconfig.as_ref().and_then(|b| b.options()).map(|o| {
+            let kargs = KernelArgs::from_string(&o);
+            let _ = kargs.delete_key_entry(OSTREE_KARG);
+            kargs.to_strv().into_iter().map(Into::into).collect()
+        });
+        let overlay_initrds = bootconfig
+            .map(|b| b.overlay_initrds().into_iter().map(Into::into).collect())
+            .unwrap_or_default();
+        let composefs = sysroot
+            .path()
+            .path()
+            .map(|root| {
+                root.join(sysroot.deployment_dirpath(deployment))
+                    .join(COMPOSEFS_NAME)
+                    .exists()
+            })
+            .unwrap_or(false);
+        Self {
+            bootcsum: deployment.bootcsum().into(),
+            kargs,
+            overlay_initrds,
+            composefs,
+        }
+    }
+}
+
+/// The blockers for soft rebooting from `booted` into `target`; this extends the checks of
+/// [`Sysroot::deployment_can_soft_reboot`].
+fn evaluate(booted: &BootState, target: &BootState) -> Vec<SoftRebootBlocker> {
+    let mut blockers = Vec::new();
+    if booted.bootcsum != target.bootcsum {
+        blockers.push(SoftRebootBlocker::KernelSkew {
+            booted: booted.bootcsum.clone(),
+            target: target.bootcsum.clone(),
+        });
+    }
+    if let (Some(b), Some(t)) = (&booted.kargs, &target.kargs) {
+        if b != t {
+            blockers.push(SoftRebootBlocker::KargsMismatch {
+                booted: b.clone(),
+                target: t.clone(),
+            });
+        }
+    }
+    if booted.overlay_initrds != target.overlay_initrds {
+        blockers.push(SoftRebootBlocker::InitrdMismatch {
+            booted: booted.overlay_initrds.clone(),
+            target: target.overlay_initrds.clone(),
+        });
+    }
+    if booted.composefs != target.composefs {
+        blockers.push(SoftRebootBlocker::ComposefsMismatch {
+            booted: booted.composefs,
+            target: target.composefs,
+        });
+    }
+    blockers
+}
+
+/// Evaluates and prepares a soft reboot into a deployment.
+///
+/// ```no_run
+/// # fn f(sysroot: &ostree::Sysroot, target: &ostree::Deployment) -> Result<(), ostree::SoftRebootError> {
+/// let plan = ostree::SoftRebootPlan::new(sysroot, target);
+/// for blocker in plan.blockers() {
+///     eprintln!("{blocker}");
+/// }
+/// plan.prepare(ostree::gio::Cancellable::NONE)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SoftRebootPlan<'a> {
+    sysroot: &'a Sysroot,
+    target: Deployment,
+    blockers: Vec<SoftRebootBlocker>,
+    allow_kernel_skew: bool,
+}
+
+impl<'a> SoftRebootPlan<'a> {
+    /// Evaluate soft rebooting from the booted deployment of `sysroot` into `target`.
+    /// The sysroot must be loaded.
+    pub fn new(sysroot: &'a Sysroot, target: &Deployment) -> Self {
+        let blockers = match sysroot.booted_deployment() {
+            Some(booted) => {
+                let mut blockers = evaluate(
+                    &BootState::new(sysroot, &booted),
+                    &BootState::new(sysroot, target),
+                );
+                if !blockers.iter().any(SoftRebootBlocker::is_kernel_skew)
+                    && !sysroot.deployment_can_soft_reboot(target)
+                {
+                    blockers.push(SoftRebootBlocker::KernelState);
+                }
+                blockers
+            }
+            None => vec![SoftRebootBlocker::NotBooted],
+        };
+        Self {
+            sysroot,
+            target: target.clone(),
+            blockers,
+            allow_kernel_skew: false,
+        }
+    }
+
+    /// Prepare the soft reboot even if the kernel state differs. The target is then
+    /// booted with the kernel, kernel arguments and initramfs of the booted deployment.
+    pub fn allow_kernel_skew(mut self, allow: bool) -> Self {
+        self.allow_kernel_skew = allow;
+        self
+    }
+
+    /// The target deployment.
+    pub fn target(&self) -> &Deployment {
+        &self.target
+    }
+
+    /// All reasons why the target cannot be soft rebooted into, including those that
+    /// [`SoftRebootPlan::allow_kernel_skew`] overrides.
+    pub fn blockers(&self) -> &[SoftRebootBlocker] {
+        &self.blockers
+    }
+
+    /// Whether [`SoftRebootPlan::prepare`] would proceed.
+    pub fn is_possible(&self) -> bool {
+        self.remaining_blockers().next().is_none()
+    }
+
+    fn remaining_blockers(&self) -> impl Iterator<Item = &SoftRebootBlocker> {
+        self.blockers
+            .iter()
+            .filter(|b| !(self.allow_kernel_skew && b.is_kernel_skew()))
+    }
+
+    /// Prepare `/run/nextroot` for soft rebooting into the target; `systemctl soft-reboot`
+    /// then switches to it. A staged deployment other than the target is discarded.
+    pub fn prepare<P: IsA<gio::Cancellable>>(
+        &self,
+        cancellable: Option<&P>,
+    ) -> Result<(), SoftRebootError> {
+        let blockers = self.remaining_blockers().cloned().collect::<Vec<_>>();
+        if !blockers.is_empty() {
+            return Err(SoftRebootError::Blocked(blockers));
+        }
+        self.sysroot.deployment_set_soft_reboot(
+            &self.target,
+            self.allow_kernel_skew,
+            cancellable,
+        )?;
+        Ok(())
+    }
+}
+
+impl Sysroot {
+    /// The deployment prepared for the next soft reboot, if any.
+    pub fn soft_reboot_target(&self) -> Option<Deployment> {
+        self.deployments()
+            .into_iter()
+            .find(|d| d.is_soft_reboot_target())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(bootcsum: &str, kargs: Option<&str>, composefs: bool) -> BootState {
+        BootState {
+            bootcsum: bootcsum.into(),
+            kargs: kargs.map(|k| k.split(' ').map(Into::into).collect()),
+            overlay_initrds: Vec::new(),
+            composefs,
+        }
+    }
+
+    #[test]
+    fn should_allow_identical_boot_state() {
+        let booted = state("a", Some("root=/dev/vda rw"), true);
+        assert!(evaluate(&booted, &state("a", Some("root=/dev/vda rw"), true)).is_empty());
+        assert!(evaluate(&booted, &state("a", None, true)).is_empty());
+    }
+
+    #[test]
+    fn should_explain_differences() {
+        let booted = state("a", Some("root=/dev/vda rw"), true);
+        let mut target = state("b", Some("root=/dev/vda ro"), false);
+        target.overlay_initrds = vec!["c0ffee".into()];
+        let blockers = evaluate(&booted, &target);
+        assert_eq!(blockers.len(), 4);
+        assert!(blockers
+            .iter()
+            .take(3)
+            .all(SoftRebootBlocker::is_kernel_skew));
+        assert_eq!(
+            blockers[1].to_string(),
+            "kernel arguments `root=/dev/vda ro` differ from the booted `root=/dev/vda rw`"
+        );
+        assert_eq!(
+            blockers[3],
+            SoftRebootBlocker::ComposefsMismatch {
+                booted: true,
+                target: false
+            }
+        );
+    }
+
+    #[test]
+    fn should_compare_kargs_exactly() {
+        let booted = state("a", Some("root=/dev/vda rw foo-bar=1"), true);
+        let reordered = state("a", Some("rw root=/dev/vda foo-bar=1"), true);
+        let blockers = evaluate(&booted, &reordered);
+        assert_eq!(
+            blockers,
+            [SoftRebootBlocker::KargsMismatch {
+                booted: vec!["root=/dev/vda".into(), "rw".into(), "foo-bar=1".into()],
+                target: vec!["rw".into(), "root=/dev/vda".into(), "foo-bar=1".into()],
+            }]
+        );
+        let underscore = state("a", Some("root=/dev/vda rw foo_bar=1"), true);
+        assert_eq!(evaluate(&booted, &underscore).len(), 1);
+        assert!(evaluate(&booted, &underscore)[0].is_kernel_skew());
+    }
+}
@@ -0,0 +1,208 @@
+//! Inspecting and discarding the staged deployment.
+//!
+//! A deployment written with [`Sysroot::stage_tree`] is only added to the bootloader
+//! configuration at shutdown, by `ostree-finalize-staged.service`. Until then, its kernel
+//! arguments and overlay initramfs images are recorded in [`STAGED_DEPLOYMENT_PATH`].
+
+use crate::sysroot::SysrootLockGuard;
+use crate::{Deployment, Sysroot};
+use gio::prelude::*;
+use glib::{VariantDict, VariantTy};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The record of the staged deployment.
+pub const STAGED_DEPLOYMENT_PATH: &str = "/run/ostree/staged-deployment";
+/// If this file exists, the staged deployment is not finalized at shutdown.
+pub const STAGED_DEPLOYMENT_LOCKED_PATH: &str = "/run/ostree/staged-deployment-locked";
+
+/// A deployment referenced by the staged deployment record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StagedDeploymentRef {
+    /// The stateroot.
+    pub osname: String,
+    /// The commit checksum.
+    pub checksum: String,
+    /// The deployment serial.
+    pub deployserial: i32,
+    /// The checksum of the kernel and initramfs.
+    pub bootcsum: String,
+}
+
+impl StagedDeploymentRef {
+    fn from_variant(v: &glib::Variant) -> Result<Self, glib::Error> {
+        let dict = VariantDict::new(Some(v));
+        let string = |key: &str| {
+            dict.lookup::<String>(key)
+                .ok()
+                .flatten()
+                .ok_or_else(|| invalid_data(&format!("Missing key: {key}")))
+        };
+        let name = string("name")?;
+        let (checksum, deployserial) = name
+            .rsplit_once('.')
+            .and_then(|(csum, serial)| Some((csum.to_string(), serial.parse().ok()?)))
+            .ok_or_else(|| invalid_data(&format!("Invalid deployment name: {name}")))?;
+        Ok(Self {
+            osname: string("osname")?,
+            checksum,
+            deployserial,
+            bootcsum: string("bootcsum")?,
+        })
+    }
+
+    /// Whether this refers to `deployment`.
+    pub fn matches(&self, deployment: &Deployment) -> bool {
+        self.osname == deployment.osname()
+            && self.checksum == deployment.csum()
+            && self.deployserial == deployment.deployserial()
+    }
+}
+
+/// The staged deployment record, returned by [`Sysroot::staged_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StagedInfo {
+    /// The staged deployment.
+    pub target: StagedDeploymentRef,
+    /// The deployment whose `/etc` is merged into the staged one when it is finalized.
+    pub merge_deployment: Option<StagedDeploymentRef>,
+    /// The kernel arguments of the boot entry.
+    pub kargs: Vec<String>,
+    /// The overlay initramfs images, by checksum.
+    pub overlay_initrds: Vec<String>,
+    /// Whether finalization is locked, either by the record itself or by
+    /// [`STAGED_DEPLOYMENT_LOCKED_PATH`].
+    pub locked: bool,
+}
+
+impl StagedInfo {
+    /// Parse the contents of [`STAGED_DEPLOYMENT_PATH`], a serialized `a{sv}` variant.
+    /// This does not take [`STAGED_DEPLOYMENT_LOCKED_PATH`] into account.
+    pub fn parse(data: &[u8]) -> Result<Self, glib::Error> {
+        let v = glib::Variant::from_data_with_type(data, VariantTy::VARDICT).normal_form();
+        let dict = VariantDict::new(Some(&v));
+        let strv = |key: &str| -> Result<Vec<String>, glib::Error> {
+            dict.lookup(key)
+                .map(Option::unwrap_or_default)
+                .map_err(|e| invalid_data(&format!("{key}: {e}")))
+        };
+        let target = dict
+            .lookup_value("target", Some(VariantTy::VARDICT))
+            .ok_or_else(|| invalid_data("Missing key: target"))?;
+        Ok(Self {
+            target: StagedDeploymentRef::from_variant(&target)?,
+            merge_deployment: dict
+                .lookup_value("merge-deployment", Some(VariantTy::VARDICT))
+                .map(|v| StagedDeploymentRef::from_variant(&v))
+                .transpose()?,
+            kargs: strv("kargs")?,
+            overlay_initrds: strv("overlay-initrds")?,
+            locked: dict.lookup("locked").ok().flatten().unwrap_or(false),
+        })
+    }
+
+    /// The kernel arguments as [`KernelArgs`](crate::KernelArgs).
+    #[cfg(any(feature = "v2019_3", feature = "dox"))]
+    #[cfg_attr(feature = "dox", doc(cfg(feature = "v2019_3")))]
+    pub fn kernel_args(&self) -> crate::KernelArgs {
+        let kargs = crate::KernelArgs::new();
+        for arg in &self.kargs {
+            kargs.append(arg);
+        }
+        kargs
+    }
+}
+
+impl Sysroot {
+    /// Read the staged deployment record, or `None` if no deployment is staged.
+    pub fn staged_info(&self) -> Result<Option<StagedInfo>, glib::Error> {
+        let data = match std::fs::read(STAGED_DEPLOYMENT_PATH) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(glib::Error::new(gio::IOErrorEnum::Failed, &e.to_string())),
+        };
+        let mut info = StagedInfo::parse(&data)?;
+        info.locked |= std::path::Path::new(STAGED_DEPLOYMENT_LOCKED_PATH).exists();
+        Ok(Some(info))
+    }
+
+    /// Remove the staged deployment, so that it is not finalized at shutdown. Returns
+    /// whether a deployment was staged.
+    ///
+    /// The sysroot lock is taken for the duration of the call, so it must not already be
+    /// held by the caller.
+    pub fn discard_staged<P: IsA<gio::Cancellable>>(
+        &self,
+        cancellable: Option<&P>,
+    ) -> Result<bool, glib::Error> {
+        let cancellable = cancellable.map(|c| c.as_ref());
+        self.lock()?;
+        let _guard = SysrootLockGuard(self);
+        self.load_if_changed(cancellable)?;
+        if self.staged_deployment().is_none() {
+            return Ok(false);
+        }
+        let deployments = self
+            .deployments()
+            .into_iter()
+            .filter(|d| !d.is_staged())
+            .collect::<Vec<_>>();
+        self.write_deployments(&deployments, cancellable)?;
+        Ok(true)
+    }
+}
+
+fn invalid_data(message: &str) -> glib::Error {
+    glib::Error::new(
+        gio::IOErrorEnum::InvalidData,
+        &format!("Invalid staged deployment: {message}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deployment(name: &str) -> glib::Variant {
+        let dict = VariantDict::new(None);
+        dict.insert("name", name);
+        dict.insert("osname", "os");
+        dict.insert("bootcsum", "b00t");
+        dict.end()
+    }
+
+    #[test]
+    fn should_parse_staged_record() {
+        let dict = VariantDict::new(None);
+        dict.insert_value("target", &deployment("c0ffee.1"));
+        dict.insert_value("merge-deployment", &deployment("beef.0"));
+        dict.insert("kargs", vec!["root=/dev/vda", "rw"]);
+        dict.insert("locked", true);
+        let info = StagedInfo::parse(dict.end().data()).unwrap();
+        assert_eq!(
+            info.target,
+            StagedDeploymentRef {
+                osname: "os".into(),
+                checksum: "c0ffee".into(),
+                deployserial: 1,
+                bootcsum: "b00t".into(),
+            }
+        );
+        assert_eq!(info.merge_deployment.unwrap().checksum, "beef");
+        assert_eq!(info.kargs, ["root=/dev/vda", "rw"]);
+        assert!(info.overlay_initrds.is_empty());
+        assert!(info.locked);
+    }
+
+    #[test]
+    fn should_reject_record_without_target() {
+        let dict = VariantDict::new(None);
+        dict.insert("kargs", vec!["rw"]);
+        assert!(StagedInfo::parse(dict.end().data()).is_err());
+        let dict = VariantDict::new(None);
+        dict.insert_value("target", &deployment("c0ffee"));
+        assert!(StagedInfo::parse(dict.end().data()).is_err());
+    }
+}
@@ -0,0 +1,760 @@
+//! Inspection of static deltas stored in a repository.
+//!
+//! This parses the on-disk format described in `ostree-repo-static-delta-private.h`: a
+//! superblock listing the delta parts and fallback objects, and the parts themselves,
+//! which contain a payload and a list of operations writing objects from it.
+//!
+//! Reading parts as stored on disk, which are usually xz-compressed, requires the
+//! `static_delta_parts` feature.
+
+use crate::{Checksum, ObjectName, ObjectType, Repo, Xattrs};
+use gio::prelude::*;
+use glib::translate::FromGlib;
+use glib::{Variant, VariantTy};
+#[cfg(feature = "static_delta_parts")]
+use std::io::Read;
+use std::path::PathBuf;
+
+const SUPERBLOCK_FORMAT: &str = "(a{sv}tayay(a{sv}aya(say)sstayay)aya(uayttay)a(yaytt))";
+const SIGNED_FORMAT: &str = "(taya{sv})";
+const PART_PAYLOAD_FORMAT: &str = "(a(uuu)aa(ayay)ayay)";
+const SIGNED_MAGIC: &[u8; 8] = b"OSTSGNDT";
+/// 1 byte for the object type, 32 bytes for the checksum.
+const OBJTYPE_CSUM_LEN: usize = 33;
+const MAX_VARINT_BYTES: usize = 10;
+
+/// The type of a delta part entry in the superblock: `(uayttay)`
+type MetaEntryVariantType = (u32, Vec<u8>, u64, u64, Vec<u8>);
+
+/// Error returned by the static delta APIs.
+#[derive(Debug, thiserror::Error)]
+pub enum StaticDeltaError {
+    /// The delta name is not of the form `TO` or `FROM-TO`.
+    #[error("invalid static delta name '{0}'")]
+    InvalidName(String),
+    /// An invalid option was passed when generating a delta.
+    #[error("invalid static delta option: {0}")]
+    InvalidOption(String),
+    /// The delta data is malformed.
+    #[error("invalid static delta: {0}")]
+    Format(String),
+    /// An I/O error reading the delta.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// Any other error.
+    #[error(transparent)]
+    Glib(#[from] glib::Error),
+}
+
+fn format_error(msg: impl Into<String>) -> StaticDeltaError {
+    StaticDeltaError::Format(msg.into())
+}
+
+/// Byte order used for the sizes stored in a superblock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaticDeltaEndianness {
+    /// Little endian.
+    Little,
+    /// Big endian.
+    Big,
+}
+
+/// A part of a static delta, as described in its superblock.
+#[derive(Debug)]
+pub struct StaticDeltaPartInfo {
+    /// The format version of the part.
+    pub version: u32,
+    /// The SHA256 checksum of the part data.
+    pub checksum: String,
+    /// The size of the part, usually compressed.
+    pub size: u64,
+    /// The total size of the objects written by the part.
+    pub uncompressed_size: u64,
+    /// The objects written by the part, in order.
+    pub objects: Vec<ObjectName>,
+    /// Whether the part is stored inline in the superblock metadata.
+    pub inline: bool,
+}
+
+/// An object that is not part of the delta and must be fetched individually.
+#[derive(Debug)]
+pub struct StaticDeltaFallback {
+    /// The object.
+    pub object: ObjectName,
+    /// The size of the object as fetched, usually compressed.
+    pub size: u64,
+    /// The uncompressed size of the object.
+    pub uncompressed_size: u64,
+}
+
+/// The superblock of a static delta, describing its parts and fallback objects.
+#[derive(Debug)]
+pub struct StaticDeltaSuperblock {
+    /// The source commit, or `None` for a delta from scratch.
+    pub from: Option<String>,
+    /// The target commit.
+    pub to: String,
+    /// The time the delta was generated, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The delta metadata, of type `a{sv}`.
+    pub metadata: Variant,
+    /// The target commit object.
+    pub commit: Variant,
+    /// Whether the superblock is signed.
+    pub signed: bool,
+    /// The byte order of the sizes in the superblock, taken from the `ostree.endianness`
+    /// metadata key or guessed like libostree does.
+    pub endianness: StaticDeltaEndianness,
+    /// The delta parts.
+    pub parts: Vec<StaticDeltaPartInfo>,
+    /// The fallback objects.
+    pub fallback: Vec<StaticDeltaFallback>,
+}
+
+impl StaticDeltaSuperblock {
+    /// Parse a superblock, which may be signed.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, StaticDeltaError> {
+        let signed = data.starts_with(SIGNED_MAGIC);
+        let data = if signed {
+            let v = Variant::from_bytes_with_type(
+                &glib::Bytes::from(data),
+                VariantTy::new(SIGNED_FORMAT).unwrap(),
+            );
+            v.child_value(1).data_as_bytes()
+        } else {
+            glib::Bytes::from(data)
+        };
+        let v = Variant::from_bytes_with_type(&data, VariantTy::new(SUPERBLOCK_FORMAT).unwrap());
+        let metadata = v.child_value(0);
+        let timestamp = u64::from_be(v.child_value(1).get::<u64>().unwrap());
+        let from = v.child_value(2).fixed_array::<u8>().unwrap().to_vec();
+        let from = match from.len() {
+            0 => None,
+            _ => Some(checksum_from_bytes(&from)?),
+        };
+        let to = checksum_from_bytes(v.child_value(3).fixed_array::<u8>().unwrap())?;
+        let commit = v.child_value(4);
+
+        let raw_parts = v.child_value(6).get::<Vec<MetaEntryVariantType>>().unwrap();
+        let raw_fallback = v
+            .child_value(7)
+            .get::<Vec<(u8, Vec<u8>, u64, u64)>>()
+            .unwrap();
+        let endianness = detect_endianness(&metadata, &raw_parts);
+        let swap = |n: u64| match endianness {
+            StaticDeltaEndianness::Little => u64::from_le(n),
+            StaticDeltaEndianness::Big => u64::from_be(n),
+        };
+
+        let mut parts = Vec::with_capacity(raw_parts.len());
+        for (i, (version, checksum, size, usize, objects)) in raw_parts.into_iter().enumerate() {
+            let inline_key = part_relpath(from.as_deref(), &to, i as u32);
+            parts.push(StaticDeltaPartInfo {
+                version,
+                checksum: checksum_from_bytes(&checksum)?,
+                size: swap(size),
+                uncompressed_size: swap(usize),
+                objects: parse_object_array(&objects)?,
+                inline: lookup(&metadata, &inline_key, None).is_some(),
+            });
+        }
+        let fallback = raw_fallback
+            .into_iter()
+            .map(|(objtype, checksum, size, usize)| {
+                Ok(StaticDeltaFallback {
+                    object: ObjectName::new(
+                        checksum_from_bytes(&checksum)?,
+                        object_type_from_u8(objtype)?,
+                    ),
+                    size: swap(size),
+                    uncompressed_size: swap(usize),
+                })
+            })
+            .collect::<Result<Vec<_>, StaticDeltaError>>()?;
+
+        Ok(Self {
+            from,
+            to,
+            timestamp,
+            metadata,
+            commit,
+            signed,
+            endianness,
+            parts,
+            fallback,
+        })
+    }
+
+    /// The name of the delta, i.e. `FROM-TO` or `TO`.
+    pub fn name(&self) -> String {
+        match self.from.as_deref() {
+            Some(from) => format!("{from}-{}", self.to),
+            None => self.to.clone(),
+        }
+    }
+
+    /// The total size of all parts.
+    pub fn parts_size(&self) -> u64 {
+        self.parts.iter().map(|p| p.size).sum()
+    }
+
+    /// The total uncompressed size of all parts.
+    pub fn parts_uncompressed_size(&self) -> u64 {
+        self.parts.iter().map(|p| p.uncompressed_size).sum()
+    }
+
+    /// The total size of all fallback objects.
+    pub fn fallback_size(&self) -> u64 {
+        self.fallback.iter().map(|f| f.size).sum()
+    }
+
+    /// The total uncompressed size of all fallback objects.
+    pub fn fallback_uncompressed_size(&self) -> u64 {
+        self.fallback.iter().map(|f| f.uncompressed_size).sum()
+    }
+
+    /// The total download size of the delta, including fallback objects.
+    pub fn total_size(&self) -> u64 {
+        self.parts_size() + self.fallback_size()
+    }
+}
+
+/// An operation in a static delta part.
+///
+/// Objects are identified by their index in [`StaticDeltaPartInfo::objects`]; offsets and
+/// lengths refer to the part payload unless a read source is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StaticDeltaOp {
+    /// Write a metadata object from the payload (`S`).
+    SpliceMetadata {
+        /// Index of the object written.
+        object: usize,
+        /// Offset of the object data in the payload.
+        offset: u64,
+        /// Length of the object data.
+        length: u64,
+    },
+    /// Write a content object from the payload in one step (`S`).
+    SpliceContent {
+        /// Index of the object written.
+        object: usize,
+        /// Index into the part modes.
+        mode: u64,
+        /// Index into the part xattrs.
+        xattrs: u64,
+        /// Offset of the file content in the payload.
+        offset: u64,
+        /// Length of the file content.
+        length: u64,
+    },
+    /// Start writing a content object (`o`).
+    Open {
+        /// Index of the object written.
+        object: usize,
+        /// Index into the part modes.
+        mode: u64,
+        /// Index into the part xattrs.
+        xattrs: u64,
+        /// Total size of the file content.
+        size: u64,
+    },
+    /// Append data to the open object, from the payload or the current read source (`w`).
+    Write {
+        /// Offset of the data.
+        offset: u64,
+        /// Length of the data.
+        length: u64,
+        /// Whether the data is read from a read source object instead of the payload.
+        from_read_source: bool,
+    },
+    /// Read subsequent writes from an existing object (`r`).
+    SetReadSource {
+        /// Checksum of the source object.
+        source: String,
+    },
+    /// Read subsequent writes from the payload again (`R`).
+    UnsetReadSource,
+    /// Finish writing the open object (`c`).
+    Close,
+    /// Write the open object by applying a bsdiff patch from the payload (`B`).
+    Bspatch {
+        /// Offset of the patch in the payload.
+        offset: u64,
+        /// Length of the patch.
+        length: u64,
+    },
+}
+
+impl StaticDeltaOp {
+    /// The opcode byte.
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Self::SpliceMetadata { .. } | Self::SpliceContent { .. } => b'S',
+            Self::Open { .. } => b'o',
+            Self::Write { .. } => b'w',
+            Self::SetReadSource { .. } => b'r',
+            Self::UnsetReadSource => b'R',
+            Self::Close => b'c',
+            Self::Bspatch { .. } => b'B',
+        }
+    }
+
+    /// The number of payload bytes used by this operation.
+    pub fn payload_bytes(&self) -> u64 {
+        match self {
+            Self::SpliceMetadata { length, .. }
+            | Self::SpliceContent { length, .. }
+            | Self::Bspatch { length, .. } => *length,
+            Self::Write {
+                length,
+                from_read_source: false,
+                ..
+            } => *length,
+            Self::SetReadSource { .. } => 32,
+            _ => 0,
+        }
+    }
+}
+
+/// A decoded static delta part.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticDeltaPart {
+    /// The (uid, gid, mode) tuples referenced by the operations.
+    pub modes: Vec<(u32, u32, u32)>,
+    /// The xattr sets referenced by the operations.
+    pub xattrs: Vec<Xattrs>,
+    /// The size of the raw data payload.
+    pub payload_size: u64,
+    /// The operations, in order.
+    pub ops: Vec<StaticDeltaOp>,
+}
+
+impl StaticDeltaPart {
+    /// Parse a part as stored on disk, i.e. a compression byte followed by the payload.
+    ///
+    /// `objects` are the objects written by the part, see [`StaticDeltaPartInfo::objects`].
+    #[cfg(feature = "static_delta_parts")]
+    pub fn parse(data: &[u8], objects: &[ObjectName]) -> Result<Self, StaticDeltaError> {
+        match data.first() {
+            Some(0) => Self::from_payload(&data[1..], objects),
+            Some(b'x') => {
+                let mut payload = Vec::new();
+                xz2::read::XzDecoder::new(&data[1..]).read_to_end(&mut payload)?;
+                Self::from_payload(&payload, objects)
+            }
+            Some(&c) => Err(format_error(format!("invalid compression type {c}"))),
+            None => Err(format_error("empty part")),
+        }
+    }
+
+    /// Parse the uncompressed payload of a part, without the compression byte.
+    pub fn from_payload(payload: &[u8], objects: &[ObjectName]) -> Result<Self, StaticDeltaError> {
+        let v = Variant::from_bytes_with_type(
+            &glib::Bytes::from(payload),
+            VariantTy::new(PART_PAYLOAD_FORMAT).unwrap(),
+        );
+        let modes = v
+            .child_value(0)
+            .get::<Vec<(u32, u32, u32)>>()
+            .unwrap()
+            .into_iter()
+            .map(|(uid, gid, mode)| (u32::from_be(uid), u32::from_be(gid), u32::from_be(mode)))
+            .collect();
+        let xattrs = v.child_value(1).get::<Vec<Xattrs>>().unwrap();
+        let blob = v.child_value(2);
+        let payload_size = blob.size() as u64;
+        let ops_v = v.child_value(3);
+        let mut ops = Vec::new();
+        let mut reader = OpReader {
+            data: ops_v.fixed_array::<u8>().unwrap(),
+        };
+        let mut object = 0usize;
+        let mut read_source = false;
+        let check_object = |object: usize| {
+            objects
+                .get(object)
+                .ok_or_else(|| format_error(format!("operation refers to object {object}")))
+        };
+        while let Some(opcode) = reader.byte() {
+            let op = match opcode {
+                b'S' => {
+                    let obj = check_object(object)?;
+                    let op = if obj.object_type() == ObjectType::File {
+                        let mode = reader.varint()?;
+                        let xattrs = reader.varint()?;
+                        let length = reader.varint()?;
+                        let offset = reader.varint()?;
+                        StaticDeltaOp::SpliceContent {
+                            object,
+                            mode,
+                            xattrs,
+                            offset,
+                            length,
+                        }
+                    } else {
+                        let length = reader.varint()?;
+                        let offset = reader.varint()?;
+                        StaticDeltaOp::SpliceMetadata {
+                            object,
+                            offset,
+                            length,
+                        }
+                    };
+                    object += 1;
+                    read_source = false;
+                    op
+                }
+                b'o' => {
+                    check_object(object)?;
+                    let mode = reader.varint()?;
+                    let xattrs = reader.varint()?;
+                    let size = reader.varint()?;
+                    StaticDeltaOp::Open {
+                        object,
+                        mode,
+                        xattrs,
+                        size,
+                    }
+                }
+                b'w' => {
+                    let length = reader.varint()?;
+                    let offset = reader.varint()?;
+                    StaticDeltaOp::Write {
+                        offset,
+                        length,
+                        from_read_source: read_source,
+                    }
+                }
+                b'r' => {
+                    let offset = reader.varint()?;
+                    let data = blob.fixed_array::<u8>().unwrap();
+                    let source = usize::try_from(offset)
+                        .ok()
+                        .and_then(|start| Some(start..start.checked_add(32)?))
+                        .and_then(|range| data.get(range))
+                        .ok_or_else(|| format_error("read source out of bounds"))?;
+                    read_source = true;
+                    StaticDeltaOp::SetReadSource {
+                        source: hex::encode(source),
+                    }
+                }
+                b'R' => {
+                    read_source = false;
+                    StaticDeltaOp::UnsetReadSource
+                }
+                b'c' => {
+                    object += 1;
+                    read_source = false;
+                    StaticDeltaOp::Close
+                }
+                b'B' => {
+                    let offset = reader.varint()?;
+                    let length = reader.varint()?;
+                    StaticDeltaOp::Bspatch { offset, length }
+                }
+                c => return Err(format_error(format!("unknown opcode {c}"))),
+            };
+            ops.push(op);
+        }
+        Ok(Self {
+            modes,
+            xattrs,
+            payload_size,
+            ops,
+        })
+    }
+
+    /// The number of payload bytes used to write each object, indexed like
+    /// [`StaticDeltaPartInfo::objects`].
+    pub fn payload_bytes_per_object(&self) -> Vec<u64> {
+        let mut sizes = Vec::new();
+        let mut object = 0usize;
+        for op in self.ops.iter() {
+            if sizes.len() <= object {
+                sizes.resize(object + 1, 0);
+            }
+            sizes[object] += op.payload_bytes();
+            if matches!(
+                op,
+                StaticDeltaOp::SpliceMetadata { .. }
+                    | StaticDeltaOp::SpliceContent { .. }
+                    | StaticDeltaOp::Close
+            ) {
+                object += 1;
+            }
+        }
+        sizes.truncate(object);
+        sizes
+    }
+}
+
+struct OpReader<'a> {
+    data: &'a [u8],
+}
+
+impl OpReader<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let (&b, rest) = self.data.split_first()?;
+        self.data = rest;
+        Some(b)
+    }
+
+    fn varint(&mut self) -> Result<u64, StaticDeltaError> {
+        let mut result = 0u64;
+        for count in 0..MAX_VARINT_BYTES {
+            let b = self
+                .byte()
+                .ok_or_else(|| format_error("unexpected EOF reading varint"))?;
+            result |= u64::from(b & 0x7f) << (7 * count);
+            if b & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err(format_error("varint too long"))
+    }
+}
+
+fn lookup(dict: &Variant, key: &str, ty: Option<&VariantTy>) -> Option<Variant> {
+    glib::VariantDict::new(Some(dict)).lookup_value(key, ty)
+}
+
+fn checksum_from_bytes(bytes: &[u8]) -> Result<String, StaticDeltaError> {
+    let bytes: &[u8; 32] = bytes
+        .try_into()
+        .map_err(|_| format_error(format!("invalid checksum length {}", bytes.len())))?;
+    Ok(Checksum::from_bytes(bytes).to_hex())
+}
+
+fn object_type_from_u8(objtype: u8) -> Result<ObjectType, StaticDeltaError> {
+    crate::validate_structureof_objtype(objtype)?;
+    // Safety: the object type was validated above.
+    Ok(unsafe { ObjectType::from_glib(objtype as ffi::OstreeObjectType) })
+}
+
+fn parse_object_array(data: &[u8]) -> Result<Vec<ObjectName>, StaticDeltaError> {
+    if data.len() % OBJTYPE_CSUM_LEN != 0 {
+        return Err(format_error("invalid object array length"));
+    }
+    data.chunks(OBJTYPE_CSUM_LEN)
+        .map(|c| {
+            Ok(ObjectName::new(
+                checksum_from_bytes(&c[1..])?,
+                object_type_from_u8(c[0])?,
+            ))
+        })
+        .collect()
+}
+
+/// Follows `_ostree_delta_get_endianness`.
+fn detect_endianness(metadata: &Variant, parts: &[MetaEntryVariantType]) -> StaticDeltaEndianness {
+    let native = if cfg!(target_endian = "big") {
+        StaticDeltaEndianness::Big
+    } else {
+        StaticDeltaEndianness::Little
+    };
+    let swapped = match native {
+        StaticDeltaEndianness::Big => StaticDeltaEndianness::Little,
+        StaticDeltaEndianness::Little => StaticDeltaEndianness::Big,
+    };
+    if let Some(e) =
+        lookup(metadata, "ostree.endianness", Some(VariantTy::BYTE)).and_then(|v| v.get::<u8>())
+    {
+        return match e {
+            b'B' => StaticDeltaEndianness::Big,
+            b'l' => StaticDeltaEndianness::Little,
+            _ => native,
+        };
+    }
+    let mut total_size = 0u64;
+    let mut total_objects = 0u64;
+    for (_, _, size, usize, objects) in parts {
+        if size > usize && (*size as f64) / (*usize as f64) > 1.2 {
+            return swapped;
+        }
+        total_size = total_size.wrapping_add(*size);
+        total_objects += (objects.len() / OBJTYPE_CSUM_LEN) as u64;
+    }
+    if total_objects > 0 && total_size / total_objects > u64::from(u32::MAX) {
+        return swapped;
+    }
+    native
+}
+
+/// Split a delta name into its source and target commits.
+pub(crate) fn parse_delta_name(name: &str) -> Result<(Option<&str>, &str), StaticDeltaError> {
+    let invalid = || StaticDeltaError::InvalidName(name.to_string());
+    let (from, to) = match name.split_once('-') {
+        Some((from, to)) => (Some(from), to),
+        None => (None, name),
+    };
+    for c in from.iter().chain(std::iter::once(&to)) {
+        crate::validate_checksum_string(c).map_err(|_| invalid())?;
+    }
+    Ok((from, to))
+}
+
+/// Mirrors `_ostree_get_relative_static_delta_path`.
+pub(crate) fn delta_relpath(from: Option<&str>, to: &str) -> String {
+    let b64 = |c: &str| Checksum::from_hex(c).unwrap().to_base64();
+    let to = b64(to);
+    match from {
+        Some(from) => {
+            let from = b64(from);
+            format!("deltas/{}/{}-{}", &from[..2], &from[2..], to)
+        }
+        None => format!("deltas/{}/{}", &to[..2], &to[2..]),
+    }
+}
+
+pub(crate) fn part_relpath(from: Option<&str>, to: &str, i: u32) -> String {
+    format!("{}/{i}", delta_relpath(from, to))
+}
+
+impl Repo {
+    pub(crate) fn local_path(&self) -> Result<PathBuf, glib::Error> {
+        self.path().path().ok_or_else(|| {
+            glib::Error::new(
+                gio::IOErrorEnum::NotSupported,
+                "Repository has no local path",
+            )
+        })
+    }
+
+    /// Read the superblock of the static delta `name`, of the form `FROM-TO` or `TO`, as
+    /// returned by [`Repo::list_static_delta_names`].
+    pub fn static_delta_info(&self, name: &str) -> Result<StaticDeltaSuperblock, StaticDeltaError> {
+        let (from, to) = parse_delta_name(name)?;
+        let path = self
+            .local_path()?
+            .join(delta_relpath(from, to))
+            .join("superblock");
+        StaticDeltaSuperblock::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Read and decode part `index` of the static delta described by `superblock`.
+    #[cfg(feature = "static_delta_parts")]
+    pub fn static_delta_part(
+        &self,
+        superblock: &StaticDeltaSuperblock,
+        index: usize,
+    ) -> Result<StaticDeltaPart, StaticDeltaError> {
+        let info = superblock
+            .parts
+            .get(index)
+            .ok_or_else(|| format_error(format!("no part {index}")))?;
+        let relpath = part_relpath(superblock.from.as_deref(), &superblock.to, index as u32);
+        let data = match lookup(
+            &superblock.metadata,
+            &relpath,
+            Some(VariantTy::new("(yay)").unwrap()),
+        ) {
+            Some(v) => v.data().to_vec(),
+            None => std::fs::read(self.local_path()?.join(&relpath))?,
+        };
+        StaticDeltaPart::parse(&data, &info.objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "0e9b0ad7ce5a4c9e4e2fbc7f1e1b5e6f0c2a1d3b4e5f60718293a4b5c6d7e8f9";
+    const B: &str = "a0b1c2d3e4f5061728394a5b6c7d8e9f00112233445566778899aabbccddeeff";
+
+    #[test]
+    fn should_parse_delta_names() {
+        assert_eq!(parse_delta_name(A).unwrap(), (None, A));
+        let name = format!("{A}-{B}");
+        assert_eq!(parse_delta_name(&name).unwrap(), (Some(A), B));
+        assert!(matches!(
+            parse_delta_name("foo-bar"),
+            Err(StaticDeltaError::InvalidName(_))
+        ));
+    }
+
+    #[test]
+    fn should_compute_delta_paths() {
+        let to = Checksum::from_hex(B).unwrap().to_base64();
+        assert_eq!(
+            delta_relpath(None, B),
+            format!("deltas/{}/{}", &to[..2], &to[2..])
+        );
+        let from = Checksum::from_hex(A).unwrap().to_base64();
+        assert_eq!(
+            part_relpath(Some(A), B, 3),
+            format!("deltas/{}/{}-{}/3", &from[..2], &from[2..], to)
+        );
+    }
+
+    #[test]
+    fn should_parse_ops() {
+        let objects = vec![
+            ObjectName::new(A, ObjectType::DirMeta),
+            ObjectName::new(B, ObjectType::File),
+            ObjectName::new(A, ObjectType::File),
+        ];
+        let mut blob = vec![0u8; 300];
+        blob[100..132].copy_from_slice(&hex::decode(B).unwrap());
+        let ops: Vec<u8> = vec![
+            b'S', 10, 0, // metadata: length 10, offset 0
+            b'S', 0, 0, 20, 10, // content: mode 0, xattrs 0, length 20, offset 10
+            b'o', 0, 0, 0x80, 0x01, // open: size 128
+            b'r', 100, // read source at offset 100
+            b'w', 64, 0, // write 64 bytes from source
+            b'R', b'w', 64, 200, // write 64 bytes from payload
+            b'c',
+        ];
+        let payload = (
+            vec![(0u32, 0u32, (libc::S_IFREG | 0o644).to_be())],
+            vec![Xattrs::new()],
+            blob,
+            ops,
+        )
+            .to_variant();
+        let part = StaticDeltaPart::from_payload(payload.data(), &objects).unwrap();
+        assert_eq!(part.modes, vec![(0, 0, libc::S_IFREG | 0o644)]);
+        assert_eq!(part.payload_size, 300);
+        assert_eq!(part.ops.len(), 8);
+        assert_eq!(
+            part.ops[4],
+            StaticDeltaOp::Write {
+                offset: 0,
+                length: 64,
+                from_read_source: true
+            }
+        );
+        assert_eq!(
+            part.ops[3],
+            StaticDeltaOp::SetReadSource { source: B.into() }
+        );
+        assert_eq!(part.payload_bytes_per_object(), vec![10, 20, 96]);
+        #[cfg(feature = "static_delta_parts")]
+        {
+            let mut compressed = vec![b'x'];
+            xz2::read::XzEncoder::new(payload.data(), 6)
+                .read_to_end(&mut compressed)
+                .unwrap();
+            assert_eq!(StaticDeltaPart::parse(&compressed, &objects).unwrap(), part);
+        }
+    }
+
+    #[test]
+    fn should_reject_out_of_bounds_read_source() {
+        let objects = vec![ObjectName::new(A, ObjectType::File)];
+        for offset in [
+            vec![0xf0, 0x01],
+            vec![0xff; 9].into_iter().chain([0x01]).collect(),
+        ] {
+            let ops = [vec![b'r'], offset].concat();
+            let payload = (
+                Vec::<(u32, u32, u32)>::new(),
+                Vec::<Xattrs>::new(),
+                vec![0u8; 64],
+                ops,
+            )
+                .to_variant();
+            assert!(StaticDeltaPart::from_payload(payload.data(), &objects).is_err());
+        }
+    }
+}
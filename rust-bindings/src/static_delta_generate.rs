@@ -0,0 +1,379 @@
+//! Typed options for static delta generation.
+
+#[cfg(any(feature = "v2020_2", feature = "dox"))]
+use crate::{prelude::SignExt, Sign};
+use crate::{
+    Repo, StaticDeltaEndianness, StaticDeltaError, StaticDeltaGenerateOpt, StaticDeltaSuperblock,
+};
+use gio::prelude::*;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+// G_LITTLE_ENDIAN and G_BIG_ENDIAN
+const GLIB_LITTLE_ENDIAN: u32 = 1234;
+const GLIB_BIG_ENDIAN: u32 = 4321;
+
+/// Size statistics of a static delta.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StaticDeltaStats {
+    /// Number of parts.
+    pub n_parts: usize,
+    /// Total size of all parts, as stored.
+    pub parts_size: u64,
+    /// Total uncompressed size of all parts.
+    pub parts_uncompressed_size: u64,
+    /// Number of fallback objects.
+    pub n_fallback: usize,
+    /// Total size of all fallback objects, as fetched.
+    pub fallback_size: u64,
+    /// Total uncompressed size of all fallback objects.
+    pub fallback_uncompressed_size: u64,
+}
+
+impl StaticDeltaStats {
+    /// The total download size of the delta, including fallback objects.
+    pub fn total_size(&self) -> u64 {
+        self.parts_size + self.fallback_size
+    }
+}
+
+impl From<&StaticDeltaSuperblock> for StaticDeltaStats {
+    fn from(superblock: &StaticDeltaSuperblock) -> Self {
+        Self {
+            n_parts: superblock.parts.len(),
+            parts_size: superblock.parts_size(),
+            parts_uncompressed_size: superblock.parts_uncompressed_size(),
+            n_fallback: superblock.fallback.len(),
+            fallback_size: superblock.fallback_size(),
+            fallback_uncompressed_size: superblock.fallback_uncompressed_size(),
+        }
+    }
+}
+
+/// A static delta written by [`Repo::static_delta_generate_with_options`].
+#[derive(Debug)]
+pub struct StaticDeltaGenerated {
+    /// The name of the delta, i.e. `FROM-TO` or `TO`.
+    pub name: String,
+    /// The superblock file, if the delta was written outside the repository.
+    pub path: Option<PathBuf>,
+    /// The superblock of the generated delta.
+    pub superblock: StaticDeltaSuperblock,
+    /// Size statistics of the generated delta.
+    pub stats: StaticDeltaStats,
+}
+
+/// Options for [`Repo::static_delta_generate_with_options`].
+///
+/// Options left unset use the libostree defaults. Delta parts are always compressed with
+/// LZMA; libostree has no option to change the compression.
+#[derive(Debug, Clone)]
+pub struct StaticDeltaGenerateOptions {
+    opt: StaticDeltaGenerateOpt,
+    min_fallback_size: Option<u32>,
+    max_bsdiff_size: Option<u32>,
+    max_chunk_size: Option<u32>,
+    bsdiff: Option<bool>,
+    inline_parts: Option<bool>,
+    verbose: Option<bool>,
+    endianness: Option<StaticDeltaEndianness>,
+    filename: Option<PathBuf>,
+    sign_name: Option<String>,
+    sign_keys: Vec<String>,
+    metadata: Option<glib::Variant>,
+}
+
+impl Default for StaticDeltaGenerateOptions {
+    fn default() -> Self {
+        Self {
+            opt: StaticDeltaGenerateOpt::Major,
+            min_fallback_size: None,
+            max_bsdiff_size: None,
+            max_chunk_size: None,
+            bsdiff: None,
+            inline_parts: None,
+            verbose: None,
+            endianness: None,
+            filename: None,
+            sign_name: None,
+            sign_keys: Vec::new(),
+            metadata: None,
+        }
+    }
+}
+
+impl StaticDeltaGenerateOptions {
+    /// Create default options, generating a [`StaticDeltaGenerateOpt::Major`] delta.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the generation strategy.
+    pub fn opt(mut self, opt: StaticDeltaGenerateOpt) -> Self {
+        self.opt = opt;
+        self
+    }
+
+    /// Objects larger than this many megabytes are not included in the delta and must be
+    /// fetched individually. `0` disables fallback objects. The default is 4.
+    pub fn min_fallback_size(mut self, megabytes: u32) -> Self {
+        self.min_fallback_size = Some(megabytes);
+        self
+    }
+
+    /// Do not use bsdiff for files larger than this many megabytes. The default is 128.
+    pub fn max_bsdiff_size(mut self, megabytes: u32) -> Self {
+        self.max_bsdiff_size = Some(megabytes);
+        self
+    }
+
+    /// The maximum uncompressed size of a part, in megabytes. The default is 32.
+    pub fn max_chunk_size(mut self, megabytes: u32) -> Self {
+        self.max_chunk_size = Some(megabytes);
+        self
+    }
+
+    /// Whether to use bsdiff for modified files. Enabled by default.
+    pub fn bsdiff(mut self, enabled: bool) -> Self {
+        self.bsdiff = Some(enabled);
+        self
+    }
+
+    /// Whether to store the parts inline in the superblock.
+    pub fn inline_parts(mut self, inline: bool) -> Self {
+        self.inline_parts = Some(inline);
+        self
+    }
+
+    /// Whether libostree should print progress to standard output.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = Some(verbose);
+        self
+    }
+
+    /// The byte order of the sizes in the superblock. The default is the host byte order.
+    pub fn endianness(mut self, endianness: StaticDeltaEndianness) -> Self {
+        self.endianness = Some(endianness);
+        self
+    }
+
+    /// Write the superblock to `path` instead of the repository. The parts are written to
+    /// the same directory.
+    pub fn filename(mut self, path: impl AsRef<Path>) -> Self {
+        self.filename = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sign the superblock with `sign`, using the given secret keys in the format accepted
+    /// by [`SignExt::set_sk`](crate::prelude::SignExt::set_sk), e.g. base64 for ed25519.
+    #[cfg(any(feature = "v2020_2", feature = "dox"))]
+    #[cfg_attr(feature = "dox", doc(cfg(feature = "v2020_2")))]
+    pub fn sign<S: IsA<Sign>>(
+        self,
+        sign: &S,
+        secret_keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.sign_with_name(sign.as_ref().name().as_str(), secret_keys)
+    }
+
+    /// Sign the superblock with the signature engine named `name`, e.g. `ed25519`.
+    pub fn sign_with_name(
+        mut self,
+        name: &str,
+        secret_keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.sign_name = Some(name.to_string());
+        self.sign_keys = secret_keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the delta metadata, of type `a{sv}`.
+    pub fn metadata(mut self, metadata: &glib::Variant) -> Self {
+        self.metadata = Some(metadata.clone());
+        self
+    }
+
+    pub(crate) fn min_fallback_size_bytes(&self) -> u64 {
+        self.min_fallback_size.unwrap_or(4) as u64 * 1000 * 1000
+    }
+
+    pub(crate) fn max_bsdiff_size_bytes(&self) -> u64 {
+        self.max_bsdiff_size.unwrap_or(128) as u64 * 1000 * 1000
+    }
+
+    pub(crate) fn bsdiff_enabled(&self) -> bool {
+        self.bsdiff.unwrap_or(true)
+    }
+
+    /// Check the options for values libostree would reject or misinterpret.
+    pub fn validate(&self) -> Result<(), StaticDeltaError> {
+        let invalid = |msg: &str| Err(StaticDeltaError::InvalidOption(msg.to_string()));
+        if self.max_chunk_size == Some(0) {
+            return invalid("max-chunk-size must be at least 1 megabyte");
+        }
+        if let Some(filename) = self.filename.as_deref() {
+            let bytes = filename.as_os_str().as_bytes();
+            if bytes.is_empty() || bytes.contains(&0) {
+                return invalid("filename must be non-empty and must not contain NUL");
+            }
+            if filename.file_name().is_none() {
+                return invalid("filename must name a file");
+            }
+        }
+        if let Some(name) = self.sign_name.as_deref() {
+            if name.is_empty() {
+                return invalid("sign-name must be non-empty");
+            }
+            if self.sign_keys.is_empty() {
+                return invalid("signing requires at least one key");
+            }
+        }
+        if let Some(metadata) = self.metadata.as_ref() {
+            if !metadata.is_type(glib::VariantTy::VARDICT) {
+                return invalid("metadata must be of type a{sv}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert to the `a{sv}` params variant accepted by [`Repo::static_delta_generate`].
+    pub fn to_variant(&self) -> glib::Variant {
+        let dict = glib::VariantDict::new(None);
+        if let Some(v) = self.min_fallback_size {
+            dict.insert("min-fallback-size", v);
+        }
+        if let Some(v) = self.max_bsdiff_size {
+            dict.insert("max-bsdiff-size", v);
+        }
+        if let Some(v) = self.max_chunk_size {
+            dict.insert("max-chunk-size", v);
+        }
+        if let Some(v) = self.bsdiff {
+            dict.insert("bsdiff-enabled", v);
+        }
+        if let Some(v) = self.inline_parts {
+            dict.insert("inline-parts", v);
+        }
+        if let Some(v) = self.verbose {
+            dict.insert("verbose", v);
+        }
+        if let Some(v) = self.endianness {
+            let v = match v {
+                StaticDeltaEndianness::Little => GLIB_LITTLE_ENDIAN,
+                StaticDeltaEndianness::Big => GLIB_BIG_ENDIAN,
+            };
+            dict.insert("endianness", v);
+        }
+        if let Some(v) = self.filename.as_deref() {
+            dict.insert_value("filename", &bytestring(v.as_os_str().as_bytes()));
+        }
+        if let Some(v) = self.sign_name.as_deref() {
+            dict.insert_value("sign-name", &bytestring(v.as_bytes()));
+            dict.insert_value("sign-key-ids", &self.sign_keys.to_variant());
+        }
+        dict.end()
+    }
+}
+
+/// Encode a `^ay` bytestring, which includes the terminating NUL.
+fn bytestring(bytes: &[u8]) -> glib::Variant {
+    let mut v = bytes.to_vec();
+    v.push(0);
+    v.to_variant()
+}
+
+impl Repo {
+    /// Generate a static delta from `from`, or from scratch if `None`, to `to`.
+    ///
+    /// Returns the name and size statistics of the generated delta, read back from its
+    /// superblock.
+    pub fn static_delta_generate_with_options<P: IsA<gio::Cancellable>>(
+        &self,
+        from: Option<&str>,
+        to: &str,
+        options: &StaticDeltaGenerateOptions,
+        cancellable: Option<&P>,
+    ) -> Result<StaticDeltaGenerated, StaticDeltaError> {
+        options.validate()?;
+        for c in from.iter().chain(std::iter::once(&to)) {
+            crate::validate_checksum_string(c)?;
+        }
+        self.static_delta_generate(
+            options.opt,
+            from,
+            to,
+            options.metadata.as_ref(),
+            Some(&options.to_variant()),
+            cancellable,
+        )?;
+        let (superblock, path) = match options.filename.as_deref() {
+            Some(path) => (
+                StaticDeltaSuperblock::from_bytes(&std::fs::read(path)?)?,
+                Some(path.to_path_buf()),
+            ),
+            None => {
+                let name = match from {
+                    Some(from) => format!("{from}-{to}"),
+                    None => to.to_string(),
+                };
+                (self.static_delta_info(&name)?, None)
+            }
+        };
+        Ok(StaticDeltaGenerated {
+            name: superblock.name(),
+            path,
+            stats: StaticDeltaStats::from(&superblock),
+            superblock,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encode_params() {
+        let v = StaticDeltaGenerateOptions::new()
+            .max_chunk_size(8)
+            .bsdiff(false)
+            .endianness(StaticDeltaEndianness::Big)
+            .filename("/tmp/delta")
+            .sign_with_name("ed25519", ["secret"])
+            .to_variant();
+        let dict = glib::VariantDict::new(Some(&v));
+        assert_eq!(dict.lookup::<u32>("max-chunk-size").unwrap(), Some(8));
+        assert_eq!(dict.lookup::<u32>("min-fallback-size").unwrap(), None);
+        assert_eq!(dict.lookup::<bool>("bsdiff-enabled").unwrap(), Some(false));
+        assert_eq!(dict.lookup::<u32>("endianness").unwrap(), Some(4321));
+        assert_eq!(
+            dict.lookup::<Vec<u8>>("filename").unwrap().unwrap(),
+            b"/tmp/delta\0"
+        );
+        assert_eq!(
+            dict.lookup::<Vec<u8>>("sign-name").unwrap().unwrap(),
+            b"ed25519\0"
+        );
+        assert_eq!(
+            dict.lookup::<Vec<String>>("sign-key-ids").unwrap().unwrap(),
+            vec!["secret"]
+        );
+    }
+
+    #[test]
+    fn should_validate_options() {
+        let invalid = |o: StaticDeltaGenerateOptions| {
+            matches!(o.validate(), Err(StaticDeltaError::InvalidOption(_)))
+        };
+        assert!(StaticDeltaGenerateOptions::new().validate().is_ok());
+        assert!(invalid(StaticDeltaGenerateOptions::new().max_chunk_size(0)));
+        assert!(invalid(StaticDeltaGenerateOptions::new().filename("")));
+        assert!(invalid(StaticDeltaGenerateOptions::new().filename("/")));
+        assert!(invalid(
+            StaticDeltaGenerateOptions::new().sign_with_name("ed25519", Vec::<String>::new())
+        ));
+        assert!(invalid(
+            StaticDeltaGenerateOptions::new().metadata(&1u32.to_variant())
+        ));
+    }
+}
@@ -0,0 +1,258 @@
+//! Maintenance of static deltas and their indexes.
+//!
+//! Repositories with `core.indexed-deltas` enabled store, for each target commit, an index
+//! file under `delta-indexes/` listing the deltas to that commit along with the SHA256 digest
+//! of their superblocks. Clients use these instead of the summary to find deltas.
+
+use crate::static_delta::{delta_relpath, parse_delta_name};
+use crate::{Checksum, ObjectType, Repo, StaticDeltaError};
+use gio::prelude::*;
+use glib::{Variant, VariantTy};
+use std::collections::{BTreeMap, HashSet};
+
+/// Key of the deltas in an index file, as in the summary.
+const SUMMARY_STATIC_DELTAS: &str = "ostree.static-deltas";
+const INDEXES_DIR: &str = "delta-indexes";
+const INDEX_SUFFIX: &str = ".index";
+
+/// The index of the static deltas to a commit, read from `delta-indexes/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticDeltaIndex {
+    /// The target commit.
+    pub to: String,
+    /// The superblock digest of each delta, keyed by delta name (`FROM-TO` or `TO`).
+    pub deltas: BTreeMap<String, Vec<u8>>,
+}
+
+impl StaticDeltaIndex {
+    /// Parse the contents of the index file for `to`.
+    pub fn from_bytes(to: &str, data: &[u8]) -> Result<Self, StaticDeltaError> {
+        let v = Variant::from_bytes_with_type(&glib::Bytes::from(data), VariantTy::VARDICT);
+        let deltas = glib::VariantDict::new(Some(&v))
+            .lookup_value(SUMMARY_STATIC_DELTAS, Some(VariantTy::VARDICT))
+            .ok_or_else(|| {
+                StaticDeltaError::Format(format!("index for {to} has no {SUMMARY_STATIC_DELTAS}"))
+            })?;
+        let mut ret = BTreeMap::new();
+        for entry in deltas.iter() {
+            let name = entry.child_value(0).str().unwrap().to_string();
+            let digest = entry.child_value(1).as_variant().unwrap();
+            let digest = digest.fixed_array::<u8>().map_err(|_| {
+                StaticDeltaError::Format(format!("invalid digest for delta {name}"))
+            })?;
+            if parse_delta_name(&name)?.1 != to {
+                return Err(StaticDeltaError::Format(format!(
+                    "delta {name} in index for {to}"
+                )));
+            }
+            ret.insert(name, digest.to_vec());
+        }
+        Ok(Self {
+            to: to.to_string(),
+            deltas: ret,
+        })
+    }
+
+    /// The source commits of the indexed deltas; `None` stands for a delta from scratch.
+    pub fn froms(&self) -> impl Iterator<Item = Option<&str>> {
+        self.deltas
+            .keys()
+            .map(|name| name.split_once('-').map(|(from, _)| from))
+    }
+}
+
+/// Mirrors `_ostree_get_relative_static_delta_index_path`.
+pub(crate) fn index_relpath(to: &str) -> String {
+    let to = Checksum::from_hex(to).unwrap().to_base64();
+    format!("{INDEXES_DIR}/{}/{}{INDEX_SUFFIX}", &to[..2], &to[2..])
+}
+
+impl Repo {
+    /// Regenerate the delta index for `target`, or for all commits if `None`.
+    ///
+    /// This also enables `core.indexed-deltas` in the repository configuration. Indexes for
+    /// commits which no longer have any deltas are removed.
+    #[cfg(any(feature = "v2020_8", feature = "dox"))]
+    #[cfg_attr(feature = "dox", doc(cfg(feature = "v2020_8")))]
+    #[doc(alias = "ostree_repo_static_delta_reindex")]
+    pub fn reindex_static_deltas<P: IsA<gio::Cancellable>>(
+        &self,
+        target: Option<&str>,
+        cancellable: Option<&P>,
+    ) -> Result<(), glib::Error> {
+        use glib::translate::*;
+        unsafe {
+            let mut error = std::ptr::null_mut();
+            let _ = ffi::ostree_repo_static_delta_reindex(
+                self.to_glib_none().0,
+                ffi::OSTREE_STATIC_DELTA_INDEX_FLAGS_NONE,
+                target.to_glib_none().0,
+                cancellable.map(|p| p.as_ref()).to_glib_none().0,
+                &mut error,
+            );
+            if error.is_null() {
+                Ok(())
+            } else {
+                Err(from_glib_full(error))
+            }
+        }
+    }
+
+    /// Read the delta index for `to`, returning `None` if there is none.
+    pub fn static_delta_index(
+        &self,
+        to: &str,
+    ) -> Result<Option<StaticDeltaIndex>, StaticDeltaError> {
+        crate::validate_checksum_string(to)?;
+        let path = self.local_path()?.join(index_relpath(to));
+        match std::fs::read(path) {
+            Ok(data) => StaticDeltaIndex::from_bytes(to, &data).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Read all delta indexes in the repository, sorted by target commit.
+    pub fn static_delta_indexes(&self) -> Result<Vec<StaticDeltaIndex>, StaticDeltaError> {
+        let dir = self.local_path()?.join(INDEXES_DIR);
+        let mut ret = Vec::new();
+        let prefixes = match std::fs::read_dir(dir) {
+            Ok(d) => d,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ret),
+            Err(e) => return Err(e.into()),
+        };
+        for prefix in prefixes {
+            let prefix = prefix?;
+            if !prefix.file_type()?.is_dir() {
+                continue;
+            }
+            for entry in std::fs::read_dir(prefix.path())? {
+                let entry = entry?;
+                let name = entry.file_name();
+                let Some(rest) = name.to_str().and_then(|n| n.strip_suffix(INDEX_SUFFIX)) else {
+                    continue;
+                };
+                let b64 = format!("{}{rest}", prefix.file_name().to_string_lossy());
+                // Ignore stray files, like libostree does.
+                let Ok(to) = Checksum::from_base64(&b64) else {
+                    continue;
+                };
+                let to = to.to_hex();
+                let data = std::fs::read(entry.path())?;
+                ret.push(StaticDeltaIndex::from_bytes(&to, &data)?);
+            }
+        }
+        ret.sort_by(|a, b| a.to.cmp(&b.to));
+        Ok(ret)
+    }
+
+    /// Return the names of the static deltas whose source commit is not reachable from any
+    /// ref by following commit parents. Deltas from scratch are always considered reachable.
+    pub fn unreachable_static_deltas<P: IsA<gio::Cancellable>>(
+        &self,
+        cancellable: Option<&P>,
+    ) -> Result<Vec<String>, StaticDeltaError> {
+        let mut reachable = HashSet::new();
+        let mut refs = self
+            .list_refs(None, cancellable)?
+            .into_values()
+            .collect::<Vec<_>>();
+        refs.sort();
+        for rev in refs {
+            let mut next = Some(rev);
+            while let Some(commit) = next.take() {
+                if !reachable.insert(commit.clone()) {
+                    break;
+                }
+                // The history may be truncated.
+                if let Some(v) = self.load_variant_if_exists(ObjectType::Commit, &commit)? {
+                    next = crate::commit_get_parent(&v).map(|p| p.to_string());
+                }
+            }
+        }
+        let mut ret = Vec::new();
+        for name in self.list_static_delta_names(cancellable)? {
+            if let (Some(from), _) = parse_delta_name(&name)? {
+                if !reachable.contains(from) {
+                    ret.push(name.to_string());
+                }
+            }
+        }
+        ret.sort();
+        Ok(ret)
+    }
+
+    /// Delete the static deltas returned by [`Repo::unreachable_static_deltas`], returning
+    /// their names.
+    ///
+    /// If `core.indexed-deltas` is enabled, the indexes of the affected target commits are
+    /// regenerated; without the `v2020_8` feature, call `ostree static-delta reindex`
+    /// afterwards instead.
+    pub fn retain_reachable_static_deltas<P: IsA<gio::Cancellable>>(
+        &self,
+        cancellable: Option<&P>,
+    ) -> Result<Vec<String>, StaticDeltaError> {
+        let unreachable = self.unreachable_static_deltas(cancellable)?;
+        let repo_path = self.local_path()?;
+        for name in unreachable.iter() {
+            if let Some(c) = cancellable {
+                c.as_ref().set_error_if_cancelled()?;
+            }
+            let (from, to) = parse_delta_name(name)?;
+            std::fs::remove_dir_all(repo_path.join(delta_relpath(from, to)))?;
+        }
+        #[cfg(any(feature = "v2020_8", feature = "dox"))]
+        if self
+            .config()
+            .boolean("core", "indexed-deltas")
+            .unwrap_or(false)
+        {
+            let mut targets = unreachable
+                .iter()
+                .map(|name| parse_delta_name(name).map(|(_, to)| to))
+                .collect::<Result<Vec<_>, _>>()?;
+            targets.sort();
+            targets.dedup();
+            for to in targets {
+                self.reindex_static_deltas(Some(to), cancellable)?;
+            }
+        }
+        Ok(unreachable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "0e9b0ad7ce5a4c9e4e2fbc7f1e1b5e6f0c2a1d3b4e5f60718293a4b5c6d7e8f9";
+    const B: &str = "a0b1c2d3e4f5061728394a5b6c7d8e9f00112233445566778899aabbccddeeff";
+
+    #[test]
+    fn should_parse_index() {
+        let deltas = glib::VariantDict::new(None);
+        deltas.insert_value(&format!("{A}-{B}"), &vec![1u8; 32].to_variant());
+        deltas.insert_value(B, &vec![2u8; 32].to_variant());
+        let index = glib::VariantDict::new(None);
+        index.insert_value(SUMMARY_STATIC_DELTAS, &deltas.end());
+        let index = StaticDeltaIndex::from_bytes(B, index.end().data()).unwrap();
+        assert_eq!(index.deltas.len(), 2);
+        assert_eq!(index.deltas[B], vec![2u8; 32]);
+        let mut froms = index.froms().collect::<Vec<_>>();
+        froms.sort();
+        assert_eq!(froms, vec![None, Some(A)]);
+        assert!(matches!(
+            StaticDeltaIndex::from_bytes(A, glib::VariantDict::new(None).end().data()),
+            Err(StaticDeltaError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn should_compute_index_path() {
+        let to = Checksum::from_hex(B).unwrap().to_base64();
+        assert_eq!(
+            index_relpath(B),
+            format!("delta-indexes/{}/{}.index", &to[..2], &to[2..])
+        );
+    }
+}
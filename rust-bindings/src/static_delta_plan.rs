@@ -0,0 +1,432 @@
+//! Size estimation for static deltas.
+//!
+//! This mirrors the analysis done by `ostree_repo_static_delta_generate` in
+//! `ostree-repo-static-delta-compilation.c` and `ostree-repo-static-delta-compilation-analysis.c`:
+//! new content objects are matched to similar objects in the source commit by size and
+//! basename, and matched pairs are checked for shared rollsum chunks. Nothing is written to
+//! the repository.
+
+use crate::{ObjectType, Repo, StaticDeltaGenerateOptions, TreeVariantType};
+use gio::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+
+/// Content objects whose sizes differ by at most this percentage may be similar.
+const SIMILARITY_THRESHOLD_PERCENT: u64 = 30;
+/// Rollsum is used if at least this percentage of the chunks of the new object match.
+const ROLLSUM_MIN_MATCH_PERCENT: usize = 50;
+const ROLLSUM_BLOB_MAX: usize = 8192 * 4;
+const BUP_BLOBBITS: u32 = 13;
+const BUP_BLOBSIZE: u32 = 1 << BUP_BLOBBITS;
+const BUP_WINDOWSIZE: usize = 64;
+const ROLLSUM_CHAR_OFFSET: u32 = 31;
+
+/// A number of objects and their total size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StaticDeltaPlanCount {
+    /// Number of objects.
+    pub objects: u32,
+    /// Total uncompressed size of the objects.
+    pub bytes: u64,
+}
+
+impl StaticDeltaPlanCount {
+    fn add(&mut self, bytes: u64) {
+        self.objects += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// An estimate of the contents and size of a static delta, returned by
+/// [`Repo::plan_static_delta`].
+///
+/// All sizes are uncompressed; the parts of a generated delta are additionally compressed
+/// with LZMA.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StaticDeltaPlan {
+    /// Metadata objects which are not in the source commit, excluding the target commit,
+    /// which is stored in the superblock.
+    pub new_metadata: StaticDeltaPlanCount,
+    /// Regular file and symlink content objects which are not in the source commit.
+    pub new_content: StaticDeltaPlanCount,
+    /// New content objects for which a similar object exists in the source commit. These
+    /// are a subset of `new_content`.
+    pub modified_content: StaticDeltaPlanCount,
+    /// Content objects shared with the source commit.
+    pub unchanged_content: StaticDeltaPlanCount,
+    /// Modified content objects which will be encoded with rollsum.
+    pub rollsum: StaticDeltaPlanCount,
+    /// Bytes of the rollsum-encoded objects which are copied from the source objects.
+    pub rollsum_matched_bytes: u64,
+    /// Modified content objects which will be encoded with bsdiff. The size of a bsdiff
+    /// cannot be known without computing it, so these count with their full size.
+    pub bsdiff: StaticDeltaPlanCount,
+    /// New content objects too large to be included in the delta, which are fetched
+    /// individually.
+    pub fallback: StaticDeltaPlanCount,
+}
+
+impl StaticDeltaPlan {
+    /// The estimated total size of the delta, including fallback objects.
+    pub fn estimated_size(&self) -> u64 {
+        self.loose_size() - self.rollsum_matched_bytes
+    }
+
+    /// The total size of the new objects when fetched individually.
+    pub fn loose_size(&self) -> u64 {
+        self.new_metadata.bytes + self.new_content.bytes
+    }
+
+    /// The fraction of the loose size saved by the delta, between 0 and 1.
+    pub fn savings(&self) -> f64 {
+        match self.loose_size() {
+            0 => 0.0,
+            loose => self.rollsum_matched_bytes as f64 / loose as f64,
+        }
+    }
+}
+
+/// A regular file content object and the names it appears under in a commit.
+#[derive(Debug)]
+struct SizeNames {
+    checksum: String,
+    size: u64,
+    basenames: Vec<String>,
+}
+
+impl SizeNames {
+    /// Mirrors `sizename_is_delta_candidate`.
+    fn is_delta_candidate(&self) -> bool {
+        self.size > 0
+            && !self.basenames.iter().any(|name| {
+                matches!(
+                    name.rsplit_once('.').map(|(_, ext)| ext),
+                    Some("xz") | Some("bz2")
+                )
+            })
+    }
+}
+
+/// Mirrors `string_array_nonempty_intersection`.
+fn basenames_intersect(a: &[String], b: &[String], fuzzy: bool) -> bool {
+    a.iter().any(|a| {
+        b.iter().any(|b| match (a.find('.'), b.find('.')) {
+            (Some(ia), Some(ib)) if fuzzy && ib > 0 && ia == ib => a[..ia] == b[..ib],
+            _ => a == b,
+        })
+    })
+}
+
+/// Mirrors `_ostree_delta_compute_similar_objects`: returns a map from new objects to
+/// similar objects in the source commit.
+fn similar_objects(from: &[SizeNames], to: &[SizeNames]) -> HashMap<String, String> {
+    let mut ret = HashMap::new();
+    let mut lower = 0;
+    for to in to.iter().filter(|s| s.is_delta_candidate()) {
+        let min = to.size * (100 - SIMILARITY_THRESHOLD_PERCENT) / 100;
+        let max = to.size * (100 + SIMILARITY_THRESHOLD_PERCENT) / 100;
+        'fuzzy: for fuzzy in [false, true] {
+            for from in &from[lower..] {
+                if !from.is_delta_candidate() {
+                    continue;
+                }
+                if from.size < min {
+                    lower += 1;
+                    continue;
+                }
+                if from.size > max {
+                    break;
+                }
+                if basenames_intersect(&from.basenames, &to.basenames, fuzzy) {
+                    ret.insert(to.checksum.clone(), from.checksum.clone());
+                    break 'fuzzy;
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// Mirrors `bupsplit_find_ofs`: returns the length of the next chunk, or 0 if there is no
+/// split point in `buf`.
+fn bupsplit_find_ofs(buf: &[u8]) -> usize {
+    let mut s1 = BUP_WINDOWSIZE as u32 * ROLLSUM_CHAR_OFFSET;
+    let mut s2 = (BUP_WINDOWSIZE * (BUP_WINDOWSIZE - 1)) as u32 * ROLLSUM_CHAR_OFFSET;
+    let mut window = [0u8; BUP_WINDOWSIZE];
+    for (count, &add) in buf.iter().enumerate() {
+        let wofs = count % BUP_WINDOWSIZE;
+        let drop = window[wofs];
+        s1 = s1.wrapping_add(add as u32).wrapping_sub(drop as u32);
+        s2 = s2
+            .wrapping_add(s1)
+            .wrapping_sub((BUP_WINDOWSIZE as u32).wrapping_mul(drop as u32 + ROLLSUM_CHAR_OFFSET));
+        window[wofs] = add;
+        if s2 & (BUP_BLOBSIZE - 1) == BUP_BLOBSIZE - 1 {
+            return count + 1;
+        }
+    }
+    0
+}
+
+/// Mirrors `rollsum_chunks_crc32`, without the checksums.
+fn rollsum_chunks(buf: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut end = false;
+    while start < buf.len() {
+        let remaining = buf.len() - start;
+        let mut len = 0;
+        if !end {
+            len = bupsplit_find_ofs(&buf[start..start + remaining.min(i32::MAX as usize)]);
+            if len == 0 {
+                end = true;
+            }
+        }
+        if len == 0 || len > ROLLSUM_BLOB_MAX {
+            len = remaining.min(ROLLSUM_BLOB_MAX);
+        }
+        chunks.push(&buf[start..start + len]);
+        start += len;
+    }
+    chunks
+}
+
+/// Mirrors `_ostree_compute_rollsum_matches`: returns the number of chunks of `to`, the
+/// number of them found in `from`, and their total size.
+fn rollsum_matches(from: &[u8], to: &[u8]) -> (usize, usize, u64) {
+    let from_chunks = rollsum_chunks(from).into_iter().collect::<HashSet<_>>();
+    let to_chunks = rollsum_chunks(to);
+    let matched = to_chunks
+        .iter()
+        .filter(|c| from_chunks.contains(*c))
+        .collect::<Vec<_>>();
+    let size = matched.iter().map(|c| c.len() as u64).sum();
+    (to_chunks.len(), matched.len(), size)
+}
+
+impl Repo {
+    /// Estimate the size of the static delta from `from`, or from scratch if `None`, to
+    /// `to`, without writing anything.
+    ///
+    /// The size-related settings of `options` are taken into account, i.e.
+    /// [`min_fallback_size`](StaticDeltaGenerateOptions::min_fallback_size),
+    /// [`max_bsdiff_size`](StaticDeltaGenerateOptions::max_bsdiff_size) and
+    /// [`bsdiff`](StaticDeltaGenerateOptions::bsdiff).
+    pub fn plan_static_delta<P: IsA<gio::Cancellable>>(
+        &self,
+        from: Option<&str>,
+        to: &str,
+        options: &StaticDeltaGenerateOptions,
+        cancellable: Option<&P>,
+    ) -> Result<StaticDeltaPlan, glib::Error> {
+        let mut plan = StaticDeltaPlan::default();
+        let from_reachable = match from {
+            Some(from) => self.traverse_commit(from, 0, cancellable)?,
+            None => HashSet::new(),
+        };
+        let to_reachable = self.traverse_commit(to, 0, cancellable)?;
+
+        let mut new_regfiles = HashSet::new();
+        for name in to_reachable.iter() {
+            let checksum = name.checksum();
+            let objtype = name.object_type();
+            if objtype == ObjectType::File {
+                let (info, _) = self.query_file(checksum, cancellable)?;
+                let size = info.size() as u64;
+                if from_reachable.contains(name) {
+                    plan.unchanged_content.add(size);
+                    continue;
+                }
+                plan.new_content.add(size);
+                if info.file_type() == gio::FileType::Regular {
+                    new_regfiles.insert(checksum.to_string());
+                }
+            } else if !from_reachable.contains(name)
+                && (objtype != ObjectType::Commit || checksum != to)
+            {
+                plan.new_metadata
+                    .add(self.load_variant(objtype, checksum)?.size() as u64);
+            }
+        }
+
+        let modified = match from {
+            Some(from) => {
+                let from_sizes = self.content_sizenames(from, None, cancellable)?;
+                let to_sizes = self.content_sizenames(to, Some(&new_regfiles), cancellable)?;
+                similar_objects(&from_sizes, &to_sizes)
+            }
+            None => HashMap::new(),
+        };
+
+        let mut optimized = HashSet::new();
+        let mut modified = modified.into_iter().collect::<Vec<_>>();
+        modified.sort();
+        for (to_checksum, from_checksum) in modified.iter() {
+            let (from_info, _) = self.query_file(from_checksum, cancellable)?;
+            let (to_info, _) = self.query_file(to_checksum, cancellable)?;
+            let (from_size, to_size) = (from_info.size() as u64, to_info.size() as u64);
+            plan.modified_content.add(to_size);
+            // Only objects readable by any client are used as a source.
+            if from_info.attribute_uint32("unix::mode") & libc::S_IROTH == 0 {
+                continue;
+            }
+            let from_data = self.read_content(from_checksum, cancellable)?;
+            let to_data = self.read_content(to_checksum, cancellable)?;
+            let (total, matched, matched_size) = rollsum_matches(&from_data, &to_data);
+            if total > 0 && matched * 100 / total >= ROLLSUM_MIN_MATCH_PERCENT {
+                plan.rollsum.add(to_size);
+                plan.rollsum_matched_bytes += matched_size;
+                optimized.insert(to_checksum.as_str());
+            } else if options.bsdiff_enabled()
+                && from_size + to_size <= options.max_bsdiff_size_bytes()
+            {
+                plan.bsdiff.add(to_size);
+                optimized.insert(to_checksum.as_str());
+            }
+        }
+
+        let min_fallback_size = options.min_fallback_size_bytes();
+        if min_fallback_size > 0 {
+            for checksum in new_regfiles.iter() {
+                if optimized.contains(checksum.as_str()) {
+                    continue;
+                }
+                let (_, size) = self.load_object_stream(ObjectType::File, checksum, cancellable)?;
+                if size > min_fallback_size {
+                    plan.fallback.add(size);
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    fn read_content<P: IsA<gio::Cancellable>>(
+        &self,
+        checksum: &str,
+        cancellable: Option<&P>,
+    ) -> Result<Vec<u8>, glib::Error> {
+        let (stream, _, _) = self.load_file(checksum, cancellable)?;
+        let mut buf = Vec::new();
+        if let Some(stream) = stream {
+            stream.into_read().read_to_end(&mut buf).map_err(|e| {
+                glib::Error::new(gio::IOErrorEnum::Failed, &format!("{checksum}: {e}"))
+            })?;
+        }
+        Ok(buf)
+    }
+
+    /// Mirrors `build_content_sizenames_filtered`: collect the regular files of `commit`,
+    /// optionally restricted to `include`, sorted by size.
+    fn content_sizenames<P: IsA<gio::Cancellable>>(
+        &self,
+        commit: &str,
+        include: Option<&HashSet<String>>,
+        cancellable: Option<&P>,
+    ) -> Result<Vec<SizeNames>, glib::Error> {
+        let commit = self.load_variant(ObjectType::Commit, commit)?;
+        let root = hex::encode(commit.child_value(6).fixed_array::<u8>().unwrap());
+        let mut map = HashMap::new();
+        let mut skipped = HashSet::new();
+        let mut trees = vec![root];
+        while let Some(tree) = trees.pop() {
+            let (files, dirs) = self
+                .load_variant(ObjectType::DirTree, &tree)?
+                .get::<TreeVariantType>()
+                .unwrap();
+            for (name, checksum) in files {
+                let checksum = hex::encode(checksum);
+                if include.is_some_and(|i| !i.contains(&checksum)) || skipped.contains(&checksum) {
+                    continue;
+                }
+                if !map.contains_key(&checksum) {
+                    let (info, _) = self.query_file(&checksum, cancellable)?;
+                    if info.file_type() != gio::FileType::Regular {
+                        skipped.insert(checksum);
+                        continue;
+                    }
+                    map.insert(
+                        checksum.clone(),
+                        SizeNames {
+                            checksum: checksum.clone(),
+                            size: info.size() as u64,
+                            basenames: Vec::new(),
+                        },
+                    );
+                }
+                map.get_mut(&checksum).unwrap().basenames.push(name);
+            }
+            trees.extend(dirs.into_iter().map(|(_, tree, _)| hex::encode(tree)));
+        }
+        let mut ret = map.into_values().collect::<Vec<_>>();
+        ret.sort_by(|a, b| (a.size, &a.checksum).cmp(&(b.size, &b.checksum)));
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sizenames(checksum: &str, size: u64, names: &[&str]) -> SizeNames {
+        SizeNames {
+            checksum: checksum.into(),
+            size,
+            basenames: names.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn should_match_similar_objects() {
+        let from = vec![
+            sizenames("a", 50, &["libfoo.so.1"]),
+            sizenames("b", 100, &["libbar.so.1"]),
+            sizenames("c", 100, &["data.xz"]),
+        ];
+        let to = vec![
+            sizenames("d", 110, &["libbar.so.2"]),
+            sizenames("e", 100, &["data.xz"]),
+            sizenames("f", 1000, &["libfoo.so.1"]),
+        ];
+        let similar = similar_objects(&from, &to);
+        assert_eq!(similar.len(), 1);
+        assert_eq!(similar["d"], "b");
+        assert!(basenames_intersect(
+            &["libbar.so.1".into()],
+            &["libbar.so.2".into()],
+            true
+        ));
+        assert!(!basenames_intersect(
+            &["libbar.so.1".into()],
+            &["libbar.so.2".into()],
+            false
+        ));
+    }
+
+    #[test]
+    fn should_find_rollsum_matches() {
+        // Deterministic pseudo-random data, so that there are split points.
+        let mut state = 0x2545f491u32;
+        let data = (0..256 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        let chunks = rollsum_chunks(&data);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.len() <= ROLLSUM_BLOB_MAX));
+        assert_eq!(chunks.iter().map(|c| c.len()).sum::<usize>(), data.len());
+
+        let (total, matched, size) = rollsum_matches(&data, &data);
+        assert_eq!(total, matched);
+        assert_eq!(size, data.len() as u64);
+
+        let mut modified = data.clone();
+        modified[1000] ^= 0xff;
+        let (total, matched, size) = rollsum_matches(&data, &modified);
+        assert!(matched < total && matched * 100 / total >= ROLLSUM_MIN_MATCH_PERCENT);
+        assert!(size < data.len() as u64);
+    }
+}
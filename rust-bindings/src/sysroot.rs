@@ -73,6 +73,17 @@ impl Sysroot {
     }
 }
 
+/// Releases the sysroot lock when dropped.
+#[cfg(any(feature = "v2018_3", feature = "dox"))]
+pub(crate) struct SysrootLockGuard<'a>(pub(crate) &'a Sysroot);
+
+#[cfg(any(feature = "v2018_3", feature = "dox"))]
+impl Drop for SysrootLockGuard<'_> {
+    fn drop(&mut self) {
+        self.0.unlock();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use gio::prelude::FileExt;
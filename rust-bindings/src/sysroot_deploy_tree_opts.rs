@@ -11,6 +11,11 @@ pub struct SysrootDeployTreeOpts<'a> {
     pub override_kernel_argv: Option<&'a [&'a str]>,
     /// Paths to initramfs files to overlay.
     pub overlay_initrds: Option<&'a [&'a str]>,
+    /// Count boot attempts, with this many tries, for the boot entry of the deployment.
+    /// libostree reads this from the repository configuration when writing boot entries,
+    /// so it is only applied by
+    /// [`Sysroot::deploy_tree_with_boot_counting`](crate::Sysroot::deploy_tree_with_boot_counting).
+    pub boot_counting_tries: Option<u32>,
 }
 
 type OptionStrSliceStorage<'a> =
@@ -81,6 +86,7 @@ mod tests {
             locked: true,
             override_kernel_argv: Some(&override_kernel_argv),
             overlay_initrds: Some(&overlay_initrds),
+            boot_counting_tries: Some(3),
         };
         let stash = options.to_glib_none();
         let ptr = stash.0;
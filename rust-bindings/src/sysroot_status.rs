@@ -0,0 +1,348 @@
+//! A structured equivalent of `ostree admin status`.
+
+#[cfg(feature = "v2020_2")]
+use crate::{prelude::SignExt, Sign};
+use crate::{CommitMetadata, Deployment, ObjectType, Repo, Sysroot};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The state of the signatures of a deployment commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case", tag = "state"))]
+pub enum SignatureState {
+    /// Neither `gpg-verify` nor `sign-verify` is enabled for the origin remote, or the
+    /// deployment has no remote.
+    Unverified,
+    /// The commit has valid signatures.
+    Valid {
+        /// The number of valid GPG signatures, plus the number of `sign-verify` signature
+        /// types the commit is validly signed with.
+        valid: u32,
+        /// The total number of GPG signatures, plus the number of `sign-verify` signature
+        /// types with keys configured.
+        total: u32,
+    },
+    /// Signature verification is enabled, but failed.
+    Invalid {
+        /// The verification error.
+        message: String,
+    },
+}
+
+/// The status of a deployment, as part of a [`SysrootStatus`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DeploymentStatus {
+    /// The stateroot.
+    pub stateroot: String,
+    /// The commit checksum.
+    pub checksum: String,
+    /// The deployment serial.
+    pub deployserial: i32,
+    /// The `version` commit metadata, if the commit is present and has one.
+    pub version: Option<String>,
+    /// The refspec or container image reference of the origin file, if it can be parsed.
+    pub origin: Option<String>,
+    /// Whether this is the booted deployment.
+    pub booted: bool,
+    /// Whether this is the staged deployment, which is written at shutdown.
+    pub staged: bool,
+    /// Whether this deployment will be booted next instead of the booted one.
+    pub pending: bool,
+    /// Whether this deployment is the rollback target of the booted stateroot.
+    pub rollback: bool,
+    /// Whether the deployment is pinned.
+    pub pinned: bool,
+    /// The unlocked state, e.g. `none` or `development`.
+    pub unlocked: String,
+    /// Whether finalization of the staged deployment is locked.
+    pub finalization_locked: bool,
+    /// Whether the deployment is the target of the next soft reboot.
+    pub soft_reboot_target: bool,
+    /// The kernel arguments of the boot entry.
+    pub kernel_args: Option<String>,
+    /// The checksum of the kernel and initramfs.
+    pub boot_checksum: String,
+    /// The signature state of the commit.
+    pub signature: SignatureState,
+}
+
+/// The status of all deployments of a sysroot, returned by [`Sysroot::status`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SysrootStatus {
+    /// The deployments, in boot order.
+    pub deployments: Vec<DeploymentStatus>,
+}
+
+impl SysrootStatus {
+    /// The booted deployment.
+    pub fn booted(&self) -> Option<&DeploymentStatus> {
+        self.deployments.iter().find(|d| d.booted)
+    }
+
+    /// The staged deployment.
+    pub fn staged(&self) -> Option<&DeploymentStatus> {
+        self.deployments.iter().find(|d| d.staged)
+    }
+
+    /// The deployment that will be booted next, if it is not the booted one.
+    pub fn pending(&self) -> Option<&DeploymentStatus> {
+        self.deployments.iter().find(|d| d.pending)
+    }
+
+    /// The rollback deployment of the booted stateroot.
+    pub fn rollback(&self) -> Option<&DeploymentStatus> {
+        self.deployments.iter().find(|d| d.rollback)
+    }
+}
+
+impl Sysroot {
+    /// Report the status of all deployments, like `ostree admin status`. The sysroot must
+    /// be loaded.
+    pub fn status(&self) -> Result<SysrootStatus, glib::Error> {
+        let booted = self.booted_deployment();
+        let (pending, rollback) = match &booted {
+            Some(_) => self.query_deployments_for(None),
+            None => (None, None),
+        };
+        let is = |a: &Option<Deployment>, b: &Deployment| a.as_ref().is_some_and(|a| a.equal(b));
+        let deployments = self
+            .deployments()
+            .iter()
+            .map(|d| {
+                let mut status = self.deployment_status(d)?;
+                status.booted = is(&booted, d);
+                status.pending = is(&pending, d);
+                status.rollback = is(&rollback, d);
+                Ok(status)
+            })
+            .collect::<Result<_, glib::Error>>()?;
+        Ok(SysrootStatus { deployments })
+    }
+
+    fn deployment_status(&self, deployment: &Deployment) -> Result<DeploymentStatus, glib::Error> {
+        let repo = self.repo();
+        let checksum = deployment.csum().to_string();
+        let version = match repo.load_variant_if_exists(ObjectType::Commit, &checksum)? {
+            Some(commit) => CommitMetadata::from_commit(&commit)
+                .ok()
+                .and_then(|m| m.version().ok().flatten()),
+            None => None,
+        };
+        let origin = deployment.parsed_origin().ok().flatten();
+        let refspec = origin.as_ref().and_then(|o| o.refspec());
+        let remote = refspec
+            .and_then(|r| crate::parse_refspec(r).ok())
+            .and_then(|(remote, _)| remote);
+        let signature = match remote {
+            Some(remote) => signature_state(&repo, &checksum, &remote),
+            None => SignatureState::Unverified,
+        };
+
+        #[cfg(feature = "v2023_8")]
+        let finalization_locked = deployment.is_finalization_locked();
+        #[cfg(not(feature = "v2023_8"))]
+        let finalization_locked = false;
+        #[cfg(feature = "v2025_3")]
+        let soft_reboot_target = deployment.is_soft_reboot_target();
+        #[cfg(not(feature = "v2025_3"))]
+        let soft_reboot_target = false;
+
+        Ok(DeploymentStatus {
+            stateroot: deployment.osname().to_string(),
+            deployserial: deployment.deployserial(),
+            version,
+            origin: origin.and_then(|o| o.refspec().or(o.container_image()).map(Into::into)),
+            booted: false,
+            staged: deployment.is_staged(),
+            pending: false,
+            rollback: false,
+            pinned: deployment.is_pinned(),
+            unlocked: Deployment::unlocked_state_to_string(deployment.unlocked()).to_string(),
+            finalization_locked,
+            soft_reboot_target,
+            kernel_args: deployment
+                .bootconfig()
+                .and_then(|b| b.options())
+                .map(|o| o.to_string()),
+            boot_checksum: deployment.bootcsum().to_string(),
+            signature,
+            checksum,
+        })
+    }
+}
+
+/// Verify the commit `checksum` as configured for `remote`. As when pulling, both the
+/// `gpg-verify` and `sign-verify` checks must pass if both are enabled.
+fn signature_state(repo: &Repo, checksum: &str, remote: &str) -> SignatureState {
+    let invalid = |e: glib::Error| SignatureState::Invalid {
+        message: e.message().to_string(),
+    };
+    let (mut valid, mut total) = (0, 0);
+    if repo.remote_get_gpg_verify(remote).unwrap_or(false) {
+        let result = match repo.verify_commit_for_remote(checksum, remote, gio::Cancellable::NONE) {
+            Ok(result) => result,
+            Err(e) => return invalid(e),
+        };
+        if let Err(e) = result.require_valid_signature() {
+            return invalid(e);
+        }
+        valid += result.count_valid();
+        total += result.count_all();
+    }
+    // Either a boolean, or the list of signature types to verify with.
+    let types = match repo.remote_option(remote, "sign-verify", None) {
+        Ok(Some(v)) => match v.trim() {
+            "true" | "1" => Some(None),
+            "false" | "0" => None,
+            types => Some(Some(
+                types
+                    .split([',', ';'])
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty())
+                    .collect::<Vec<_>>(),
+            )),
+        },
+        Ok(None) => None,
+        Err(e) => return invalid(e),
+    };
+    if let Some(types) = types {
+        match sign_verify(repo, checksum, remote, types.as_deref()) {
+            Ok((sign_valid, sign_total)) => {
+                valid += sign_valid;
+                total += sign_total;
+            }
+            Err(e) => return invalid(e),
+        }
+    }
+    if total == 0 {
+        SignatureState::Unverified
+    } else {
+        SignatureState::Valid { valid, total }
+    }
+}
+
+/// Verify the commit `checksum` with the keys configured for `remote` through the
+/// `verification-<type>-key` and `verification-<type>-file` options, or the default keys of
+/// each signature type, returning the number of types it is validly signed with and the
+/// number of types with keys. Only `types` are used if given. At least one type must
+/// verify.
+#[cfg(feature = "v2020_2")]
+fn sign_verify(
+    repo: &Repo,
+    checksum: &str,
+    remote: &str,
+    types: Option<&[String]>,
+) -> Result<(u32, u32), glib::Error> {
+    let signers = match types {
+        Some(types) => types
+            .iter()
+            .map(|t| Sign::by_name(t))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Sign::all(),
+    };
+    let (mut valid, mut total) = (0, 0);
+    let mut last_error = None;
+    for sign in signers {
+        let name = sign.name();
+        let key = repo.remote_option(remote, &format!("verification-{name}-key"), None)?;
+        let file = repo.remote_option(remote, &format!("verification-{name}-file"), None)?;
+        let loaded = match (key, file) {
+            (None, None) => sign.load_pk(&glib::VariantDict::new(None).end()).is_ok(),
+            (key, file) => {
+                if let Some(key) = key {
+                    sign.add_pk(&glib::Variant::from(key.as_str()))?;
+                }
+                if let Some(file) = file {
+                    let options = glib::VariantDict::new(None);
+                    options.insert("filename", file.as_str());
+                    sign.load_pk(&options.end())?;
+                }
+                true
+            }
+        };
+        if !loaded {
+            continue;
+        }
+        total += 1;
+        match sign.commit_verify(repo, checksum, gio::Cancellable::NONE) {
+            Ok(_) => valid += 1,
+            Err(e) => last_error = Some(e),
+        }
+    }
+    if total == 0 {
+        return Err(glib::Error::new(
+            gio::IOErrorEnum::NotFound,
+            &format!("No keys found for the signature types of remote {remote}"),
+        ));
+    }
+    match last_error {
+        Some(e) if valid == 0 => Err(e),
+        _ => Ok((valid, total)),
+    }
+}
+
+#[cfg(not(feature = "v2020_2"))]
+fn sign_verify(
+    _repo: &Repo,
+    _checksum: &str,
+    _remote: &str,
+    _types: Option<&[String]>,
+) -> Result<(u32, u32), glib::Error> {
+    Err(glib::Error::new(
+        gio::IOErrorEnum::NotSupported,
+        "Verifying sign-verify signatures requires the v2020_2 feature",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(booted: bool, rollback: bool) -> DeploymentStatus {
+        DeploymentStatus {
+            stateroot: "os".into(),
+            checksum: "a".repeat(64),
+            deployserial: 0,
+            version: Some("1".into()),
+            origin: Some("remote:ref".into()),
+            booted,
+            staged: false,
+            pending: false,
+            rollback,
+            pinned: false,
+            unlocked: "none".into(),
+            finalization_locked: false,
+            soft_reboot_target: false,
+            kernel_args: Some("root=/dev/vda".into()),
+            boot_checksum: "b".repeat(64),
+            signature: SignatureState::Unverified,
+        }
+    }
+
+    #[test]
+    fn should_find_deployments_by_role() {
+        let s = SysrootStatus {
+            deployments: vec![status(true, false), status(false, true)],
+        };
+        assert!(s.booted().unwrap().booted);
+        assert!(s.rollback().unwrap().rollback);
+        assert!(s.staged().is_none());
+        assert!(s.pending().is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_round_trip_through_json() {
+        let mut d = status(true, false);
+        d.signature = SignatureState::Valid { valid: 1, total: 2 };
+        let s = SysrootStatus {
+            deployments: vec![d],
+        };
+        let json = serde_json::to_value(&s).unwrap();
+        assert_eq!(json["deployments"][0]["signature"]["state"], "valid");
+        assert_eq!(serde_json::from_value::<SysrootStatus>(json).unwrap(), s);
+    }
+}
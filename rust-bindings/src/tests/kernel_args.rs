@@ -1,6 +1,6 @@
 #![cfg(feature = "v2019_3")]
 
-use crate::KernelArgs;
+use crate::{KernelArgChange, KernelArgs};
 
 #[test]
 fn should_create_and_fill_kernel_args() {
@@ -62,3 +62,60 @@ fn should_replace_argv() {
     args.replace_argv(&["arg1=value3", "arg3=value4", "arg4"]);
     assert_eq!(args.to_string(), "arg1=value3 arg2=value2 arg3=value4 arg4");
 }
+
+#[test]
+fn should_iterate_with_kernel_quoting() {
+    let args = KernelArgs::from_string(r#"root=/dev/vda "foo=bar baz" quiet x="a b""#);
+    assert_eq!(
+        args.iter().collect::<Vec<_>>(),
+        vec![
+            ("root".to_string(), Some("/dev/vda".to_string())),
+            ("foo".to_string(), Some("bar baz".to_string())),
+            ("quiet".to_string(), None),
+            ("x".to_string(), Some("a b".to_string())),
+        ]
+    );
+}
+
+#[test]
+fn should_query_values_with_equivalent_keys() {
+    let args = KernelArgs::from_string("console=tty0 rd.lvm_lv=a console=ttyS0 rd.lvm-lv=b");
+    assert_eq!(
+        args.values("console"),
+        [Some("tty0".to_string()), Some("ttyS0".to_string())]
+    );
+    assert_eq!(args.values("rd.lvm-lv").len(), 2);
+    assert!(args.contains("rd.lvm_lv"));
+    assert!(!args.contains("quiet"));
+}
+
+#[test]
+fn should_compare_and_diff() {
+    let a = KernelArgs::from_string("quiet root=/dev/vda console=tty0 rd_foo=1");
+    let b = KernelArgs::from_string("rd-foo=1 root=/dev/vda quiet console=tty0");
+    assert_eq!(a, b);
+    assert!(a.diff(&b).is_empty());
+
+    let c = KernelArgs::from_string("root=/dev/vdb console=tty0 console=ttyS0 rhgb");
+    assert_ne!(a, c);
+    let diff = a.diff(&c);
+    assert_eq!(
+        diff,
+        vec![
+            KernelArgChange::Removed("quiet".into(), None),
+            KernelArgChange::Changed {
+                key: "root".into(),
+                old: vec![Some("/dev/vda".into())],
+                new: vec![Some("/dev/vdb".into())],
+            },
+            KernelArgChange::Changed {
+                key: "console".into(),
+                old: vec![Some("tty0".into())],
+                new: vec![Some("tty0".into()), Some("ttyS0".into())],
+            },
+            KernelArgChange::Removed("rd_foo".into(), Some("1".into())),
+            KernelArgChange::Added("rhgb".into(), None),
+        ]
+    );
+    assert_eq!(diff[1].to_string(), "~root=/dev/vda -> root=/dev/vdb");
+}
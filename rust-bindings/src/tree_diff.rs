@@ -0,0 +1,384 @@
+//! Comparing the trees of two commits without checking them out.
+//!
+//! Dirtree objects are compared by checksum, so identical subtrees are skipped without
+//! being loaded.
+
+use crate::{DirMetaParsed, ObjectType, Repo, TreeVariantType};
+use gio::prelude::*;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// The size of the chunks in which file contents are compared.
+const COMPARE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The type of a tree entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeEntryType {
+    /// A regular file.
+    File,
+    /// A symbolic link.
+    Symlink,
+    /// A directory.
+    Directory,
+}
+
+/// How an entry present in both trees differs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeModification {
+    /// The type of the entry in the new tree; files may have become symbolic links.
+    pub entry_type: TreeEntryType,
+    /// Whether the file content or symbolic link target differs. Always `false` for
+    /// directories, whose content is reported as separate changes.
+    pub content: bool,
+    /// The old and new mode, if different.
+    pub mode: Option<(u32, u32)>,
+    /// The old and new owner, if different.
+    pub uid: Option<(u32, u32)>,
+    /// The old and new group, if different.
+    pub gid: Option<(u32, u32)>,
+    /// Whether the extended attributes differ.
+    pub xattrs: bool,
+}
+
+impl TreeModification {
+    /// Whether only the mode, ownership or extended attributes differ.
+    pub fn is_metadata_only(&self) -> bool {
+        !self.content
+    }
+}
+
+/// The kind of a [`TreeChange`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeChangeKind {
+    /// The path only exists in the new tree. The contents of added directories are not
+    /// listed.
+    Added(TreeEntryType),
+    /// The path only exists in the old tree. The contents of removed directories are not
+    /// listed.
+    Removed(TreeEntryType),
+    /// The path exists in both trees with different content or metadata.
+    Modified(TreeModification),
+}
+
+/// A difference between two trees, returned by [`Repo::diff_commits`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeChange {
+    /// The absolute path in the tree; `/` for the root directory.
+    pub path: String,
+    /// The kind of change.
+    pub kind: TreeChangeKind,
+    /// The change in file size in bytes, if requested with
+    /// [`TreeDiffOptions::content_sizes`]. Always `None` for directories.
+    pub size_delta: Option<i64>,
+}
+
+/// Options for [`Repo::diff_commits_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct TreeDiffOptions {
+    content_sizes: bool,
+}
+
+impl TreeDiffOptions {
+    /// Create default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compute [`TreeChange::size_delta`] for files.
+    pub fn content_sizes(mut self, enabled: bool) -> Self {
+        self.content_sizes = enabled;
+        self
+    }
+}
+
+/// Metadata of a content object.
+struct FileMeta {
+    entry_type: TreeEntryType,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    symlink_target: Option<std::path::PathBuf>,
+    xattrs: glib::Variant,
+}
+
+impl Repo {
+    /// Compare the trees of the commits `old` and `new`, given as refs or checksums.
+    pub fn diff_commits(&self, old: &str, new: &str) -> Result<Vec<TreeChange>, glib::Error> {
+        self.diff_commits_with_options(old, new, &TreeDiffOptions::default())
+    }
+
+    /// Compare the trees of the commits `old` and `new`, given as refs or checksums.
+    pub fn diff_commits_with_options(
+        &self,
+        old: &str,
+        new: &str,
+        options: &TreeDiffOptions,
+    ) -> Result<Vec<TreeChange>, glib::Error> {
+        let load = |rev| -> Result<crate::CommitVariantType, glib::Error> {
+            let checksum = self.require_rev(rev)?;
+            Ok(self
+                .load_variant(ObjectType::Commit, &checksum)?
+                .get::<crate::CommitVariantType>()
+                .unwrap())
+        };
+        let (old, new) = (load(old)?, load(new)?);
+        let mut out = Vec::new();
+        if old.7 != new.7 {
+            out.push(self.diff_dirmeta("/", &hex::encode(&old.7), &hex::encode(&new.7))?);
+        }
+        if old.6 != new.6 {
+            self.diff_dirtrees(
+                "",
+                &hex::encode(&old.6),
+                &hex::encode(&new.6),
+                options,
+                &mut out,
+            )?;
+        }
+        Ok(out)
+    }
+
+    /// Append the differences between the dirtrees `old` and `new`, found at `path`, to
+    /// `out`.
+    pub(crate) fn diff_dirtrees(
+        &self,
+        path: &str,
+        old: &str,
+        new: &str,
+        options: &TreeDiffOptions,
+        out: &mut Vec<TreeChange>,
+    ) -> Result<(), glib::Error> {
+        let load = |checksum| -> Result<TreeVariantType, glib::Error> {
+            Ok(self
+                .load_variant(ObjectType::DirTree, checksum)?
+                .get::<TreeVariantType>()
+                .unwrap())
+        };
+        let (old, new) = (load(old)?, load(new)?);
+
+        let old_files = old.0.into_iter().collect::<BTreeMap<_, _>>();
+        let new_files = new.0.into_iter().collect::<BTreeMap<_, _>>();
+        for name in merged_keys(&old_files, &new_files) {
+            let p = format!("{path}/{name}");
+            match (old_files.get(name), new_files.get(name)) {
+                (Some(o), Some(n)) if o == n => {}
+                (Some(o), Some(n)) => {
+                    out.push(self.diff_files(p, &hex::encode(o), &hex::encode(n), options)?)
+                }
+                (Some(o), None) => {
+                    let meta = self.file_meta(&hex::encode(o))?;
+                    out.push(TreeChange {
+                        path: p,
+                        kind: TreeChangeKind::Removed(meta.entry_type),
+                        size_delta: options.content_sizes.then(|| -(meta.size as i64)),
+                    });
+                }
+                (None, Some(n)) => {
+                    let meta = self.file_meta(&hex::encode(n))?;
+                    out.push(TreeChange {
+                        path: p,
+                        kind: TreeChangeKind::Added(meta.entry_type),
+                        size_delta: options.content_sizes.then_some(meta.size as i64),
+                    });
+                }
+                (None, None) => unreachable!(),
+            }
+        }
+
+        let old_dirs = old
+            .1
+            .into_iter()
+            .map(|(n, tree, meta)| (n, (tree, meta)))
+            .collect::<BTreeMap<_, _>>();
+        let new_dirs = new
+            .1
+            .into_iter()
+            .map(|(n, tree, meta)| (n, (tree, meta)))
+            .collect::<BTreeMap<_, _>>();
+        for name in merged_keys(&old_dirs, &new_dirs) {
+            let p = format!("{path}/{name}");
+            let kind = match (old_dirs.get(name), new_dirs.get(name)) {
+                (Some((otree, ometa)), Some((ntree, nmeta))) => {
+                    if ometa != nmeta {
+                        out.push(self.diff_dirmeta(
+                            &p,
+                            &hex::encode(ometa),
+                            &hex::encode(nmeta),
+                        )?);
+                    }
+                    if otree != ntree {
+                        self.diff_dirtrees(
+                            &p,
+                            &hex::encode(otree),
+                            &hex::encode(ntree),
+                            options,
+                            out,
+                        )?;
+                    }
+                    continue;
+                }
+                (Some(_), None) => TreeChangeKind::Removed(TreeEntryType::Directory),
+                (None, Some(_)) => TreeChangeKind::Added(TreeEntryType::Directory),
+                (None, None) => unreachable!(),
+            };
+            out.push(TreeChange {
+                path: p,
+                kind,
+                size_delta: None,
+            });
+        }
+        Ok(())
+    }
+
+    fn diff_dirmeta(&self, path: &str, old: &str, new: &str) -> Result<TreeChange, glib::Error> {
+        let load = |checksum| -> Result<DirMetaParsed, glib::Error> {
+            let v = self.load_variant(ObjectType::DirMeta, checksum)?;
+            DirMetaParsed::from_variant(&v)
+                .map_err(|e| glib::Error::new(gio::IOErrorEnum::InvalidData, &e.to_string()))
+        };
+        let (old, new) = (load(old)?, load(new)?);
+        Ok(TreeChange {
+            path: path.to_string(),
+            kind: TreeChangeKind::Modified(TreeModification {
+                entry_type: TreeEntryType::Directory,
+                content: false,
+                mode: changed(old.mode, new.mode),
+                uid: changed(old.uid, new.uid),
+                gid: changed(old.gid, new.gid),
+                xattrs: old.xattrs != new.xattrs,
+            }),
+            size_delta: None,
+        })
+    }
+
+    fn diff_files(
+        &self,
+        path: String,
+        old: &str,
+        new: &str,
+        options: &TreeDiffOptions,
+    ) -> Result<TreeChange, glib::Error> {
+        let (o, n) = (self.file_meta(old)?, self.file_meta(new)?);
+        let mode = changed(o.mode, n.mode);
+        let uid = changed(o.uid, n.uid);
+        let gid = changed(o.gid, n.gid);
+        let xattrs = o.xattrs != n.xattrs;
+        // The checksums differ, so if the metadata is identical the content is not.
+        let same_meta = mode.is_none() && uid.is_none() && gid.is_none() && !xattrs;
+        let content = if same_meta || o.entry_type != n.entry_type || o.size != n.size {
+            true
+        } else if n.entry_type == TreeEntryType::Symlink {
+            o.symlink_target != n.symlink_target
+        } else {
+            !self.same_content(old, new)?
+        };
+        Ok(TreeChange {
+            path,
+            kind: TreeChangeKind::Modified(TreeModification {
+                entry_type: n.entry_type,
+                content,
+                mode,
+                uid,
+                gid,
+                xattrs,
+            }),
+            size_delta: options.content_sizes.then(|| n.size as i64 - o.size as i64),
+        })
+    }
+
+    fn file_meta(&self, checksum: &str) -> Result<FileMeta, glib::Error> {
+        let (info, xattrs) = self.query_file(checksum, gio::Cancellable::NONE)?;
+        let entry_type = match info.file_type() {
+            gio::FileType::SymbolicLink => TreeEntryType::Symlink,
+            _ => TreeEntryType::File,
+        };
+        Ok(FileMeta {
+            entry_type,
+            mode: info.attribute_uint32("unix::mode"),
+            uid: info.attribute_uint32("unix::uid"),
+            gid: info.attribute_uint32("unix::gid"),
+            size: info.size() as u64,
+            symlink_target: info.symlink_target(),
+            xattrs,
+        })
+    }
+
+    /// Compare the content of two regular files of the same size, a chunk at a time.
+    fn same_content(&self, old: &str, new: &str) -> Result<bool, glib::Error> {
+        let open = |checksum| -> Result<Box<dyn Read>, glib::Error> {
+            Ok(match self.load_file(checksum, gio::Cancellable::NONE)? {
+                (Some(stream), _, _) => Box::new(stream.into_read()),
+                (None, _, _) => Box::new(std::io::empty()),
+            })
+        };
+        let (mut old, mut new) = (open(old)?, open(new)?);
+        let mut old_buf = vec![0u8; COMPARE_CHUNK_SIZE];
+        let mut new_buf = vec![0u8; COMPARE_CHUNK_SIZE];
+        loop {
+            let n = fill(&mut old, &mut old_buf)?;
+            if n != fill(&mut new, &mut new_buf)? || old_buf[..n] != new_buf[..n] {
+                return Ok(false);
+            }
+            if n < COMPARE_CHUNK_SIZE {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+/// Read into `buf` until it is full or the end of `input`, returning the length read.
+fn fill(input: &mut impl Read, buf: &mut [u8]) -> Result<usize, glib::Error> {
+    let mut len = 0;
+    while len < buf.len() {
+        match input.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(glib::Error::new(gio::IOErrorEnum::Failed, &e.to_string())),
+        }
+    }
+    Ok(len)
+}
+
+fn changed(old: u32, new: u32) -> Option<(u32, u32)> {
+    (old != new).then_some((old, new))
+}
+
+/// The keys of both maps, in order and without duplicates.
+fn merged_keys<'a, V>(
+    a: &'a BTreeMap<String, V>,
+    b: &'a BTreeMap<String, V>,
+) -> impl Iterator<Item = &'a String> {
+    let mut keys = a.keys().chain(b.keys()).collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_merge_keys() {
+        let a = BTreeMap::from([("a".to_string(), ()), ("c".to_string(), ())]);
+        let b = BTreeMap::from([("b".to_string(), ()), ("c".to_string(), ())]);
+        assert_eq!(merged_keys(&a, &b).collect::<Vec<_>>(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn should_detect_metadata_only_change() {
+        let m = TreeModification {
+            entry_type: TreeEntryType::File,
+            content: false,
+            mode: changed(0o100644, 0o100755),
+            uid: changed(0, 0),
+            gid: None,
+            xattrs: false,
+        };
+        assert!(m.is_metadata_only());
+        assert_eq!(m.mode, Some((0o100644, 0o100755)));
+        assert_eq!(m.uid, None);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::util::*;
+use glib::prelude::*;
+use ostree::{Bundle, BundleError, BundleImportOptions, BundleWriter};
+
+fn unverified() -> BundleImportOptions<'static> {
+    BundleImportOptions::new().insecure_skip_verification(true)
+}
+
+#[test]
+fn should_write_and_import_bundle() {
+    let src = TestRepo::new();
+    let base = src.test_commit("commit1");
+    let target = src.test_commit("commit2");
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("update.bundle");
+    BundleWriter::new(&src.repo)
+        .commit(&base)
+        .commit_from(&base, &target)
+        .set_ref("os/stable", &target)
+        .write_to_path(&path, gio::Cancellable::NONE)
+        .unwrap();
+
+    let bundle = Bundle::open(&path).unwrap();
+    assert_eq!(bundle.commits().len(), 2);
+    assert_eq!(bundle.refs()["os/stable"], target.as_str());
+    let superblock = bundle.superblock(&bundle.commits()[1]).unwrap();
+    assert_eq!(superblock.from.as_deref(), Some(base.as_str()));
+    assert!(superblock.parts.iter().all(|p| p.inline));
+    assert!(superblock.fallback.is_empty());
+
+    let dest = TestRepo::new();
+    assert!(matches!(
+        dest.repo.import_bundle(&path, &[], gio::Cancellable::NONE),
+        Err(BundleError::NoSigners)
+    ));
+    assert!(dest.repo.resolve_rev("os/stable", true).unwrap().is_none());
+    dest.repo
+        .import_bundle_with_options(&path, &unverified(), gio::Cancellable::NONE)
+        .unwrap();
+    assert_eq!(dest.repo.require_rev("os/stable").unwrap(), target);
+    dest.repo
+        .traverse_commit(&target, -1, gio::Cancellable::NONE)
+        .unwrap();
+}
+
+#[test]
+fn should_not_import_bundle_without_base() {
+    let src = TestRepo::new();
+    let base = src.test_commit("commit1");
+    let target = src.test_commit("commit2");
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("update.bundle");
+    BundleWriter::new(&src.repo)
+        .commit_from(&base, &target)
+        .set_ref("os/stable", &target)
+        .write_to_path(&path, gio::Cancellable::NONE)
+        .unwrap();
+
+    let dest = TestRepo::new();
+    assert!(matches!(
+        dest.repo
+            .import_bundle_with_options(&path, &unverified(), gio::Cancellable::NONE),
+        Err(BundleError::Invalid(_))
+    ));
+    assert!(dest.repo.resolve_rev("os/stable", true).unwrap().is_none());
+
+    #[cfg(feature = "v2020_2")]
+    {
+        let path = dir.path().join("full.bundle");
+        BundleWriter::new(&src.repo)
+            .commit(&target)
+            .write_to_path(&path, gio::Cancellable::NONE)
+            .unwrap();
+        let sign = ostree::Sign::by_name("dummy").unwrap();
+        assert!(matches!(
+            dest.repo
+                .import_bundle(&path, &[sign], gio::Cancellable::NONE),
+            Err(BundleError::Unsigned(_))
+        ));
+    }
+}
+
+fn bound_commit(repo: &ostree::Repo, ref_: &str) -> glib::GString {
+    let mtree = create_mtree(repo);
+    let metadata = glib::VariantDict::new(None);
+    metadata.insert_value("ostree.ref-binding", &vec![ref_].to_variant());
+    commit(repo, &mtree, ref_, None, None, Some(&metadata.end()))
+}
+
+#[test]
+fn should_not_import_bundle_with_swapped_ref() {
+    let src = TestRepo::new();
+    let testing = bound_commit(&src.repo, "os/testing");
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("update.bundle");
+    BundleWriter::new(&src.repo)
+        .commit(&testing)
+        .set_ref("os/testing", &testing)
+        .write_to_path(&path, gio::Cancellable::NONE)
+        .unwrap();
+
+    // Retarget the ref in the manifest, which comes first, but not the commit binding.
+    let mut data = std::fs::read(&path).unwrap();
+    let pos = data
+        .windows(b"os/testing".len())
+        .position(|w| w == b"os/testing")
+        .unwrap();
+    data[pos..pos + 10].copy_from_slice(b"os/release");
+    std::fs::write(&path, &data).unwrap();
+    assert_eq!(
+        Bundle::open(&path).unwrap().refs()["os/release"],
+        testing.as_str()
+    );
+
+    let dest = TestRepo::new();
+    assert!(matches!(
+        dest.repo
+            .import_bundle_with_options(&path, &unverified(), gio::Cancellable::NONE),
+        Err(BundleError::RefBinding { .. })
+    ));
+    assert!(dest.repo.resolve_rev("os/release", true).unwrap().is_none());
+
+    let options = unverified().allow_ref_binding_mismatch(true);
+    dest.repo
+        .import_bundle_with_options(&path, &options, gio::Cancellable::NONE)
+        .unwrap();
+    assert_eq!(dest.repo.require_rev("os/release").unwrap(), testing);
+}
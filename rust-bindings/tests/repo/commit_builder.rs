@@ -0,0 +1,64 @@
+use crate::util::*;
+use ostree::{CommitBuilder, CommitMetadata, CommitParent, CommitSource, ObjectType};
+use std::path::Path;
+
+fn test_tar() -> gio::File {
+    gio::File::for_path(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("rust-bindings/tests/data/test.tar"),
+    )
+}
+
+#[test]
+fn should_commit_tar_and_set_ref() {
+    let test_repo = TestRepo::new();
+    let repo = &test_repo.repo;
+    let tar = test_tar();
+
+    let mut metadata = CommitMetadata::new();
+    metadata.set_version("1.0");
+    let (first, stats) = CommitBuilder::new(CommitSource::Tar(&tar))
+        .subject("First")
+        .metadata(metadata)
+        .timestamp(1_000_000)
+        .target_ref("test")
+        .commit(repo, gio::Cancellable::NONE)
+        .expect("commit");
+    assert_eq!(repo.require_rev("test").unwrap(), first);
+    assert!(stats.get_metadata_objects_written() > 0);
+
+    let commit = repo.load_variant(ObjectType::Commit, &first).unwrap();
+    let timestamp = commit.get::<ostree::CommitVariantType>().unwrap().5;
+    assert_eq!(u64::from_be(timestamp), 1_000_000);
+    assert_eq!(ostree::commit_get_parent(&commit), None);
+    let metadata = CommitMetadata::from_commit(&commit).unwrap();
+    assert_eq!(metadata.version().unwrap().as_deref(), Some("1.0"));
+    assert_eq!(
+        metadata.ref_binding().unwrap(),
+        Some(vec!["test".to_string()])
+    );
+
+    let mtree = create_mtree(repo);
+    let (second, _) = CommitBuilder::new(CommitSource::MutableTree(&mtree))
+        .parent(CommitParent::FromRef("test"))
+        .bindings(false)
+        .target_ref("test")
+        .commit(repo, gio::Cancellable::NONE)
+        .expect("commit");
+    assert_eq!(repo.require_rev("test").unwrap(), second);
+    let commit = repo.load_variant(ObjectType::Commit, &second).unwrap();
+    assert_eq!(ostree::commit_get_parent(&commit).unwrap(), first);
+    let metadata = CommitMetadata::from_commit(&commit).unwrap();
+    assert_eq!(metadata.ref_binding().unwrap(), None);
+}
+
+#[test]
+fn should_not_set_ref_when_parent_is_missing() {
+    let test_repo = TestRepo::new();
+    let tar = test_tar();
+    let r = CommitBuilder::new(CommitSource::Tar(&tar))
+        .parent(CommitParent::Explicit("nosuchrev"))
+        .target_ref("test")
+        .commit(&test_repo.repo, gio::Cancellable::NONE);
+    assert!(r.is_err());
+    assert!(test_repo.repo.require_rev("test").is_err());
+}
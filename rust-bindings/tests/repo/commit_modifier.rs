@@ -0,0 +1,55 @@
+use crate::util::*;
+use gio::prelude::*;
+use ostree::{
+    CommitBuilder, CommitFileMetadata, CommitSource, Repo, RepoCommitFilterResult,
+    RepoCommitModifier, RepoCommitModifierFlags,
+};
+use std::path::Path;
+
+#[test]
+fn should_apply_closure_modifier() {
+    let test_repo = TestRepo::new();
+    let src = tempfile::tempdir().unwrap();
+    std::fs::create_dir(src.path().join("bin")).unwrap();
+    std::fs::write(src.path().join("bin/tool"), "tool").unwrap();
+    std::fs::write(src.path().join("skipme"), "skip").unwrap();
+
+    let modifier = RepoCommitModifier::new_with_filter(
+        RepoCommitModifierFlags::NONE,
+        None,
+        |_: &Repo, path: &Path, meta: &mut CommitFileMetadata| {
+            if path == Path::new("/skipme") {
+                return RepoCommitFilterResult::Skip;
+            }
+            meta.uid = 42;
+            meta.gid = 43;
+            if meta.file_type() == gio::FileType::Regular {
+                meta.mode = libc::S_IFREG | 0o4711;
+            }
+            RepoCommitFilterResult::Allow
+        },
+    );
+    let dir = gio::File::for_path(src.path());
+    let (checksum, _) = CommitBuilder::new(CommitSource::Directory(&dir))
+        .modifier(&modifier)
+        .target_ref("test")
+        .commit(&test_repo.repo, gio::Cancellable::NONE)
+        .expect("commit");
+
+    let (root, _) = test_repo
+        .repo
+        .read_commit(&checksum, gio::Cancellable::NONE)
+        .unwrap();
+    assert!(!root.child("skipme").query_exists(gio::Cancellable::NONE));
+    let info = root
+        .child("bin/tool")
+        .query_info(
+            "unix::*",
+            gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+            gio::Cancellable::NONE,
+        )
+        .unwrap();
+    assert_eq!(info.attribute_uint32("unix::uid"), 42);
+    assert_eq!(info.attribute_uint32("unix::gid"), 43);
+    assert_eq!(info.attribute_uint32("unix::mode"), libc::S_IFREG | 0o4711);
+}
@@ -0,0 +1,36 @@
+use crate::util::*;
+use ostree::{CommitBuilder, CommitSource, ComposefsError};
+
+#[test]
+fn should_verify_composefs_digest() {
+    let test_repo = TestRepo::new();
+    let src = tempfile::tempdir().unwrap();
+    std::fs::create_dir(src.path().join("usr")).unwrap();
+    std::fs::write(src.path().join("usr/file"), "content").unwrap();
+    let dir = gio::File::for_path(src.path());
+
+    let (plain, _) = CommitBuilder::new(CommitSource::Directory(&dir))
+        .commit(&test_repo.repo, gio::Cancellable::NONE)
+        .unwrap();
+    assert!(matches!(
+        test_repo.repo.composefs_digest(&plain),
+        Err(ComposefsError::MissingDigest(_))
+    ));
+
+    let checksum = match CommitBuilder::new(CommitSource::Directory(&dir))
+        .composefs_metadata(true)
+        .commit(&test_repo.repo, gio::Cancellable::NONE)
+    {
+        Ok((checksum, _)) => checksum,
+        Err(e) => match ComposefsError::from(e) {
+            ComposefsError::Unsupported => return,
+            e => panic!("{e}"),
+        },
+    };
+    let recorded = test_repo.repo.composefs_digest(&checksum).unwrap();
+    let verified = test_repo
+        .repo
+        .verify_composefs_digest(&checksum, gio::Cancellable::NONE)
+        .unwrap();
+    assert_eq!(recorded, verified);
+}
@@ -0,0 +1,55 @@
+use crate::util::*;
+use ostree::{CommitBuilder, CommitSource, FsVerityError, FsVerityMode, ObjectType, RepoMode};
+
+#[test]
+fn should_enable_fsverity_on_existing_objects() {
+    let test_repo = TestRepo::new_with_mode(RepoMode::BareUser);
+    let repo = &test_repo.repo;
+    assert_eq!(repo.fsverity_mode().unwrap(), FsVerityMode::Disabled);
+
+    let src = tempfile::tempdir().unwrap();
+    std::fs::write(src.path().join("file"), "content").unwrap();
+    let dir = gio::File::for_path(src.path());
+    CommitBuilder::new(CommitSource::Directory(&dir))
+        .commit(repo, gio::Cancellable::NONE)
+        .unwrap();
+
+    let config = repo.copy_config();
+    config.set_string("ex-integrity", "fsverity", "maybe");
+    repo.write_config_and_reload(&config).unwrap();
+    assert_eq!(repo.fsverity_mode().unwrap(), FsVerityMode::Opportunistic);
+
+    let objects = repo
+        .list_objects(
+            ostree::ffi::OSTREE_REPO_LIST_OBJECTS_LOOSE,
+            gio::Cancellable::NONE,
+        )
+        .unwrap();
+    let file = objects
+        .keys()
+        .find(|o| o.object_type() == ObjectType::File)
+        .unwrap()
+        .checksum()
+        .to_string();
+
+    match repo.enable_fsverity_for_objects(|_| true, gio::Cancellable::NONE) {
+        Ok(stats) => {
+            assert_eq!(stats.enabled + stats.already_enabled, 1);
+            let digest = repo.object_fsverity_digest(&file).unwrap();
+            assert_eq!(digest.algorithm(), 1);
+            assert_eq!(digest.as_bytes().len(), 32);
+            let stats = repo
+                .enable_fsverity_for_objects(|_| true, gio::Cancellable::NONE)
+                .unwrap();
+            assert_eq!((stats.enabled, stats.already_enabled), (0, 1));
+        }
+        // e.g. tmpfs
+        Err(FsVerityError::Unsupported) => {
+            assert!(matches!(
+                repo.object_fsverity_digest(&file),
+                Err(FsVerityError::Unsupported) | Err(FsVerityError::NotEnabled(_))
+            ));
+        }
+        Err(e) => panic!("{e}"),
+    }
+}
@@ -41,3 +41,191 @@ fn should_generate_static_delta_at() {
 
     assert!(delta_path.try_exists().unwrap());
 }
+
+#[test]
+fn should_inspect_static_delta() {
+    let test_repo = TestRepo::new();
+    let from = test_repo.test_commit("commit1");
+    let to = test_repo.test_commit("commit2");
+    test_repo
+        .repo
+        .static_delta_generate(
+            ostree::StaticDeltaGenerateOpt::Major,
+            Some(&from),
+            &to,
+            None,
+            None,
+            gio::Cancellable::NONE,
+        )
+        .expect("static delta generate");
+
+    let names = test_repo
+        .repo
+        .list_static_delta_names(gio::Cancellable::NONE)
+        .unwrap();
+    let name = format!("{from}-{to}");
+    assert_eq!(names, vec![name.as_str()]);
+
+    let superblock = test_repo.repo.static_delta_info(&name).unwrap();
+    assert_eq!(superblock.name(), name);
+    assert_eq!(superblock.from.as_deref(), Some(from.as_str()));
+    assert_eq!(superblock.to, to.as_str());
+    assert!(!superblock.signed);
+    assert!(!superblock.parts.is_empty());
+    #[cfg(feature = "static_delta_parts")]
+    for (i, info) in superblock.parts.iter().enumerate() {
+        let part = test_repo.repo.static_delta_part(&superblock, i).unwrap();
+        let written = part.payload_bytes_per_object();
+        assert_eq!(written.len(), info.objects.len());
+    }
+    assert!(matches!(
+        test_repo.repo.static_delta_info("nonsense"),
+        Err(StaticDeltaError::InvalidName(_))
+    ));
+}
+
+#[test]
+fn should_generate_static_delta_with_options() {
+    let test_repo = TestRepo::new();
+    let from = test_repo.test_commit("commit1");
+    let to = test_repo.test_commit("commit2");
+
+    let delta_dir = tempfile::tempdir().expect("static delta dir");
+    let delta_path = delta_dir.path().join("superblock");
+    let options = StaticDeltaGenerateOptions::new()
+        .endianness(StaticDeltaEndianness::Big)
+        .min_fallback_size(0)
+        .filename(&delta_path);
+    let generated = test_repo
+        .repo
+        .static_delta_generate_with_options(Some(&from), &to, &options, gio::Cancellable::NONE)
+        .expect("static delta generate");
+    assert_eq!(generated.name, format!("{from}-{to}"));
+    assert_eq!(generated.path.as_deref(), Some(delta_path.as_path()));
+    assert_eq!(generated.superblock.endianness, StaticDeltaEndianness::Big);
+    assert_eq!(generated.stats.n_parts, generated.superblock.parts.len());
+    assert_eq!(generated.stats.n_fallback, 0);
+    assert!(generated.stats.total_size() > 0);
+    assert!(delta_path.try_exists().unwrap());
+
+    let options = StaticDeltaGenerateOptions::new().inline_parts(true);
+    let generated = test_repo
+        .repo
+        .static_delta_generate_with_options(None, &to, &options, gio::Cancellable::NONE)
+        .expect("static delta generate");
+    assert_eq!(generated.name, to.as_str());
+    assert!(generated.path.is_none());
+    assert!(generated.superblock.parts.iter().all(|p| p.inline));
+
+    let options = StaticDeltaGenerateOptions::new().max_chunk_size(0);
+    assert!(matches!(
+        test_repo.repo.static_delta_generate_with_options(
+            None,
+            &to,
+            &options,
+            gio::Cancellable::NONE
+        ),
+        Err(StaticDeltaError::InvalidOption(_))
+    ));
+}
+
+#[test]
+fn should_plan_static_delta() {
+    let test_repo = TestRepo::new();
+    let src = tempfile::tempdir().unwrap();
+    let mut state = 0x2545f491u32;
+    let mut data = (0..256 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect::<Vec<_>>();
+    let commit = |data: &[u8]| {
+        std::fs::write(src.path().join("libfoo.so.1"), data).unwrap();
+        std::fs::write(src.path().join("README"), "unchanged").unwrap();
+        let dir = gio::File::for_path(src.path());
+        CommitBuilder::new(CommitSource::Directory(&dir))
+            .subject("plan")
+            .commit(&test_repo.repo, gio::Cancellable::NONE)
+            .unwrap()
+            .0
+    };
+    let from = commit(&data);
+    data[1000] ^= 0xff;
+    let to = commit(&data);
+
+    let options = StaticDeltaGenerateOptions::new();
+    let plan = test_repo
+        .repo
+        .plan_static_delta(Some(&from), &to, &options, gio::Cancellable::NONE)
+        .unwrap();
+    assert_eq!(plan.new_content.objects, 1);
+    assert_eq!(plan.modified_content.objects, 1);
+    assert_eq!(plan.rollsum.objects, 1);
+    assert_eq!(plan.unchanged_content.objects, 1);
+    assert!(plan.rollsum_matched_bytes > 0);
+    assert!(plan.estimated_size() < plan.loose_size());
+
+    let plan = test_repo
+        .repo
+        .plan_static_delta(None, &to, &options, gio::Cancellable::NONE)
+        .unwrap();
+    assert_eq!(plan.new_content.objects, 2);
+    assert_eq!(plan.modified_content.objects, 0);
+    assert_eq!(plan.estimated_size(), plan.loose_size());
+}
+
+#[test]
+fn should_retain_reachable_static_deltas() {
+    let test_repo = TestRepo::new();
+    let from = test_repo.test_commit("commit1");
+    let to = test_repo.test_commit("commit2");
+    let options = StaticDeltaGenerateOptions::new();
+    for from in [Some(from.as_str()), None] {
+        test_repo
+            .repo
+            .static_delta_generate_with_options(from, &to, &options, gio::Cancellable::NONE)
+            .expect("static delta generate");
+    }
+    let name = format!("{from}-{to}");
+    assert!(test_repo.repo.static_delta_index(&to).unwrap().is_none());
+    #[cfg(feature = "v2020_8")]
+    {
+        test_repo
+            .repo
+            .reindex_static_deltas(None, gio::Cancellable::NONE)
+            .unwrap();
+        let index = test_repo.repo.static_delta_index(&to).unwrap().unwrap();
+        assert_eq!(index.deltas.len(), 2);
+        assert!(index.deltas.contains_key(&name));
+        assert_eq!(test_repo.repo.static_delta_indexes().unwrap(), vec![index]);
+    }
+
+    let unreachable = test_repo
+        .repo
+        .unreachable_static_deltas(gio::Cancellable::NONE)
+        .unwrap();
+    assert!(unreachable.is_empty());
+
+    test_repo
+        .repo
+        .set_ref_immediate(None, "commit1", None, gio::Cancellable::NONE)
+        .unwrap();
+    let removed = test_repo
+        .repo
+        .retain_reachable_static_deltas(gio::Cancellable::NONE)
+        .unwrap();
+    assert_eq!(removed, vec![name]);
+    let names = test_repo
+        .repo
+        .list_static_delta_names(gio::Cancellable::NONE)
+        .unwrap();
+    assert_eq!(names, vec![to.as_str()]);
+    #[cfg(feature = "v2020_8")]
+    {
+        let index = test_repo.repo.static_delta_index(&to).unwrap().unwrap();
+        assert_eq!(index.froms().collect::<Vec<_>>(), vec![None]);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::util::*;
+use ostree::{LogEntry, LogOptions, PullLocalOptions};
+
+/// Commit a chain of commits to `os/stable` with the given timestamps, oldest first.
+fn commit_chain(repo: &ostree::Repo, times: &[u64]) -> Vec<String> {
+    let mtree = create_mtree(repo);
+    let mut ret: Vec<String> = Vec::new();
+    for time in times {
+        let parent = ret.last().map(String::as_str);
+        let checksum = commit(repo, &mtree, "os/stable", parent, Some(*time), None);
+        ret.push(checksum.into());
+    }
+    ret
+}
+
+fn checksums(log: ostree::CommitLog) -> Vec<String> {
+    log.map(|e| match e.unwrap() {
+        LogEntry::Commit(c) => c.checksum,
+        LogEntry::Truncated(c) => format!("truncated {c}"),
+    })
+    .collect()
+}
+
+#[test]
+fn should_walk_history() {
+    let test_repo = TestRepo::new();
+    let commits = commit_chain(&test_repo.repo, &[100, 200, 300]);
+    let log = |options| test_repo.repo.log("os/stable", options).unwrap();
+
+    let entries = log(LogOptions::new())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries.len(), 3);
+    let LogEntry::Commit(newest) = &entries[0] else {
+        panic!("expected a commit");
+    };
+    assert_eq!(newest.subject, "Test Commit");
+    assert_eq!(newest.timestamp, 300);
+    assert_eq!(newest.parent.as_deref(), Some(commits[1].as_str()));
+    assert!(!newest.is_partial());
+
+    assert_eq!(
+        checksums(log(LogOptions::new().max_count(2))),
+        vec![commits[2].clone(), commits[1].clone()]
+    );
+    assert_eq!(
+        checksums(log(LogOptions::new().since(200))),
+        vec![commits[2].clone(), commits[1].clone()]
+    );
+    assert_eq!(
+        checksums(log(LogOptions::new().until(250).max_count(1))),
+        vec![commits[1].clone()]
+    );
+    assert!(test_repo.repo.log("nosuchref", LogOptions::new()).is_err());
+}
+
+#[test]
+fn should_report_truncated_history() {
+    let src = TestRepo::new();
+    let commits = commit_chain(&src.repo, &[100, 200]);
+    let dest = TestRepo::new();
+    dest.repo
+        .pull_local(
+            &src.repo,
+            &[],
+            &PullLocalOptions::new().commit_metadata_only(true),
+            gio::Cancellable::NONE,
+        )
+        .unwrap();
+
+    let entries = dest
+        .repo
+        .log("os/stable", LogOptions::new())
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries.len(), 2);
+    let LogEntry::Commit(commit) = &entries[0] else {
+        panic!("expected a commit");
+    };
+    assert_eq!(commit.checksum, commits[1]);
+    assert!(commit.is_partial());
+    assert!(matches!(&entries[1], LogEntry::Truncated(c) if *c == commits[0]));
+}
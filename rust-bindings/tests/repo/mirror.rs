@@ -0,0 +1,204 @@
+use crate::util::*;
+use ostree::{MirrorOptions, StaticDeltaGenerateOptions};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+
+/// Serve the files under `root` over HTTP, returning the base URL.
+fn serve(root: &Path) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let root = root.to_path_buf();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let root = root.clone();
+            std::thread::spawn(move || handle(stream.unwrap(), &root));
+        }
+    });
+    url
+}
+
+fn handle(mut stream: TcpStream, root: &Path) {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+    }
+    let request = String::from_utf8_lossy(&request);
+    let path = request.split(' ').nth(1).unwrap_or("/");
+    let path = path.split('?').next().unwrap().trim_start_matches('/');
+    if let Some(path) = path.strip_prefix("moved/") {
+        let response = format!(
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: /{path}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+        let _ = stream.write_all(response.as_bytes());
+        return;
+    }
+    let file = root.join(path);
+    let response = match std::fs::read(&file) {
+        Ok(data) if !path.contains("..") => {
+            let mut r = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                data.len()
+            )
+            .into_bytes();
+            r.extend_from_slice(&data);
+            r
+        }
+        _ => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+    };
+    let _ = stream.write_all(&response);
+}
+
+fn upstream() -> (TestRepo, String) {
+    let src = TestRepo::new();
+    src.test_commit("other/app");
+    let commit = src.test_commit("os/stable");
+    src.repo
+        .static_delta_generate_with_options(
+            None,
+            &commit,
+            &StaticDeltaGenerateOptions::new(),
+            gio::Cancellable::NONE,
+        )
+        .unwrap();
+    src.repo
+        .regenerate_summary(None, gio::Cancellable::NONE)
+        .unwrap();
+    (src, commit.to_string())
+}
+
+fn add_remote(repo: &ostree::Repo, url: &str) {
+    add_remote_with_contenturl(repo, url, None);
+}
+
+fn add_remote_with_contenturl(repo: &ostree::Repo, url: &str, contenturl: Option<&str>) {
+    let options = glib::VariantDict::new(None);
+    options.insert("gpg-verify", false);
+    if let Some(contenturl) = contenturl {
+        options.insert("contenturl", contenturl);
+    }
+    repo.remote_add(
+        "upstream",
+        Some(url),
+        Some(&options.end()),
+        gio::Cancellable::NONE,
+    )
+    .unwrap();
+}
+
+#[test]
+fn should_mirror_over_http() {
+    let (src, commit) = upstream();
+    let url = serve(src.dir.path());
+    let dest = TestRepo::new();
+    add_remote(&dest.repo, &url);
+
+    let report = dest
+        .repo
+        .mirror("upstream", &MirrorOptions::new(), gio::Cancellable::NONE)
+        .unwrap();
+    assert_eq!(report.changed_refs.len(), 2);
+    let stable = &report.changed_refs["os/stable"];
+    assert_eq!(stable.previous, None);
+    assert_eq!(stable.commit, commit);
+    assert_eq!(
+        std::fs::read(dest.dir.path().join("summary")).unwrap(),
+        std::fs::read(src.dir.path().join("summary")).unwrap()
+    );
+    #[cfg(feature = "mirror_static_deltas")]
+    {
+        assert_eq!(report.static_deltas, vec![commit.clone()]);
+        let superblock = dest.repo.static_delta_info(&commit).unwrap();
+        assert_eq!(superblock.to, commit);
+        #[cfg(feature = "static_delta_parts")]
+        for i in 0..superblock.parts.len() {
+            dest.repo.static_delta_part(&superblock, i).unwrap();
+        }
+    }
+
+    // Nothing changed upstream.
+    let report = dest
+        .repo
+        .mirror("upstream", &MirrorOptions::new(), gio::Cancellable::NONE)
+        .unwrap();
+    assert!(report.changed_refs.is_empty());
+    #[cfg(feature = "mirror_static_deltas")]
+    assert!(report.static_deltas.is_empty());
+}
+
+#[test]
+fn should_mirror_refs_with_prefix() {
+    let (src, commit) = upstream();
+    let url = format!("file://{}", src.dir.path().display());
+    let dest = TestRepo::new();
+
+    let options = MirrorOptions::new().ref_prefix("os/");
+    #[cfg(feature = "mirror_static_deltas")]
+    let options = options.static_deltas(false);
+    let report = dest
+        .repo
+        .mirror(&url, &options, gio::Cancellable::NONE)
+        .unwrap();
+    assert_eq!(
+        report.changed_refs.keys().collect::<Vec<_>>(),
+        vec!["os/stable"]
+    );
+    assert_eq!(dest.repo.require_rev("os/stable").unwrap(), commit);
+    assert!(dest.repo.resolve_rev("other/app", true).unwrap().is_none());
+    assert!(dest
+        .repo
+        .list_static_delta_names(gio::Cancellable::NONE)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+#[cfg(feature = "mirror_static_deltas")]
+fn should_follow_redirects_to_contenturl() {
+    let (src, commit) = upstream();
+    let url = serve(src.dir.path());
+    let dest = TestRepo::new();
+    add_remote_with_contenturl(&dest.repo, &url, Some(&format!("{url}/moved")));
+
+    let report = dest
+        .repo
+        .mirror("upstream", &MirrorOptions::new(), gio::Cancellable::NONE)
+        .unwrap();
+    assert_eq!(report.static_deltas, vec![commit.clone()]);
+    let superblock = dest.repo.static_delta_info(&commit).unwrap();
+    assert_eq!(superblock.to, commit);
+    #[cfg(feature = "static_delta_parts")]
+    for i in 0..superblock.parts.len() {
+        dest.repo.static_delta_part(&superblock, i).unwrap();
+    }
+}
+
+#[test]
+#[cfg(feature = "mirror_static_deltas")]
+fn should_reject_unsupported_remote_settings() {
+    let (src, commit) = upstream();
+    let url = format!("file://{}", src.dir.path().display());
+    let dest = TestRepo::new();
+    add_remote(&dest.repo, &url);
+    std::fs::write(dest.dir.path().join("upstream.cookies.txt"), "").unwrap();
+
+    let err = dest
+        .repo
+        .mirror("upstream", &MirrorOptions::new(), gio::Cancellable::NONE)
+        .unwrap_err();
+    assert!(matches!(err, ostree::MirrorError::Unsupported(_)), "{err}");
+    assert!(dest.repo.resolve_rev("os/stable", true).unwrap().is_none());
+
+    // The settings only matter for fetching static deltas.
+    let options = MirrorOptions::new().static_deltas(false);
+    let report = dest
+        .repo
+        .mirror("upstream", &options, gio::Cancellable::NONE)
+        .unwrap();
+    assert_eq!(report.changed_refs["os/stable"].commit, commit);
+    assert!(report.static_deltas.is_empty());
+}
@@ -8,9 +8,21 @@ use ostree::{ObjectName, ObjectType};
 #[cfg(feature = "v2017_10")]
 use std::os::fd::AsFd;
 
+mod bundle;
 #[cfg(any(feature = "v2016_8", feature = "dox"))]
 mod checkout_at;
+mod commit_builder;
+mod commit_modifier;
+mod composefs;
+mod fsverity;
 mod generate_static;
+#[cfg(feature = "v2015_7")]
+mod history;
+#[cfg(any(feature = "v2016_6", feature = "dox"))]
+mod mirror;
+mod pull_local;
+mod reproducible;
+mod tree_diff;
 
 #[test]
 fn should_commit_content_to_repo_and_list_refs_again() {
@@ -19,7 +31,7 @@ fn should_commit_content_to_repo_and_list_refs_again() {
     assert!(test_repo.repo.require_rev("nosuchrev").is_err());
 
     let mtree = create_mtree(&test_repo.repo);
-    let checksum = commit(&test_repo.repo, &mtree, "test");
+    let checksum = commit(&test_repo.repo, &mtree, "test", None, None, None);
 
     assert_eq!(test_repo.repo.require_rev("test").unwrap(), checksum);
 
@@ -73,7 +85,7 @@ fn cap_std_commit() {
     assert!(test_repo.repo.require_rev("nosuchrev").is_err());
 
     let mtree = create_mtree(&test_repo.repo);
-    let checksum = commit(&test_repo.repo, &mtree, "test");
+    let checksum = commit(&test_repo.repo, &mtree, "test", None, None, None);
 
     assert_eq!(test_repo.repo.require_rev("test").unwrap(), checksum);
 
@@ -170,7 +182,7 @@ fn should_checkout_tree() {
 fn should_write_content_to_repo() {
     let src = TestRepo::new();
     let mtree = create_mtree(&src.repo);
-    let checksum = commit(&src.repo, &mtree, "test");
+    let checksum = commit(&src.repo, &mtree, "test", None, None, None);
 
     let dest = TestRepo::new();
     let objects = src
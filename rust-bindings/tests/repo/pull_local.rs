@@ -0,0 +1,80 @@
+use crate::util::*;
+use glib::GString;
+use ostree::{ObjectType, PullLocalOptions};
+
+fn commit_with_parent(repo: &ostree::Repo, parent: Option<&str>) -> GString {
+    let mtree = create_mtree(repo);
+    commit(repo, &mtree, "os/stable", parent, None, None)
+}
+
+#[test]
+fn should_pull_local_untrusted() {
+    let src = TestRepo::new();
+    let first = commit_with_parent(&src.repo, None);
+    let second = commit_with_parent(&src.repo, Some(&first));
+    let dest = TestRepo::new();
+
+    let pulled = dest
+        .repo
+        .pull_local(
+            &src.repo,
+            &[],
+            &PullLocalOptions::new().untrusted(true),
+            gio::Cancellable::NONE,
+        )
+        .unwrap();
+    assert_eq!(pulled.len(), 1);
+    assert_eq!(pulled["os/stable"], second);
+    dest.repo
+        .traverse_commit(&second, 0, gio::Cancellable::NONE)
+        .unwrap();
+    assert!(!dest
+        .repo
+        .has_object(ObjectType::Commit, &first, gio::Cancellable::NONE)
+        .unwrap());
+}
+
+#[test]
+fn should_pull_local_commit_metadata_with_depth() {
+    let src = TestRepo::new();
+    let first = commit_with_parent(&src.repo, None);
+    let second = commit_with_parent(&src.repo, Some(&first));
+    let dest = TestRepo::new();
+
+    let options = PullLocalOptions::new()
+        .commit_metadata_only(true)
+        .remote("origin")
+        .depth(-1);
+    let pulled = dest
+        .repo
+        .pull_local(&src.repo, &["os/stable"], &options, gio::Cancellable::NONE)
+        .unwrap();
+    assert_eq!(pulled["origin:os/stable"], second);
+    for commit in [&first, &second] {
+        assert!(dest
+            .repo
+            .has_object(ObjectType::Commit, commit, gio::Cancellable::NONE)
+            .unwrap());
+    }
+    assert!(dest
+        .repo
+        .traverse_commit(&second, 0, gio::Cancellable::NONE)
+        .is_err());
+}
+
+#[test]
+fn should_require_remote_to_verify_local_pull() {
+    let src = TestRepo::new();
+    src.test_commit("os/stable");
+    let dest = TestRepo::new();
+    let err = dest
+        .repo
+        .pull_local(
+            &src.repo,
+            &[],
+            &PullLocalOptions::new().gpg_verify(true),
+            gio::Cancellable::NONE,
+        )
+        .unwrap_err();
+    assert!(err.matches(gio::IOErrorEnum::InvalidArgument));
+}
@@ -0,0 +1,64 @@
+use crate::util::*;
+use ostree::{CommitBuilder, CommitSource, RebuildDifference, ReproducibleOptions};
+
+fn build(
+    test_repo: &TestRepo,
+    src: &std::path::Path,
+    expected: Option<&str>,
+) -> Result<String, glib::Error> {
+    let dir = gio::File::for_path(src);
+    let mut builder = CommitBuilder::new(CommitSource::Directory(&dir))
+        .subject("reproducible")
+        .reproducible(ReproducibleOptions::new(1_700_000_000))
+        .target_ref("test");
+    if let Some(expected) = expected {
+        builder = builder.expect_checksum(expected);
+    }
+    let (checksum, _) = builder.commit(&test_repo.repo, gio::Cancellable::NONE)?;
+    Ok(checksum.to_string())
+}
+
+#[test]
+fn should_rebuild_identical_commit() {
+    let test_repo = TestRepo::new();
+    let src = tempfile::tempdir().unwrap();
+    std::fs::create_dir(src.path().join("bin")).unwrap();
+    std::fs::write(src.path().join("bin/tool"), "tool").unwrap();
+
+    let first = build(&test_repo, src.path(), None).unwrap();
+    // Touching the files must not change the result.
+    std::fs::write(src.path().join("bin/tool"), "tool").unwrap();
+    let second = build(&test_repo, src.path(), Some(&first)).unwrap();
+    assert_eq!(first, second);
+}
+
+#[test]
+fn should_report_rebuild_mismatch() {
+    let test_repo = TestRepo::new();
+    let src = tempfile::tempdir().unwrap();
+    std::fs::write(src.path().join("a"), "a").unwrap();
+    let first = build(&test_repo, src.path(), None).unwrap();
+
+    std::fs::write(src.path().join("a"), "changed").unwrap();
+    std::fs::write(src.path().join("b"), "b").unwrap();
+    let err = build(&test_repo, src.path(), Some(&first)).unwrap_err();
+    assert!(err.message().contains("M    /a"), "{}", err.message());
+    assert!(err.message().contains("A    /b"), "{}", err.message());
+    // The ref was not moved.
+    let rev = test_repo.repo.require_rev("test").unwrap();
+    assert_eq!(rev.as_str(), first);
+
+    let second = build(&test_repo, src.path(), None).unwrap();
+    let report = test_repo
+        .repo
+        .verify_rebuild(&second, &first)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        report.differences,
+        vec![
+            RebuildDifference::Modified("/a".into()),
+            RebuildDifference::Added("/b".into()),
+        ]
+    );
+}
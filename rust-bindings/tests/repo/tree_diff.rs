@@ -0,0 +1,90 @@
+use crate::util::*;
+use ostree::{
+    CommitBuilder, CommitSource, TreeChange, TreeChangeKind, TreeDiffOptions, TreeEntryType,
+};
+use std::os::unix::fs::PermissionsExt;
+
+fn commit_dir(test_repo: &TestRepo, src: &std::path::Path, subject: &str) -> String {
+    let dir = gio::File::for_path(src);
+    let (checksum, _) = CommitBuilder::new(CommitSource::Directory(&dir))
+        .subject(subject)
+        .commit(&test_repo.repo, gio::Cancellable::NONE)
+        .unwrap();
+    checksum.to_string()
+}
+
+fn chmod(path: &std::path::Path, mode: u32) {
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+}
+
+#[test]
+fn should_diff_commits() {
+    let test_repo = TestRepo::new();
+    let src = tempfile::tempdir().unwrap();
+    let root = src.path();
+    std::fs::write(root.join("a"), "a").unwrap();
+    std::fs::write(root.join("b"), "b").unwrap();
+    chmod(&root.join("b"), 0o644);
+    std::fs::create_dir_all(root.join("same/deep")).unwrap();
+    std::fs::write(root.join("same/deep/file"), "unchanged").unwrap();
+    std::fs::create_dir(root.join("d")).unwrap();
+    std::fs::write(root.join("d/x"), "x").unwrap();
+    let old = commit_dir(&test_repo, root, "old");
+
+    std::fs::write(root.join("a"), "aaaa").unwrap();
+    chmod(&root.join("b"), 0o755);
+    std::fs::remove_file(root.join("d/x")).unwrap();
+    std::os::unix::fs::symlink("../a", root.join("d/y")).unwrap();
+    std::fs::create_dir(root.join("e")).unwrap();
+    let new = commit_dir(&test_repo, root, "new");
+
+    let changes = test_repo
+        .repo
+        .diff_commits_with_options(&old, &new, &TreeDiffOptions::new().content_sizes(true))
+        .unwrap();
+    let paths = changes.iter().map(|c| c.path.as_str()).collect::<Vec<_>>();
+    assert_eq!(paths, ["/a", "/b", "/d/x", "/d/y", "/e"]);
+
+    let TreeChangeKind::Modified(a) = &changes[0].kind else {
+        panic!("unexpected change {:?}", changes[0]);
+    };
+    assert!(a.content);
+    assert_eq!(changes[0].size_delta, Some(3));
+
+    let TreeChangeKind::Modified(b) = &changes[1].kind else {
+        panic!("unexpected change {:?}", changes[1]);
+    };
+    assert!(b.is_metadata_only());
+    assert_eq!(b.mode, Some((0o100644, 0o100755)));
+    assert_eq!(changes[1].size_delta, Some(0));
+
+    assert_eq!(
+        changes[2],
+        TreeChange {
+            path: "/d/x".into(),
+            kind: TreeChangeKind::Removed(TreeEntryType::File),
+            size_delta: Some(-1),
+        }
+    );
+    assert_eq!(
+        changes[3].kind,
+        TreeChangeKind::Added(TreeEntryType::Symlink)
+    );
+    assert_eq!(
+        changes[4],
+        TreeChange {
+            path: "/e".into(),
+            kind: TreeChangeKind::Added(TreeEntryType::Directory),
+            size_delta: None,
+        }
+    );
+
+    assert!(test_repo.repo.diff_commits(&new, &new).unwrap().is_empty());
+    let reverse = test_repo.repo.diff_commits(&new, &old).unwrap();
+    assert_eq!(reverse.len(), 5);
+    assert_eq!(
+        reverse[4].kind,
+        TreeChangeKind::Removed(TreeEntryType::Directory)
+    );
+    assert_eq!(reverse[0].size_delta, None);
+}
@@ -0,0 +1,67 @@
+use super::{fabricate_deployment, CHECKSUM};
+use ostree::SysrootBuilder;
+
+#[test]
+fn should_configure_boot_counting_on_new_sysroot() {
+    let td = tempfile::tempdir().unwrap();
+    let sysroot = SysrootBuilder::new()
+        .path(Some(td.path().to_path_buf()))
+        .create(None)
+        .unwrap();
+    assert_eq!(sysroot.boot_counting_tries().unwrap(), None);
+
+    sysroot.set_boot_counting_tries(Some(3)).unwrap();
+    assert_eq!(sysroot.boot_counting_tries().unwrap(), Some(3));
+    assert!(sysroot.set_boot_counting_tries(Some(6)).is_err());
+    assert_eq!(sysroot.boot_counting_tries().unwrap(), Some(3));
+
+    let repo = sysroot.repo();
+    let config = repo.copy_config();
+    config.set_string("sysroot", "boot-counting-tries", "three");
+    repo.write_config_and_reload(&config).unwrap();
+    assert!(sysroot.boot_counting_tries().is_err());
+
+    sysroot.set_boot_counting_tries(None).unwrap();
+    assert_eq!(sysroot.boot_counting_tries().unwrap(), None);
+    assert!(sysroot.failed_deployments().unwrap().is_empty());
+}
+
+#[test]
+fn should_assess_boot_counted_entries() {
+    let td = tempfile::tempdir().unwrap();
+    SysrootBuilder::new()
+        .path(Some(td.path().to_path_buf()))
+        .create(None)
+        .unwrap();
+    fabricate_deployment(td.path(), 0, "ostree-1-os+0-3.conf");
+    fabricate_deployment(td.path(), 1, "ostree-2-os+2-1.conf");
+    let sysroot = SysrootBuilder::new()
+        .path(Some(td.path().to_path_buf()))
+        .load(None)
+        .unwrap();
+    let deployments = sysroot.deployments();
+    assert_eq!(deployments.len(), 2);
+    let serial = |serial| {
+        deployments
+            .iter()
+            .find(|d| d.deployserial() == serial)
+            .unwrap()
+    };
+
+    let failed = sysroot.failed_deployments().unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].deployserial(), 0);
+    assert_eq!(failed[0].csum(), CHECKSUM);
+
+    assert!(sysroot.mark_boot_good(serial(1)).unwrap());
+    let entries = td.path().join("boot/loader/entries");
+    assert!(entries.join("ostree-2-os.conf").exists());
+    assert!(!entries.join("ostree-2-os+2-1.conf").exists());
+    assert_eq!(sysroot.deployments().len(), 2);
+    assert!(!sysroot.mark_boot_good(serial(1)).unwrap());
+
+    // Marking a failed deployment good drops its counter too.
+    assert!(sysroot.mark_boot_good(serial(0)).unwrap());
+    assert!(entries.join("ostree-1-os.conf").exists());
+    assert!(sysroot.failed_deployments().unwrap().is_empty());
+}
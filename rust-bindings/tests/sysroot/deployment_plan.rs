@@ -0,0 +1,77 @@
+use super::{fabricate_deployment, BOOTCSUM};
+use ostree::{DeploymentPlan, SysrootBuilder, SysrootSimpleWriteDeploymentFlags};
+
+#[test]
+fn should_preview_and_apply_plan() {
+    let td = tempfile::tempdir().unwrap();
+    SysrootBuilder::new()
+        .path(Some(td.path().to_path_buf()))
+        .create(None)
+        .unwrap();
+    fabricate_deployment(td.path(), 0, "ostree-1-os.conf");
+    fabricate_deployment(td.path(), 1, "ostree-2-os.conf");
+    let sysroot = SysrootBuilder::new()
+        .path(Some(td.path().to_path_buf()))
+        .load(None)
+        .unwrap();
+    let deployments = sysroot.deployments();
+    let serials = |deployments: &[ostree::Deployment]| {
+        deployments
+            .iter()
+            .map(|d| d.deployserial())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(serials(&deployments), [1, 0]);
+
+    let plan = DeploymentPlan::new(&sysroot)
+        .flags(SysrootSimpleWriteDeploymentFlags::NO_CLEAN)
+        .set_default(&deployments[1])
+        .pin(&deployments[1], true);
+    let preview = plan.preview().unwrap();
+    assert!(preview.removed.is_empty());
+    let planned = preview
+        .deployments
+        .iter()
+        .map(|d| (d.deployserial, d.index, d.pinned, d.new))
+        .collect::<Vec<_>>();
+    assert_eq!(planned, [(0, 0, true, false), (1, 1, false, false)]);
+    let entries = preview
+        .deployments
+        .iter()
+        .map(|d| {
+            (
+                d.bootcsum.as_str(),
+                d.title.as_deref(),
+                d.version.as_deref(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        entries,
+        [
+            (BOOTCSUM, Some("os"), Some("1")),
+            (BOOTCSUM, Some("os"), Some("2"))
+        ]
+    );
+    // Previewing changes nothing.
+    assert!(!deployments[1].is_pinned());
+
+    plan.apply(gio::Cancellable::NONE).unwrap();
+    let sysroot = SysrootBuilder::new()
+        .path(Some(td.path().to_path_buf()))
+        .load(None)
+        .unwrap();
+    let deployments = sysroot.deployments();
+    assert_eq!(serials(&deployments), [0, 1]);
+    assert!(deployments[0].is_pinned());
+    assert!(!deployments[1].is_pinned());
+
+    // A stale plan is rejected.
+    let stale = DeploymentPlan::new(&sysroot).remove(&deployments[1]);
+    DeploymentPlan::new(&sysroot)
+        .flags(SysrootSimpleWriteDeploymentFlags::NO_CLEAN)
+        .set_default(&deployments[1])
+        .apply(gio::Cancellable::NONE)
+        .unwrap();
+    assert!(stale.apply(gio::Cancellable::NONE).is_err());
+}
@@ -0,0 +1,58 @@
+#[cfg(any(feature = "v2020_7", feature = "dox"))]
+mod boot_counting;
+#[cfg(any(feature = "v2018_3", feature = "dox"))]
+mod deployment_plan;
+#[cfg(any(feature = "v2018_5", feature = "dox"))]
+mod staged;
+
+#[cfg(any(feature = "v2018_3", feature = "dox"))]
+use std::path::Path;
+
+#[cfg(any(feature = "v2018_3", feature = "dox"))]
+const CHECKSUM: &str = "5280a884f930cae329e2e39d52f2c8e910c2ef4733216b67679db32a2b56c4db";
+#[cfg(any(feature = "v2018_3", feature = "dox"))]
+const BOOTCSUM: &str = "b00t";
+
+/// Write a deployment of stateroot `os` with serial `serial`, with a kernel and an origin,
+/// and its boot entry `entry`, as libostree lays them out in bootversion 0.
+#[cfg(any(feature = "v2018_3", feature = "dox"))]
+fn fabricate_deployment(root: &Path, serial: u32, entry: &str) {
+    use std::os::unix::fs::symlink;
+
+    let deploy = format!("{CHECKSUM}.{serial}");
+    let deploy_dir = root.join("ostree/deploy/os/deploy");
+    let modules = deploy_dir.join(&deploy).join("usr/lib/modules/5.0");
+    std::fs::create_dir_all(&modules).unwrap();
+    std::fs::write(modules.join("vmlinuz"), "kernel").unwrap();
+    std::fs::write(modules.join("initramfs.img"), "initramfs").unwrap();
+    std::fs::write(
+        deploy_dir.join(format!("{deploy}.origin")),
+        "[origin]\nrefspec=os:test\n",
+    )
+    .unwrap();
+    let links = root.join("ostree/boot.0.1/os").join(BOOTCSUM);
+    std::fs::create_dir_all(&links).unwrap();
+    symlink(
+        format!("../../../deploy/os/deploy/{deploy}"),
+        links.join(serial.to_string()),
+    )
+    .unwrap();
+    if !root.join("ostree/boot.0").exists() {
+        symlink("boot.0.1", root.join("ostree/boot.0")).unwrap();
+    }
+
+    let entries = root.join("boot/loader.0/entries");
+    std::fs::create_dir_all(&entries).unwrap();
+    if !root.join("boot/loader").exists() {
+        symlink("loader.0", root.join("boot/loader")).unwrap();
+    }
+    std::fs::write(
+        entries.join(entry),
+        format!(
+            "title os\nversion {}\nlinux /ostree/os-{BOOTCSUM}/vmlinuz\n\
+             options root=/dev/vda ostree=/ostree/boot.0/os/{BOOTCSUM}/{serial}\n",
+            serial + 1
+        ),
+    )
+    .unwrap();
+}
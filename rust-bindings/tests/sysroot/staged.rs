@@ -0,0 +1,16 @@
+use ostree::SysrootBuilder;
+
+#[test]
+fn should_not_discard_without_staged_deployment() {
+    let td = tempfile::tempdir().unwrap();
+    let sysroot = SysrootBuilder::new()
+        .path(Some(td.path().to_path_buf()))
+        .create(None)
+        .unwrap();
+    assert!(sysroot.staged_deployment().is_none());
+    assert!(!sysroot.discard_staged(gio::Cancellable::NONE).unwrap());
+    assert!(sysroot.deployments().is_empty());
+    // The lock was released.
+    sysroot.lock().unwrap();
+    sysroot.unlock();
+}
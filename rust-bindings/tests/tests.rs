@@ -3,4 +3,5 @@ mod functions;
 mod repo;
 #[cfg(any(feature = "v2020_2", feature = "dox"))]
 mod sign;
+mod sysroot;
 mod util;
@@ -27,7 +27,7 @@ impl TestRepo {
 
     pub fn test_commit(&self, ref_: &str) -> GString {
         let mtree = create_mtree(&self.repo);
-        commit(&self.repo, &mtree, ref_)
+        commit(&self.repo, &mtree, ref_, None, None, None)
     }
 }
 
@@ -64,7 +64,14 @@ pub fn create_mtree(repo: &ostree::Repo) -> ostree::MutableTree {
     mtree
 }
 
-pub fn commit(repo: &ostree::Repo, mtree: &ostree::MutableTree, ref_: &str) -> GString {
+pub fn commit(
+    repo: &ostree::Repo,
+    mtree: &ostree::MutableTree,
+    ref_: &str,
+    parent: Option<&str>,
+    time: Option<u64>,
+    metadata: Option<&glib::Variant>,
+) -> GString {
     let txn = repo
         .auto_transaction(gio::Cancellable::NONE)
         .expect("prepare transaction");
@@ -74,16 +81,26 @@ pub fn commit(repo: &ostree::Repo, mtree: &ostree::MutableTree, ref_: &str) -> G
         .expect("write mtree")
         .downcast::<ostree::RepoFile>()
         .unwrap();
-    let checksum = repo
-        .write_commit(
-            None,
+    let checksum = match time {
+        Some(time) => repo.write_commit_with_time(
+            parent,
             "Test Commit".into(),
             None,
+            metadata,
+            &repo_file,
+            time,
+            gio::Cancellable::NONE,
+        ),
+        None => repo.write_commit(
+            parent,
+            "Test Commit".into(),
             None,
+            metadata,
             &repo_file,
             gio::Cancellable::NONE,
-        )
-        .expect("write commit");
+        ),
+    }
+    .expect("write commit");
     repo.transaction_set_ref(None, ref_, checksum.as_str().into());
     txn.commit(gio::Cancellable::NONE)
         .expect("commit transaction");